@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (&str, u16)| {
+    let (hostname, space_left) = input;
+    mud_comms::fuzzing::descriptor_strings(hostname, space_left.into());
+});
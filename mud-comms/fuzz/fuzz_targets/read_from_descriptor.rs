@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mud_comms::fuzzing::OverlongLines;
+
+fuzz_target!(|input: (Vec<Vec<u8>>, Vec<u16>, u16, u8)| {
+    let (chunks, buffer_sizes, max_line_length, overlong_lines) = input;
+    let buffer_sizes: Vec<usize> = buffer_sizes.into_iter().map(usize::from).collect();
+    let overlong_lines = match overlong_lines % 3 {
+        0 => OverlongLines::Truncate,
+        1 => OverlongLines::Drop,
+        _ => OverlongLines::Disconnect,
+    };
+    mud_comms::fuzzing::read_from_descriptor(
+        &chunks,
+        &buffer_sizes,
+        max_line_length.into(),
+        overlong_lines,
+    );
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (Vec<String>, Vec<u16>)| {
+    let (messages, buffer_sizes) = input;
+    let buffer_sizes: Vec<usize> = buffer_sizes.into_iter().map(usize::from).collect();
+    mud_comms::fuzzing::slack_input(&messages, &buffer_sizes);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u16, &[u8])| {
+    let (split_at, input) = input;
+    mud_comms::fuzzing::telnet_input(input, split_at.into());
+});
@@ -0,0 +1,351 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::mem::size_of;
+use std::net::Shutdown;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use libc::c_void;
+use libc::getsockopt;
+use libc::socklen_t;
+use libc::ucred;
+use libc::SOL_SOCKET;
+use libc::SO_PEERCRED;
+use log::*;
+
+use crate::descriptor::AdminCommand;
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+
+/// How long a client gets to say what it wants before it's dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request line a client may send
+const MAX_REQUEST_LENGTH: usize = 4096;
+
+/// How long a command's reply may take to write before the client is given up on
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often `block_until_descriptor` checks for clients
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Takes admin clients (ie `mudctl`) on a Unix domain socket that only the game's own user (or
+/// root) can connect to. Each starts with a line saying what it wants: `attach` to log in like
+/// any other connection, or `command <command>` to have the game run one of its admin commands
+/// (eg `command who`) and answer before hanging up.
+pub struct AdminSocketDescriptorManager {
+    path: PathBuf,
+    listener: RefCell<Option<UnixListener>>,
+    /// Clients that haven't finished their request line yet
+    connecting: RefCell<Vec<Connecting>>,
+    attached: RefCell<VecDeque<AdminDescriptor>>,
+    commands: RefCell<VecDeque<AdminCommand>>,
+}
+
+struct Connecting {
+    stream: UnixStream,
+    request: Vec<u8>,
+    since: Instant,
+}
+
+impl AdminSocketDescriptorManager {
+    /// Listens on a socket at `path`, replacing any left behind by an earlier process (eg one
+    /// that crashed, or exec'd this one in a copyover)
+    pub fn new(path: PathBuf) -> Result<Self, std::io::Error> {
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{:?} exists and isn't a socket", path),
+                ))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(&path)?;
+        // Connecting needs write permission, so this leaves the game's user (and root) alone
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        info!("Listening for admins on {:?}", path);
+        Ok(AdminSocketDescriptorManager {
+            path,
+            listener: RefCell::new(Some(listener)),
+            connecting: RefCell::new(Vec::new()),
+            attached: RefCell::new(VecDeque::new()),
+            commands: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    /// Accepts any new clients and reads the requests of those that haven't sent one yet
+    fn poll(&self) {
+        if let Some(listener) = self.listener.borrow().as_ref() {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => self.admit(stream),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Cannot accept admin connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut connecting = self.connecting.borrow_mut();
+        let mut buf = [0; 512];
+        connecting.retain_mut(|client| loop {
+            if let Some(end) = client.request.iter().position(|&b| b == b'\n') {
+                let line = String::from_utf8_lossy(&client.request[..end])
+                    .trim()
+                    .to_owned();
+                // Whatever came after it is the start of an attached session
+                let early_input = client.request.split_off(end + 1);
+                let stream = match client.stream.try_clone() {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Cannot take admin request: {}", e);
+                        return false;
+                    }
+                };
+                self.take_request(&line, stream, early_input);
+                return false;
+            }
+            if client.request.len() > MAX_REQUEST_LENGTH || client.since.elapsed() > REQUEST_TIMEOUT
+            {
+                warn!("Dropping admin client that never sent a request");
+                return false;
+            }
+            match client.stream.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(read) => client.request.extend_from_slice(&buf[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(e) => {
+                    warn!("Cannot read admin request: {}", e);
+                    return false;
+                }
+            }
+        });
+    }
+
+    fn admit(&self, stream: UnixStream) {
+        // The socket's permissions should already keep others out, but it may be somewhere they
+        // can reach before they're set
+        match peer_uid(&stream) {
+            // SAFETY: geteuid can't fail
+            Ok(uid) if uid == 0 || uid == unsafe { libc::geteuid() } => {}
+            Ok(uid) => {
+                warn!("Refusing admin connection from uid {}", uid);
+                return;
+            }
+            Err(e) => {
+                error!("Cannot check admin connection's credentials: {}", e);
+                return;
+            }
+        }
+        if let Err(e) = stream.set_nonblocking(true) {
+            error!("Cannot set admin connection nonblocking: {}", e);
+            return;
+        }
+        self.connecting.borrow_mut().push(Connecting {
+            stream,
+            request: Vec::new(),
+            since: Instant::now(),
+        });
+    }
+
+    fn take_request(&self, line: &str, mut stream: UnixStream, early_input: Vec<u8>) {
+        let (request, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match request {
+            "attach" => {
+                info!("Admin attached");
+                self.attached.borrow_mut().push_back(AdminDescriptor {
+                    stream,
+                    early_input,
+                });
+            }
+            "command" if !argument.trim().is_empty() => {
+                // The game answers in one go, so it may as well wait (briefly) for room
+                if let Err(e) = stream
+                    .set_nonblocking(false)
+                    .and_then(|_| stream.set_write_timeout(Some(REPLY_TIMEOUT)))
+                {
+                    error!("Cannot set up admin connection for a reply: {}", e);
+                    return;
+                }
+                info!("Admin command: {}", argument.trim());
+                self.commands.borrow_mut().push_back(AdminCommand::new(
+                    argument.trim().to_owned(),
+                    Box::new(stream),
+                ));
+            }
+            _ => {
+                let _ = stream.write_all(b"Expected 'attach' or 'command <command>'\n");
+            }
+        }
+    }
+}
+
+fn peer_uid(stream: &UnixStream) -> Result<u32, std::io::Error> {
+    let mut credentials = ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<ucred>() as socklen_t;
+    // SAFETY: `credentials` outlives the call and is the size given
+    let result = unsafe {
+        getsockopt(
+            stream.as_raw_fd(),
+            SOL_SOCKET,
+            SO_PEERCRED,
+            &mut credentials as *mut ucred as *mut c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(credentials.uid)
+}
+
+impl DescriptorManager for AdminSocketDescriptorManager {
+    fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+        // A command wakes the game as well, so it can be answered even when nobody's playing
+        loop {
+            self.poll();
+            if !self.attached.borrow().is_empty() || !self.commands.borrow().is_empty() {
+                return Ok(());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn new_descriptor(
+        &self,
+    ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        self.poll();
+        match self.attached.borrow_mut().pop_front() {
+            Some(descriptor) => Ok(Box::new(descriptor)),
+            None => Err(Box::new(std::io::Error::from(ErrorKind::WouldBlock))),
+        }
+    }
+
+    fn next_admin_command(&self) -> Option<AdminCommand> {
+        self.poll();
+        self.commands.borrow_mut().pop_front()
+    }
+
+    fn shutdown(&mut self, _deadline: Instant) {
+        if self.listener.get_mut().take().is_some() {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                warn!("Cannot remove admin socket {:?}: {}", self.path, e);
+            }
+        }
+        self.connecting.get_mut().clear();
+        self.commands.get_mut().clear();
+    }
+}
+
+impl Drop for AdminSocketDescriptorManager {
+    fn drop(&mut self) {
+        self.shutdown(Instant::now());
+    }
+}
+
+/// An admin client logging in like a player, from the same machine
+pub struct AdminDescriptor {
+    stream: UnixStream,
+    /// Input sent along with the request, to be read before any more
+    early_input: Vec<u8>,
+}
+
+impl Descriptor for AdminDescriptor {
+    fn get_hostname(&self) -> &str {
+        "localhost"
+    }
+
+    fn get_type(&self) -> &'static str {
+        "ADMIN"
+    }
+
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        self.stream.shutdown(Shutdown::Write)?;
+        Ok(true)
+    }
+}
+
+impl Read for AdminDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.early_input.is_empty() {
+            return self.stream.read(buf);
+        }
+        let len = buf.len().min(self.early_input.len());
+        buf[..len].copy_from_slice(&self.early_input[..len]);
+        self.early_input.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for AdminDescriptor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_admins_attach_and_send_commands() {
+        let dir = TempDir::new("admin_socket").expect("temporary directory");
+        let path = dir.path().join("admin.sock");
+        let mut manager = AdminSocketDescriptorManager::new(path.clone()).expect("manager");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+
+        let mut commander = UnixStream::connect(&path).expect("connected");
+        commander.write_all(b"command wall ").unwrap();
+        let mut attacher = UnixStream::connect(&path).expect("connected");
+        attacher.write_all(b"attach\nFoo\r\n").unwrap();
+        assert!(manager.next_admin_command().is_none());
+        commander.write_all(b"Back soon\n").unwrap();
+
+        manager.block_until_descriptor().expect("something to do");
+        let command = manager.next_admin_command().expect("a command");
+        assert_eq!("wall Back soon", command.command);
+        command.reply("Sent.\n").unwrap();
+        let mut reply = String::new();
+        commander.read_to_string(&mut reply).unwrap();
+        assert_eq!("Sent.\n", reply);
+
+        let mut descriptor = manager.new_descriptor().expect("attached");
+        assert_eq!("ADMIN", descriptor.get_type());
+        let mut input = [0; 16];
+        assert_eq!(5, descriptor.read(&mut input).unwrap());
+        assert_eq!(b"Foo\r\n", &input[..5]);
+        descriptor.write_all(b"By what name").unwrap();
+        let mut buf = [0; 12];
+        attacher.read_exact(&mut buf).unwrap();
+        assert_eq!(b"By what name", &buf);
+
+        manager.shutdown(Instant::now());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,462 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::*;
+
+use crate::cidr::Cidr;
+
+/// CircleMUD's `BANNED_SITE_LENGTH`
+pub const MAX_SITE_LENGTH: usize = 50;
+
+/// How much of the game a banned site is kept out of, as CircleMUD's `BAN_*` constants
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BanType {
+    Not = 0,
+    /// No new characters
+    New = 1,
+    /// Only characters flagged SITEOK may log in
+    Select = 2,
+    /// No connections at all
+    All = 3,
+}
+
+impl BanType {
+    /// The name `lib/etc/badsites` stores the type under
+    pub fn name(self) -> &'static str {
+        match self {
+            BanType::Not => "no",
+            BanType::New => "new",
+            BanType::Select => "select",
+            BanType::All => "all",
+        }
+    }
+}
+
+impl TryFrom<i32> for BanType {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BanType::Not),
+            1 => Ok(BanType::New),
+            2 => Ok(BanType::Select),
+            3 => Ok(BanType::All),
+            other => Err(format!("Unknown ban type {}", other)),
+        }
+    }
+}
+
+impl FromStr for BanType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [BanType::Not, BanType::New, BanType::Select, BanType::All]
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("Unknown ban type {}", s))
+    }
+}
+
+/// A banned site, as written by whoever banned it: an address or CIDR block (IPv4 or IPv6), a
+/// hostname pattern using `*` and `?`, or (as CircleMUD always had) any part of a hostname
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Site {
+    text: String,
+    matcher: Matcher,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Matcher {
+    Block(Cidr),
+    Pattern,
+    Substring,
+}
+
+impl Site {
+    /// Whether a client named `hostname` (its address if it had no name) at `ip` is this site
+    pub fn matches(&self, hostname: &str, ip: Option<IpAddr>) -> bool {
+        let hostname = hostname.to_lowercase();
+        let ip_text = ip.map(|ip| ip.to_canonical().to_string());
+        let mut names = std::iter::once(hostname.as_str()).chain(ip_text.as_deref());
+        match &self.matcher {
+            Matcher::Block(block) => match ip.or_else(|| hostname.parse().ok()) {
+                Some(ip) => block.contains(ip),
+                None => false,
+            },
+            Matcher::Pattern => {
+                names.any(|name| wildcard_matches(self.text.as_bytes(), name.as_bytes()))
+            }
+            Matcher::Substring => names.any(|name| name.contains(self.text.as_str())),
+        }
+    }
+}
+
+impl FromStr for Site {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.to_lowercase();
+        if text.is_empty() || text.contains(char::is_whitespace) {
+            return Err(format!("Invalid site {:?}", s));
+        }
+        if text.len() > MAX_SITE_LENGTH {
+            return Err(format!(
+                "Site {} is longer than {} characters",
+                s, MAX_SITE_LENGTH
+            ));
+        }
+        let matcher = if text.contains('/') || text.parse::<IpAddr>().is_ok() {
+            Matcher::Block(text.parse()?)
+        } else if text.contains(['*', '?']) {
+            Matcher::Pattern
+        } else {
+            Matcher::Substring
+        };
+        Ok(Site { text, matcher })
+    }
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one
+fn wildcard_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| wildcard_matches(rest, &name[skip..])),
+        Some((&c, rest)) => match name.split_first() {
+            Some((&n, name)) if c == b'?' || c == n => wildcard_matches(rest, name),
+            _ => false,
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ban {
+    pub kind: BanType,
+    pub site: Site,
+    /// When it was banned, in seconds since the epoch (0 if unknown)
+    pub date: i64,
+    /// Who banned it
+    pub name: String,
+    /// When it stops being banned, in seconds since the epoch, if it ever does
+    pub expires: Option<i64>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+
+    /// Reads a `lib/etc/badsites` line: `<type> <site> <date> <name> [<expires>]`
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (kind, site, date, name, expires) = match fields[..] {
+            [kind, site, date, name] => (kind, site, date, name, None),
+            [kind, site, date, name, expires] => (kind, site, date, name, Some(expires)),
+            _ => return Err(format!("Invalid ban {:?}", line)),
+        };
+        let number = |n: &str| {
+            n.parse::<i64>()
+                .map_err(|e| format!("Invalid time {} in ban {:?}: {}", n, line, e))
+        };
+        Ok(Ban {
+            kind: kind.parse()?,
+            site: site.parse()?,
+            date: number(date)?,
+            name: name.to_owned(),
+            expires: expires.map(number).transpose()?,
+        })
+    }
+}
+
+impl fmt::Display for Ban {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.kind.name(),
+            self.site,
+            self.date,
+            self.name
+        )?;
+        match self.expires {
+            Some(expires) => write!(f, " {}", expires),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The banned sites, stored in CircleMUD's `lib/etc/badsites` format (plus an optional expiry
+/// time on the end of each line), newest first
+#[derive(Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    /// Loads the bans stored at `path`, starting with none if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let mut bans = Vec::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match Ban::parse(&line) {
+                        // Written oldest first
+                        Ok(ban) => bans.insert(0, ban),
+                        Err(e) => warn!("Ignoring ban: {}", e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Ban file {:?} doesn't exist", path)
+            }
+            Err(e) => return Err(e),
+        }
+        info!("Loaded {} bans from {:?}", bans.len(), path);
+        Ok(BanList {
+            path: Some(path),
+            bans,
+        })
+    }
+
+    /// The strictest ban on a client named `hostname` at `ip`
+    pub fn check(&self, hostname: &str, ip: Option<IpAddr>, now: i64) -> BanType {
+        self.active(now)
+            .filter(|ban| ban.site.matches(hostname, ip))
+            .map(|ban| ban.kind)
+            .max()
+            .unwrap_or(BanType::Not)
+    }
+
+    /// The bans still in force, newest first
+    pub fn active(&self, now: i64) -> impl Iterator<Item = &Ban> {
+        self.bans.iter().filter(move |ban| ban.is_active(now))
+    }
+
+    /// Adds `ban` unless its site is already banned (expired bans on it are replaced)
+    pub fn add(&mut self, ban: Ban, now: i64) -> Result<(), std::io::Error> {
+        if self.active(now).any(|banned| banned.site == ban.site) {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                "That site has already been banned -- unban it to change the ban type.",
+            ));
+        }
+        self.bans.retain(|banned| banned.site != ban.site);
+        self.bans.insert(0, ban);
+        self.save()
+    }
+
+    /// Removes the ban on `site`, returning it if there was one in force
+    pub fn remove(&mut self, site: &str, now: i64) -> Result<Option<Ban>, std::io::Error> {
+        let site = site.to_lowercase();
+        let Some(index) = self.bans.iter().position(|ban| ban.site.text == site) else {
+            return Ok(None);
+        };
+        let ban = self.bans.remove(index);
+        self.save()?;
+        Ok(Some(ban).filter(|ban| ban.is_active(now)))
+    }
+
+    /// Forgets every ban until they're next loaded, without touching the file
+    pub fn clear(&mut self) {
+        self.bans.clear();
+    }
+
+    fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let now = now();
+        // Write a sibling file and rename it over the old one so a crash can't truncate the bans
+        let temporary_path = path.with_extension("tmp");
+        let mut file = File::create(&temporary_path)?;
+        for ban in self.bans.iter().rev().filter(|ban| ban.is_active(now)) {
+            writeln!(file, "{}", ban)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(temporary_path, path)
+    }
+}
+
+/// Seconds since the epoch, as CircleMUD's `time(0)`
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Parses a ban's length, eg `30m`, `12h`, `7d` or `2w`, into seconds
+pub fn parse_duration(s: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid duration {} (try eg 30m, 12h, 7d or 2w)", s);
+    let unit = match s.chars().last() {
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    s[..s.len() - 1]
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(invalid)
+}
+
+/// When a ban of length `duration` (as [`parse_duration`] reads it) placed at `now` expires
+pub fn expiry(now: i64, duration: &str) -> Result<i64, String> {
+    now.checked_add(parse_duration(duration)?)
+        .ok_or_else(|| format!("Duration {} is too long", duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn ban(kind: BanType, site: &str) -> Ban {
+        Ban {
+            kind,
+            site: site.parse().expect("valid site"),
+            date: 1000,
+            name: "Foo".to_owned(),
+            expires: None,
+        }
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_sites() {
+        let substring: Site = "Example.COM".parse().unwrap();
+        assert!(substring.matches("dialup-7.example.com", ip("192.0.2.7")));
+        assert!(!substring.matches("example.org", ip("192.0.2.7")));
+
+        let partial_address: Site = "192.0.2.".parse().unwrap();
+        assert!(partial_address.matches("dialup-7.example.com", ip("192.0.2.7")));
+
+        let pattern: Site = "dialup-?.*.com".parse().unwrap();
+        assert!(pattern.matches("dialup-7.example.com", ip("192.0.2.7")));
+        assert!(!pattern.matches("dialup-17.example.com", ip("192.0.2.7")));
+        assert!(!pattern.matches("x.dialup-7.example.com", ip("192.0.2.7")));
+
+        let block: Site = "192.0.2.0/28".parse().unwrap();
+        assert!(block.matches("dialup-7.example.com", ip("192.0.2.7")));
+        assert!(block.matches("dialup-7.example.com", ip("::ffff:192.0.2.7")));
+        assert!(!block.matches("dialup-7.example.com", ip("192.0.2.17")));
+        // Without an address, one is only found in an unresolved hostname
+        assert!(block.matches("192.0.2.7", None));
+        assert!(!block.matches("dialup-7.example.com", None));
+
+        let v6: Site = "2001:db8::/32".parse().unwrap();
+        assert!(v6.matches("example.com", ip("2001:db8::9")));
+        let single: Site = "2001:db8::9".parse().unwrap();
+        assert!(!single.matches("example.com", ip("2001:db8::10")));
+
+        assert!("192.0.2.0/33".parse::<Site>().is_err());
+        assert!("a".repeat(MAX_SITE_LENGTH + 1).parse::<Site>().is_err());
+    }
+
+    #[test]
+    fn test_strictest_active_ban_applies() {
+        let mut bans = BanList::default();
+        bans.add(ban(BanType::New, "example.com"), 1000).unwrap();
+        bans.add(ban(BanType::All, "dialup-*"), 1000).unwrap();
+        let mut expiring = ban(BanType::All, "192.0.2.0/24");
+        expiring.expires = Some(2000);
+        bans.add(expiring, 1000).unwrap();
+
+        assert_eq!(
+            BanType::New,
+            bans.check("www.example.com", ip("198.51.100.1"), 1000)
+        );
+        assert_eq!(BanType::All, bans.check("dialup-7.example.com", None, 1000));
+        assert_eq!(
+            BanType::All,
+            bans.check("www.example.com", ip("192.0.2.1"), 1999)
+        );
+        assert_eq!(
+            BanType::New,
+            bans.check("www.example.com", ip("192.0.2.1"), 2000)
+        );
+        assert_eq!(
+            BanType::Not,
+            bans.check("example.org", ip("198.51.100.1"), 1000)
+        );
+
+        assert_eq!(
+            ErrorKind::AlreadyExists,
+            bans.add(ban(BanType::Select, "EXAMPLE.com"), 1000)
+                .unwrap_err()
+                .kind()
+        );
+        // Expired bans are replaced
+        assert!(bans.add(ban(BanType::New, "192.0.2.0/24"), 2000).is_ok());
+
+        let removed = bans.remove("example.com", 1000).unwrap();
+        assert_eq!(Some(BanType::New), removed.map(|ban| ban.kind));
+        assert_eq!(None, bans.remove("example.com", 1000).unwrap());
+    }
+
+    #[test]
+    fn test_badsites_round_trips() {
+        let dir = TempDir::new("bans").expect("temporary directory");
+        let path = dir.path().join("badsites");
+        std::fs::write(
+            &path,
+            "select foo.com 867530900 Admin\nall 192.0.2.0/24 867531000 Admin 4000000000\n",
+        )
+        .unwrap();
+
+        let mut bans = BanList::load(&path).expect("loaded");
+        let sites: Vec<String> = bans.active(1000).map(|ban| ban.site.to_string()).collect();
+        assert_eq!(vec!["192.0.2.0/24", "foo.com"], sites);
+
+        bans.add(ban(BanType::New, "*.example.com"), 1000).unwrap();
+        assert_eq!(
+            "select foo.com 867530900 Admin\n\
+             all 192.0.2.0/24 867531000 Admin 4000000000\n\
+             new *.example.com 1000 Foo\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(Ok(30 * 60), parse_duration("30m"));
+        assert_eq!(Ok(2 * 7 * 24 * 60 * 60), parse_duration("2w"));
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("7y").is_err());
+    }
+
+    #[test]
+    fn test_expiry() {
+        assert_eq!(Ok(1000 + 12 * 60 * 60), expiry(1000, "12h"));
+        // Long enough to parse, but not to add to the time
+        assert!(parse_duration("15250284452471w").is_ok());
+        assert!(expiry(now(), "15250284452471w").is_err());
+    }
+}
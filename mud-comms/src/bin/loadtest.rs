@@ -0,0 +1,383 @@
+//! Sets a crowd of bots on a server to see how it copes: each connects over telnet, creates a
+//! character, then walks and chats for a number of rounds, timing how long every command takes to
+//! be answered. The bots' characters are saved like any other, so point it at a throwaway copy of
+//! `mud-comms/tests/clean_lib` (eg `bin/circle -d /tmp/loadtest_lib 4000`):
+//!
+//!     loadtest 127.0.0.1:4000 --players 100 --rounds 20 --script chat
+//!
+//! Bots only play over telnet, as there's no transport for players on WebSockets (Slack's Socket
+//! Mode is a WebSocket the game opens to Slack, not one players connect to).
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use mud_comms::telnet;
+
+const USAGE: &str =
+    "Usage: loadtest [address (default 127.0.0.1:4000)] [--players N (default 10)] \
+     [--rounds N (default 10)] [--script walk|chat|both (default both)] [--ramp-up SECONDS]";
+
+/// How long a bot waits for an answer before giving up on the server
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the bots' characters log in with
+const PASSWORD: &str = "loadtest";
+
+/// Where new characters start, and where they're walked back to
+const START_ROOM: &str = "The Temple Of Midgaard";
+
+/// Where the first character made on a new lib starts, as it's made the game's implementor
+const IMMORTAL_START_ROOM: &str = "The Immortal Board Room";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Script {
+    Walk,
+    Chat,
+    Both,
+}
+
+struct Args {
+    address: String,
+    players: usize,
+    rounds: usize,
+    script: Script,
+    ramp_up: Duration,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut address = None;
+    let mut players = 10;
+    let mut rounds = 10;
+    let mut script = Script::Both;
+    let mut ramp_up = Duration::ZERO;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--players" => {
+                players = value("--players")?
+                    .parse()
+                    .map_err(|e| format!("Invalid --players provided: {}", e))?
+            }
+            "--rounds" => {
+                rounds = value("--rounds")?
+                    .parse()
+                    .map_err(|e| format!("Invalid --rounds provided: {}", e))?
+            }
+            "--script" => {
+                script = match value("--script")?.as_str() {
+                    "walk" => Script::Walk,
+                    "chat" => Script::Chat,
+                    "both" => Script::Both,
+                    other => return Err(format!("Unknown --script {}\n{}", other, USAGE)),
+                }
+            }
+            "--ramp-up" => {
+                ramp_up = Duration::try_from_secs_f64(
+                    value("--ramp-up")?
+                        .parse()
+                        .map_err(|e| format!("Invalid --ramp-up provided: {}", e))?,
+                )
+                .map_err(|e| format!("Invalid --ramp-up provided: {}", e))?
+            }
+            "-h" | "--help" => return Err(USAGE.to_owned()),
+            _ if address.is_none() => address = Some(arg),
+            _ => return Err(format!("Unexpected argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(Args {
+        address: address.unwrap_or_else(|| "127.0.0.1:4000".to_owned()),
+        players,
+        rounds,
+        script,
+        ramp_up,
+    })
+}
+
+/// A command a bot sends each round, and what it waits for in reply
+struct Step {
+    name: &'static str,
+    command: String,
+    expected: &'static str,
+}
+
+fn steps(script: Script, player: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+    if script != Script::Chat {
+        steps.push(Step {
+            name: "west",
+            command: "west".to_owned(),
+            expected: "The Reading Room",
+        });
+        steps.push(Step {
+            name: "east",
+            command: "east".to_owned(),
+            expected: START_ROOM,
+        });
+    }
+    if script != Script::Walk {
+        steps.push(Step {
+            name: "say",
+            command: format!("say Hello, I'm {}", player),
+            expected: "You say, '",
+        });
+        steps.push(Step {
+            name: "gossip",
+            command: "gossip Load testing, sorry for the noise".to_owned(),
+            expected: "You gossip, '",
+        });
+    }
+    steps
+}
+
+/// How long each kind of step took to be answered
+#[derive(Default)]
+struct Timings(BTreeMap<&'static str, Vec<Duration>>);
+
+impl Timings {
+    fn record(&mut self, step: &'static str, took: Duration) {
+        self.0.entry(step).or_default().push(took);
+    }
+
+    fn merge(&mut self, other: Timings) {
+        for (step, times) in other.0 {
+            self.0.entry(step).or_default().extend(times);
+        }
+    }
+}
+
+/// A telnet client that reads the game's text (without its negotiation) until it sees what it's
+/// waiting for
+struct Bot {
+    stream: TcpStream,
+    telnet: telnet::Parser,
+    /// Text received since what was last waited for
+    text: Vec<u8>,
+}
+
+impl Bot {
+    fn connect(address: &str) -> Result<Self, std::io::Error> {
+        Ok(Bot {
+            stream: TcpStream::connect(address)?,
+            telnet: telnet::Parser::default(),
+            text: Vec::new(),
+        })
+    }
+
+    /// Sends `line` and waits for one of `expected`, returning which came first and how long
+    /// after sending
+    fn command(
+        &mut self,
+        line: &str,
+        expected: &[&str],
+    ) -> Result<(usize, Duration), std::io::Error> {
+        let sent = Instant::now();
+        self.stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        let found = self.wait_for(expected)?;
+        Ok((found, sent.elapsed()))
+    }
+
+    fn wait_for(&mut self, expected: &[&str]) -> Result<usize, std::io::Error> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut buf = [0; 4096];
+        loop {
+            if let Some((found, end)) = expected
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| find(&self.text, e.as_bytes()).map(|at| (i, at + e.len())))
+                .min_by_key(|&(_, end)| end)
+            {
+                self.text.drain(..end);
+                return Ok(found);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "timed out waiting for {:?}, last got {:?}",
+                        expected,
+                        String::from_utf8_lossy(&self.text[self.text.len().saturating_sub(200)..])
+                    ),
+                ));
+            }
+            self.stream.set_read_timeout(Some(left))?;
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    for event in self.telnet.parse(&buf[..read]) {
+                        if let telnet::Event::Data(data) = event {
+                            self.text.extend(data);
+                        }
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Logs `name` in (creating the character if it's new), plays the script and quits
+fn play(name: &str, args: &Args) -> Result<Timings, std::io::Error> {
+    let mut timings = Timings::default();
+    let connecting = Instant::now();
+    let mut bot = Bot::connect(&args.address)?;
+    bot.wait_for(&["By what name do you wish to be known? "])?;
+    timings.record("connect", connecting.elapsed());
+
+    let (found, took) = bot.command(name, &["(Y/N)? ", "Password: "])?;
+    timings.record("login", took);
+    let login: &[(&str, &str)] = match found {
+        0 => &[
+            ("y", "Give me a password for "),
+            (PASSWORD, "Please retype password: "),
+            (PASSWORD, "What is your sex (M/F)? "),
+            ("m", "Class: "),
+            ("w", "*** PRESS RETURN: "),
+            ("", "Make your choice: "),
+        ],
+        // Left over from an earlier run
+        _ => &[(PASSWORD, "*** PRESS RETURN: "), ("", "Make your choice: ")],
+    };
+    for (line, expected) in login {
+        timings.record("login", bot.command(line, &[expected])?.1);
+    }
+    let (found, took) = bot.command("1", &[START_ROOM, IMMORTAL_START_ROOM])?;
+    timings.record("enter game", took);
+    if found == 1 {
+        bot.command("goto 3001", &[START_ROOM])?;
+    }
+
+    let steps = steps(args.script, name);
+    for _ in 0..args.rounds {
+        for step in &steps {
+            timings.record(step.name, bot.command(&step.command, &[step.expected])?.1);
+        }
+    }
+    timings.record("quit", bot.command("quit", &["Make your choice: "])?.1);
+    bot.stream.write_all(b"0\r\n")?;
+    Ok(timings)
+}
+
+/// A name for each bot, unique to the run so characters from earlier runs aren't logged into
+/// (names can only have letters)
+fn names(players: usize) -> Vec<String> {
+    let run = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    (0..players)
+        .map(|player| {
+            format!(
+                "Bot{}{}",
+                letters(run % 26u64.pow(4), 4),
+                letters(player as u64, 1)
+            )
+        })
+        .collect()
+}
+
+fn letters(mut n: u64, min_len: usize) -> String {
+    let mut letters = Vec::new();
+    while n > 0 || letters.len() < min_len {
+        letters.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).expect("letters are ASCII")
+}
+
+/// The time at percentile `p` of `sorted`, by nearest rank
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn report(timings: Timings, finished: usize, failed: usize, elapsed: Duration) {
+    let steps: usize = timings.0.values().map(Vec::len).sum();
+    println!(
+        "{} players finished, {} failed, in {:.1}s ({:.1} steps/s)",
+        finished,
+        failed,
+        elapsed.as_secs_f64(),
+        steps as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "{:<12} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "step", "count", "p50", "p90", "p99", "max"
+    );
+    for (step, mut times) in timings.0 {
+        times.sort();
+        println!(
+            "{:<12} {:>7} {:>9} {:>9} {:>9} {:>9}",
+            step,
+            times.len(),
+            millis(percentile(&times, 50.0)),
+            millis(percentile(&times, 90.0)),
+            millis(percentile(&times, 99.0)),
+            millis(*times.last().expect("at least one time")),
+        );
+    }
+}
+
+fn run(args: Args) -> bool {
+    let started = Instant::now();
+    let args = std::sync::Arc::new(args);
+    let bots: Vec<_> = names(args.players)
+        .into_iter()
+        .enumerate()
+        .map(|(player, name)| {
+            let args = std::sync::Arc::clone(&args);
+            let delay = args.ramp_up.mul_f64(player as f64 / args.players as f64);
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let result = play(&name, &args);
+                (name, result)
+            })
+        })
+        .collect();
+
+    let mut timings = Timings::default();
+    let mut failed = 0;
+    for bot in bots {
+        match bot.join().expect("bot thread panicked") {
+            (_, Ok(times)) => timings.merge(times),
+            (name, Err(e)) => {
+                eprintln!("{}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    report(timings, args.players - failed, failed, started.elapsed());
+    failed == 0
+}
+
+fn main() -> ExitCode {
+    match parse_args().map(run) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
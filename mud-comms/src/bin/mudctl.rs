@@ -0,0 +1,108 @@
+//! Runs the game's admin commands, or logs in, over the admin socket set by
+//! `MUD_COMMS_ADMIN_SOCKET`, without going near a network port:
+//!
+//!     mudctl --socket lib/etc/admin.sock who
+//!     mudctl wall The game is going down for a reboot in five minutes
+//!     mudctl close 12
+//!     mudctl attach
+
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+
+const USAGE: &str =
+    "Usage: mudctl [--socket PATH] <who | shutdown | reboot | wall MESSAGE | close ID | attach>\n\
+     The socket defaults to $MUD_COMMS_ADMIN_SOCKET.";
+
+/// Telnet's IAC, which starts the negotiation a terminal shouldn't be shown
+const IAC: u8 = 255;
+
+struct Args {
+    socket: PathBuf,
+    command: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut socket = std::env::var_os("MUD_COMMS_ADMIN_SOCKET").map(PathBuf::from);
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" if command.is_empty() => {
+                socket = Some(args.next().ok_or("--socket needs a path")?.into())
+            }
+            "-h" | "--help" if command.is_empty() => return Err(USAGE.to_owned()),
+            _ => command.push(arg),
+        }
+    }
+    if command.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+    Ok(Args {
+        socket: socket.ok_or(format!("No admin socket given\n{}", USAGE))?,
+        command,
+    })
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut stream = UnixStream::connect(&args.socket)
+        .map_err(|e| format!("Cannot connect to {:?}: {}", args.socket, e))?;
+    if args.command == ["attach"] {
+        return attach(stream);
+    }
+    writeln!(stream, "command {}", args.command.join(" "))?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    print!("{}", reply);
+    Ok(())
+}
+
+/// Logs in like any other connection, until the game or stdin hangs up
+fn attach(mut stream: UnixStream) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    stream.write_all(b"attach\n")?;
+    let mut output = stream.try_clone()?;
+    let printer = thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut stdout = std::io::stdout();
+        let mut negotiation = 0;
+        while let Ok(read @ 1..) = output.read(&mut buf) {
+            // Drops IAC <verb> <option>, which is all the game sends
+            let mut shown = Vec::with_capacity(read);
+            for &byte in &buf[..read] {
+                match (negotiation, byte) {
+                    (0, IAC) => negotiation = 2,
+                    (0, _) => shown.push(byte),
+                    (_, _) => negotiation -= 1,
+                }
+            }
+            let _ = stdout.write_all(&shown);
+            let _ = stdout.flush();
+        }
+    });
+
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        if printer.is_finished() {
+            break;
+        }
+        stream.write_all(format!("{}\r\n", line?).as_bytes())?;
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+    let _ = printer.join();
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args().map_err(Into::into).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
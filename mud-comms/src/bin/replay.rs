@@ -0,0 +1,95 @@
+//! Plays a session recorded with `MUD_COMMS_RECORD_SESSIONS` back against a server, sending what
+//! the player sent with the same pauses and printing what the server says back, eg to reproduce a
+//! bug report on a test copy of the game:
+//!
+//!     replay ../log/sessions/20240501-201502-12.cast 127.0.0.1:4000 --speed 4
+
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use mud_comms::recording::read_events;
+use mud_comms::recording::Direction;
+
+const USAGE: &str = "Usage: replay <recording.cast> [address (default 127.0.0.1:4000)] [--speed N]";
+
+/// How long to keep printing the server's output after the last input was sent
+const LINGER: Duration = Duration::from_secs(2);
+
+struct Args {
+    recording: PathBuf,
+    address: String,
+    speed: f64,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut recording = None;
+    let mut address = None;
+    let mut speed: f64 = 1.0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--speed" => {
+                speed = args
+                    .next()
+                    .ok_or("--speed needs a value")?
+                    .parse()
+                    .map_err(|e| format!("Invalid --speed provided: {}", e))?;
+                if speed.is_nan() || speed <= 0.0 {
+                    return Err("--speed must be more than 0".to_owned());
+                }
+            }
+            "-h" | "--help" => return Err(USAGE.to_owned()),
+            _ if recording.is_none() => recording = Some(PathBuf::from(arg)),
+            _ if address.is_none() => address = Some(arg),
+            _ => return Err(format!("Unexpected argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(Args {
+        recording: recording.ok_or(USAGE)?,
+        address: address.unwrap_or_else(|| "127.0.0.1:4000".to_owned()),
+        speed,
+    })
+}
+
+fn replay(args: Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let events = read_events(&args.recording)?;
+    let mut stream = TcpStream::connect(&args.address)?;
+    let mut output = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut stdout = std::io::stdout();
+        while let Ok(read @ 1..) = output.read(&mut buf) {
+            let _ = stdout.write_all(&buf[..read]);
+            let _ = stdout.flush();
+        }
+    });
+
+    let started = Instant::now();
+    for event in events {
+        if event.direction != Direction::Input {
+            continue;
+        }
+        let due = started + Duration::from_secs_f64(event.time / args.speed);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        stream.write_all(event.data.as_bytes())?;
+    }
+    thread::sleep(LINGER);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().map_err(Into::into).and_then(replay);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
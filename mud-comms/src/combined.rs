@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::copyover::SavedDescriptor;
+use crate::descriptor::no_new_descriptor;
+use crate::descriptor::AdminCommand;
+use crate::descriptor::ChannelMessage;
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+
+/// How often `block_until_descriptor` checks each manager
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Takes descriptors from several managers at once, eg the game's transport and the admin
+/// socket. The first is the main one, which keeps its listener through a copyover and restores
+/// the descriptors saved from it.
+pub struct CombinedDescriptorManager {
+    managers: Vec<Box<dyn DescriptorManager>>,
+    /// Descriptors and commands found while waiting for something to do
+    waiting: RefCell<VecDeque<Box<dyn Descriptor>>>,
+    commands: RefCell<VecDeque<AdminCommand>>,
+}
+
+impl CombinedDescriptorManager {
+    pub fn new(managers: Vec<Box<dyn DescriptorManager>>) -> Self {
+        CombinedDescriptorManager {
+            managers,
+            waiting: RefCell::new(VecDeque::new()),
+            commands: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn next_waiting(
+        &self,
+    ) -> Result<Option<Box<dyn Descriptor>>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(descriptor) = self.waiting.borrow_mut().pop_front() {
+            return Ok(Some(descriptor));
+        }
+        for manager in &self.managers {
+            match manager.new_descriptor() {
+                Ok(descriptor) => return Ok(Some(descriptor)),
+                Err(e) if no_new_descriptor(e.as_ref()) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl DescriptorManager for CombinedDescriptorManager {
+    fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+        // Managers each block their own way, so they're polled instead
+        loop {
+            if !self.commands.borrow().is_empty() {
+                return Ok(());
+            }
+            match self.next_waiting() {
+                Ok(Some(descriptor)) => {
+                    self.waiting.borrow_mut().push_back(descriptor);
+                    return Ok(());
+                }
+                Ok(None) => {}
+                Err(e) => return Err(std::io::Error::other(e)),
+            }
+            if let Some(command) = self.managers.iter().find_map(|m| m.next_admin_command()) {
+                self.commands.borrow_mut().push_back(command);
+                return Ok(());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn new_descriptor(
+        &self,
+    ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        self.next_waiting()?
+            .ok_or_else(|| std::io::Error::from(ErrorKind::WouldBlock).into())
+    }
+
+    fn mirror(&self, target: &str, text: &str) {
+        for manager in &self.managers {
+            manager.mirror(target, text);
+        }
+    }
+
+    fn unlink_character(&self, character: &str) -> Result<(), std::io::Error> {
+        for manager in &self.managers {
+            manager.unlink_character(character)?;
+        }
+        Ok(())
+    }
+
+    fn next_channel_message(&self) -> Option<ChannelMessage> {
+        self.managers.iter().find_map(|m| m.next_channel_message())
+    }
+
+    fn next_admin_command(&self) -> Option<AdminCommand> {
+        self.commands
+            .borrow_mut()
+            .pop_front()
+            .or_else(|| self.managers.iter().find_map(|m| m.next_admin_command()))
+    }
+
+    fn save_listener(&self) -> Result<Option<RawFd>, std::io::Error> {
+        match self.managers.first() {
+            Some(manager) => manager.save_listener(),
+            None => Ok(None),
+        }
+    }
+
+    fn shutdown(&mut self, deadline: Instant) {
+        for manager in self.managers.iter_mut() {
+            manager.shutdown(deadline);
+        }
+        self.waiting.get_mut().clear();
+        self.commands.get_mut().clear();
+    }
+
+    fn restore_descriptor(
+        &self,
+        saved: SavedDescriptor,
+    ) -> Result<Box<dyn Descriptor>, std::io::Error> {
+        match self.managers.first() {
+            Some(manager) => manager.restore_descriptor(saved),
+            None => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "no manager to restore descriptors",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::io::Write;
+
+    use super::*;
+
+    struct FakeDescriptor(&'static str);
+
+    impl Descriptor for FakeDescriptor {
+        fn get_hostname(&self) -> &str {
+            self.0
+        }
+
+        fn get_type(&self) -> &'static str {
+            "FAKE"
+        }
+    }
+
+    impl Read for FakeDescriptor {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for FakeDescriptor {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Hands out `pending` descriptors named `name`, one per call
+    struct FakeManager {
+        name: &'static str,
+        pending: Cell<usize>,
+    }
+
+    impl DescriptorManager for FakeManager {
+        fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+            unreachable!("combined managers are polled")
+        }
+
+        fn new_descriptor(
+            &self,
+        ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+            match self.pending.get() {
+                0 => Err(Box::new(std::io::Error::from(ErrorKind::WouldBlock))),
+                pending => {
+                    self.pending.set(pending - 1);
+                    Ok(Box::new(FakeDescriptor(self.name)))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_descriptors_come_from_every_manager() {
+        let manager = CombinedDescriptorManager::new(vec![
+            Box::new(FakeManager {
+                name: "telnet",
+                pending: Cell::new(0),
+            }),
+            Box::new(FakeManager {
+                name: "admin",
+                pending: Cell::new(2),
+            }),
+        ]);
+        // What's found while blocking is still handed out first
+        manager.block_until_descriptor().expect("a descriptor");
+        let hostnames: Vec<_> = std::iter::from_fn(|| manager.new_descriptor().ok())
+            .map(|descriptor| descriptor.get_hostname().to_owned())
+            .collect();
+        assert_eq!(vec!["admin", "admin"], hostnames);
+        let e = manager.new_descriptor().err().expect("no more descriptors");
+        assert!(no_new_descriptor(e.as_ref()));
+    }
+}
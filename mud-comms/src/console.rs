@@ -0,0 +1,272 @@
+use std::cell::Cell;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::io::RawFd;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use libc::c_void;
+use libc::isatty;
+use libc::poll;
+use libc::pollfd;
+use libc::read;
+use libc::tcgetattr;
+use libc::tcsetattr;
+use libc::termios;
+use libc::ECHO;
+use libc::ICANON;
+use libc::POLLIN;
+use libc::STDIN_FILENO;
+use libc::TCSANOW;
+use libc::VMIN;
+use libc::VTIME;
+use log::*;
+
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+use crate::telnet;
+
+/// The terminal's settings from before the console took it over, to put back when it's done
+static SAVED_TERMINAL: OnceLock<termios> = OnceLock::new();
+
+/// Lets a developer play in the terminal the game was started from, with no client: hands out
+/// exactly one descriptor, reading stdin and writing stdout. Once it's closed there won't be
+/// another, so the game just waits to be stopped.
+#[derive(Default)]
+pub struct ConsoleDescriptorManager {
+    taken: Cell<bool>,
+}
+
+impl DescriptorManager for ConsoleDescriptorManager {
+    fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+        if !self.taken.get() {
+            return Ok(());
+        }
+        info!("Console session over, stop the game with Ctrl-C");
+        loop {
+            std::thread::sleep(Duration::from_secs(3600));
+        }
+    }
+
+    fn new_descriptor(
+        &self,
+    ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        if self.taken.replace(true) {
+            return Err(Box::new(std::io::Error::from(ErrorKind::WouldBlock)));
+        }
+        Ok(Box::new(ConsoleDescriptor::open()?))
+    }
+}
+
+/// The player at the terminal. Input is taken a key at a time and echoed here, as a telnet
+/// client would, so the game's own backspacing and password prompts work; the telnet commands
+/// in the game's output are acted on and left out.
+pub struct ConsoleDescriptor {
+    input: RawFd,
+    output: Box<dyn Write>,
+    /// Whether input is from a terminal, which needs its typing echoed (piped input doesn't)
+    terminal: bool,
+    /// Whether the game has said it will echo, ie it's asking for a password and won't
+    game_echoes: bool,
+    telnet: telnet::Parser,
+}
+
+impl ConsoleDescriptor {
+    fn open() -> Result<Self, std::io::Error> {
+        // SAFETY: isatty only looks at the descriptor
+        let terminal = unsafe { isatty(STDIN_FILENO) } == 1;
+        if terminal {
+            take_over_terminal()?;
+        }
+        Ok(ConsoleDescriptor::new(
+            STDIN_FILENO,
+            Box::new(std::io::stdout()),
+            terminal,
+        ))
+    }
+
+    fn new(input: RawFd, output: Box<dyn Write>, terminal: bool) -> Self {
+        ConsoleDescriptor {
+            input,
+            output,
+            terminal,
+            game_echoes: false,
+            telnet: telnet::Parser::default(),
+        }
+    }
+
+    fn echo(&mut self, input: &[u8]) -> Result<(), std::io::Error> {
+        let mut echoed = Vec::with_capacity(input.len());
+        for &byte in input {
+            match byte {
+                b'\r' | b'\n' => echoed.extend_from_slice(b"\r\n"),
+                // The game takes both as backspace
+                b'\x08' | 127 => echoed.extend_from_slice(b"\x08 \x08"),
+                b' '..=b'~' => echoed.push(byte),
+                _ => {}
+            }
+        }
+        self.output.write_all(&echoed)?;
+        self.output.flush()
+    }
+}
+
+/// Turns off the terminal's line editing and echo, so the game sees each key as it's typed,
+/// until `restore_terminal`. Ctrl-C still stops the game, which restores it on the way out.
+fn take_over_terminal() -> Result<(), std::io::Error> {
+    // SAFETY: termios is plain data, filled in by tcgetattr before it's used
+    let mut settings: termios = unsafe { std::mem::zeroed() };
+    if unsafe { tcgetattr(STDIN_FILENO, &mut settings) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if SAVED_TERMINAL.set(settings).is_ok() {
+        // SAFETY: restore_terminal_at_exit is safe to run at any point
+        if unsafe { libc::atexit(restore_terminal_at_exit) } != 0 {
+            warn!("Cannot restore the terminal at exit");
+        }
+    }
+    settings.c_lflag &= !(ICANON | ECHO);
+    settings.c_cc[VMIN] = 1;
+    settings.c_cc[VTIME] = 0;
+    // SAFETY: settings are those just read with two flags changed
+    if unsafe { tcsetattr(STDIN_FILENO, TCSANOW, &settings) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn restore_terminal() {
+    if let Some(settings) = SAVED_TERMINAL.get() {
+        // SAFETY: settings are the terminal's own, as read by tcgetattr
+        if unsafe { tcsetattr(STDIN_FILENO, TCSANOW, settings) } < 0 {
+            error!(
+                "Cannot restore terminal: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// The game exits from its signal handlers, which skips dropping the descriptor
+extern "C" fn restore_terminal_at_exit() {
+    restore_terminal();
+}
+
+impl Drop for ConsoleDescriptor {
+    fn drop(&mut self) {
+        if self.terminal {
+            restore_terminal();
+        }
+    }
+}
+
+impl Descriptor for ConsoleDescriptor {
+    fn get_hostname(&self) -> &str {
+        "localhost"
+    }
+
+    fn get_type(&self) -> &'static str {
+        "CONSOLE"
+    }
+}
+
+impl Read for ConsoleDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Polled rather than made nonblocking, which would also change the shell's terminal
+        let mut ready = pollfd {
+            fd: self.input,
+            events: POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `ready` outlives the call
+        match unsafe { poll(&mut ready, 1, 0) } {
+            0 => return Err(std::io::Error::from(ErrorKind::WouldBlock)),
+            result if result < 0 => return Err(std::io::Error::last_os_error()),
+            _ => {}
+        }
+        // SAFETY: `buf` is writable for its length
+        let result = unsafe { read(self.input, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let read = result as usize;
+        if self.terminal && !self.game_echoes {
+            self.echo(&buf[..read])?;
+        }
+        Ok(read)
+    }
+}
+
+impl Write for ConsoleDescriptor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut shown = Vec::with_capacity(buf.len());
+        for event in self.telnet.parse(buf) {
+            match event {
+                telnet::Event::Data(data) => shown.extend(data),
+                telnet::Event::Will(telnet::ECHO) => self.game_echoes = true,
+                telnet::Event::Wont(telnet::ECHO) => self.game_echoes = false,
+                _ => {}
+            }
+        }
+        self.output.write_all(&shown)?;
+        self.output.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Collects what's written for the test to look at
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_typing_is_echoed_except_passwords() {
+        let (mut keyboard, input) = UnixStream::pair().expect("socket pair");
+        let output = SharedOutput::default();
+        let mut descriptor =
+            ConsoleDescriptor::new(input.as_raw_fd(), Box::new(output.clone()), true);
+        let mut buf = [0; 16];
+        let error = descriptor.read(&mut buf).expect_err("nothing typed");
+        assert_eq!(ErrorKind::WouldBlock, error.kind());
+
+        descriptor.write_all(b"Name? ").unwrap();
+        keyboard.write_all(b"Fop\x7fo\n").unwrap();
+        assert_eq!(6, descriptor.read(&mut buf).unwrap());
+        assert_eq!(b"Fop\x7fo\n", &buf[..6]);
+        descriptor.write_all(b"Password: \xFF\xFB\x01").unwrap();
+        keyboard.write_all(b"hunter2\n").unwrap();
+        assert_eq!(8, descriptor.read(&mut buf).unwrap());
+        descriptor.write_all(b"\xFF\xFC\x01\r\n> ").unwrap();
+        keyboard.write_all(b"l").unwrap();
+        assert_eq!(1, descriptor.read(&mut buf).unwrap());
+
+        assert_eq!(
+            &b"Name? Fop\x08 \x08o\r\nPassword: \r\n> l"[..],
+            &output.0.borrow()[..]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::net::IpAddr;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use libc::fcntl;
+use libc::fstat;
+use libc::FD_CLOEXEC;
+use libc::F_GETFD;
+use libc::F_SETFD;
+use libc::S_IFMT;
+use libc::S_IFSOCK;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::telnet::TelnetInput;
+
+/// Set (to the state file's path) only for a process exec'd by a copyover, so a stale state file
+/// can never hand out file descriptors that mean something else by now
+pub const COPYOVER_ENV: &str = "MUD_COMMS_COPYOVER";
+
+/// What a transport needs to recreate one of its descriptors after a copyover
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum SavedDescriptor {
+    /// The connected socket, which the exec leaves open, and how far its telnet has got
+    Telnet {
+        fd: RawFd,
+        hostname: String,
+        /// Missing from state saved before connections were limited by address
+        #[serde(default)]
+        ip: Option<IpAddr>,
+        /// Missing from state saved before telnet was kept across a copyover
+        #[serde(default)]
+        input: TelnetInput,
+    },
+    /// The direct message channel a session was bound to
+    Slack {
+        channel: String,
+        identity: Option<String>,
+    },
+}
+
+/// Every descriptor that survives a copyover, under the ID it had before
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CopyoverState {
+    pub next_id: u64,
+    pub descriptors: Vec<CopyoverEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CopyoverEntry {
+    pub id: u64,
+    /// Set by the game to find its place again (ie the character's name)
+    pub tag: Option<String>,
+    pub descriptor: SavedDescriptor,
+}
+
+impl CopyoverState {
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(&file), self)?;
+        file.sync_all()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+}
+
+/// Clears close-on-exec on `fd` so it stays open in the copyover's new process
+pub fn keep_across_exec(fd: RawFd) -> Result<(), std::io::Error> {
+    set_close_on_exec(fd, false)
+}
+
+/// Checks that `fd` survived the copyover as a socket and sets close-on-exec on it again, so it
+/// doesn't leak into anything else the new process runs
+pub fn reclaim_after_exec(fd: RawFd) -> Result<(), std::io::Error> {
+    unsafe {
+        let mut stat = std::mem::zeroed();
+        if fstat(fd, &mut stat) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if stat.st_mode & S_IFMT != S_IFSOCK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("file descriptor {} isn't a socket", fd),
+            ));
+        }
+    }
+    set_close_on_exec(fd, true)
+}
+
+fn set_close_on_exec(fd: RawFd, close_on_exec: bool) -> Result<(), std::io::Error> {
+    unsafe {
+        let flags = fcntl(fd, F_GETFD);
+        if flags < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let flags = if close_on_exec {
+            flags | FD_CLOEXEC
+        } else {
+            flags & !FD_CLOEXEC
+        };
+        if fcntl(fd, F_SETFD, flags) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::os::unix::io::AsRawFd;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_state_round_trips() {
+        let dir = TempDir::new("copyover").expect("temporary directory");
+        let path = dir.path().join("copyover");
+        let state = CopyoverState {
+            next_id: 4,
+            descriptors: vec![
+                CopyoverEntry {
+                    id: 1,
+                    tag: Some("Foo".to_owned()),
+                    descriptor: SavedDescriptor::Telnet {
+                        fd: 7,
+                        hostname: "localhost".to_owned(),
+                        ip: Some("127.0.0.1".parse().unwrap()),
+                        input: telnet_input(b"look\xFF\xFA\x18"),
+                    },
+                },
+                CopyoverEntry {
+                    id: 3,
+                    tag: None,
+                    descriptor: SavedDescriptor::Slack {
+                        channel: "D0001".to_owned(),
+                        identity: Some("slack:T0001:U0001".to_owned()),
+                    },
+                },
+            ],
+        };
+
+        state.save(&path).expect("state saved");
+        assert_eq!(state, CopyoverState::load(&path).expect("state loaded"));
+    }
+
+    #[test]
+    fn test_state_from_before_telnet_was_saved_loads() {
+        let dir = TempDir::new("copyover").expect("temporary directory");
+        let path = dir.path().join("copyover");
+        std::fs::write(
+            &path,
+            r#"{"next_id":2,"descriptors":[{"id":1,"tag":null,"descriptor":{"transport":"telnet","fd":7,"hostname":"localhost"}}]}"#,
+        )
+        .expect("state written");
+
+        let state = CopyoverState::load(&path).expect("state loaded");
+        assert_eq!(
+            SavedDescriptor::Telnet {
+                fd: 7,
+                hostname: "localhost".to_owned(),
+                ip: None,
+                input: TelnetInput::default(),
+            },
+            state.descriptors[0].descriptor
+        );
+    }
+
+    /// Telnet input partway through `received`
+    fn telnet_input(received: &[u8]) -> TelnetInput {
+        let mut input = TelnetInput::default();
+        input.strip(&mut received.to_vec());
+        input
+    }
+
+    #[test]
+    fn test_only_sockets_are_reclaimed() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        keep_across_exec(listener.as_raw_fd()).expect("close-on-exec cleared");
+        reclaim_after_exec(listener.as_raw_fd()).expect("socket reclaimed");
+
+        let file = File::open("Cargo.toml").expect("a regular file");
+        assert!(reclaim_after_exec(file.as_raw_fd()).is_err());
+    }
+}
@@ -1,12 +1,156 @@
+use std::net::IpAddr;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
 use std::{io::Read, io::Write};
 
+use crate::copyover::SavedDescriptor;
+use crate::keepalive::Keepalive;
+
 pub trait DescriptorManager {
     fn block_until_descriptor(&self) -> Result<(), std::io::Error>;
     fn new_descriptor(
         &self,
     ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Copies game output for `target` (`room:<vnum>` or a chat channel like `gossip`) to any
+    /// transport channels bound to it
+    fn mirror(&self, _target: &str, _text: &str) {}
+
+    /// Removes any transport identity's link to `character`, which has been deleted or whose name
+    /// is being taken by a new character
+    fn unlink_character(&self, _character: &str) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    /// The next line a linked character said in a transport channel bound to a game target
+    fn next_channel_message(&self) -> Option<ChannelMessage> {
+        None
+    }
+
+    /// The next command sent by an admin client (eg `mudctl who`), for the game to run and answer
+    fn next_admin_command(&self) -> Option<AdminCommand> {
+        None
+    }
+
+    /// Keeps the manager's listening socket open through a copyover, returning it for the new
+    /// process to inherit so the port is never closed (transports without one return `None`)
+    fn save_listener(&self) -> Result<Option<RawFd>, std::io::Error> {
+        Ok(None)
+    }
+
+    /// Stops accepting connections, closes any listening socket and ends the manager's
+    /// background threads, waiting for them until `deadline`. Descriptors already handed out
+    /// keep working so the game can say goodbye.
+    fn shutdown(&mut self, _deadline: Instant) {}
+
+    /// Recreates a descriptor that a `Descriptor::save` in the previous process handed over
+    fn restore_descriptor(
+        &self,
+        _saved: SavedDescriptor,
+    ) -> Result<Box<dyn Descriptor>, std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "transport cannot restore descriptors",
+        ))
+    }
+}
+
+/// Something a linked character said in a transport channel, to be spoken in game
+#[derive(Debug, PartialEq)]
+pub struct ChannelMessage {
+    pub target: String,
+    pub character: String,
+    pub text: String,
+}
+
+/// A command from an admin client, answered by whoever runs it
+pub struct AdminCommand {
+    pub command: String,
+    reply: Box<dyn Write + Send>,
+}
+
+impl AdminCommand {
+    pub fn new(command: String, reply: Box<dyn Write + Send>) -> Self {
+        AdminCommand { command, reply }
+    }
+
+    /// Sends the client the command's result, which is all it gets
+    pub fn reply(mut self, text: &str) -> Result<(), std::io::Error> {
+        self.reply.write_all(text.as_bytes())?;
+        self.reply.flush()
+    }
+}
+
+/// Whether an error from `DescriptorManager::new_descriptor` only means there isn't a new
+/// descriptor yet
+pub fn no_new_descriptor(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if let Some(error) = e.downcast_ref::<std::io::Error>() {
+        return error.kind() == std::io::ErrorKind::WouldBlock;
+    }
+    if let Some(error) = e.downcast_ref::<crossbeam_channel::TryRecvError>() {
+        return error.is_empty();
+    }
+    false
+}
+
+/// A descriptor's handle in its `DescriptorRegistry`, which never reuses one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DescriptorId(pub u64);
+
+impl std::fmt::Display for DescriptorId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 pub trait Descriptor: Read + Write {
     fn get_hostname(&self) -> &str;
+
+    /// The client's address, for transports connected over IP
+    fn get_ip(&self) -> Option<IpAddr> {
+        None
+    }
+
+    /// The transport's tag for descriptors of its kind (eg `TELNET` or `SLACK`)
+    fn get_type(&self) -> &'static str;
+
+    /// A verified account identity, for transports that authenticate their users (eg Slack)
+    fn get_identity(&self) -> Option<&str> {
+        None
+    }
+
+    /// The character that this descriptor's identity may log into without a password
+    fn get_linked_character(&self) -> Option<String> {
+        None
+    }
+
+    /// Links (or with `None`, unlinks) this descriptor's identity and `character`
+    fn link_character(&mut self, _character: Option<&str>) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "descriptor has no identity to link",
+        ))
+    }
+
+    /// Turns on TCP keepalive probing, for transports connected over TCP (others have their own
+    /// ways of noticing their clients have gone, eg Slack's idle timeout)
+    fn set_keepalive(&self, _keepalive: &Keepalive) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    /// Tells the client nothing more is coming once what's been written is sent, returning
+    /// whether to read until it hangs up before closing (closing a TCP connection with input
+    /// unread throws away any output it hasn't sent yet)
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        Ok(false)
+    }
+
+    /// Prepares this descriptor to outlive the process through a copyover, returning what its
+    /// manager needs to restore it afterwards
+    fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "transport cannot save descriptors",
+        ))
+    }
 }
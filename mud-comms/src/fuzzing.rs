@@ -0,0 +1,244 @@
+//! Entry points for the fuzz targets in `fuzz/`, each running one case through what takes
+//! untrusted input and panicking if it breaks a promise the game relies on. Crashes the fuzzers
+//! find are kept as tests below, calling the same entry points.
+
+pub use crate::input::OverlongLines;
+
+use crate::descriptor::DescriptorId;
+use crate::input::InputLimits;
+use crate::limits::ConnectionLimits;
+use crate::loopback::LoopbackClient;
+use crate::loopback::LoopbackDescriptorManager;
+use crate::registry::DescriptorRegistry;
+use crate::slack::SlackInput;
+use crate::telnet::TelnetInput;
+
+/// Bytes past the end of each buffer handed to the FFI, which must be left as they were
+const GUARD: usize = 16;
+const GUARD_BYTE: u8 = 0xAA;
+
+/// A registry on the loopback transport with one descriptor open, as C sees them
+struct Connection {
+    registry: *mut DescriptorRegistry,
+    id: u64,
+    client: LoopbackClient,
+}
+
+impl Connection {
+    fn open(hostname: &str, input_limits: InputLimits) -> Self {
+        let (manager, connector) = LoopbackDescriptorManager::new();
+        let mut registry = DescriptorRegistry::new(
+            Box::new(manager),
+            ConnectionLimits::default(),
+            input_limits,
+            None,
+        );
+        let client = connector.connect(hostname).expect("connected");
+        registry
+            .manager()
+            .block_until_descriptor()
+            .expect("a client");
+        let DescriptorId(id) = registry.accept().expect("accepted").expect("a descriptor");
+        Connection {
+            registry: Box::into_raw(Box::new(registry)),
+            id,
+            client,
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // SAFETY: the registry came from `open`, and isn't used after this
+        unsafe { crate::close_descriptor_manager(self.registry) };
+    }
+}
+
+/// Strips `input` as a socket descriptor does, read whole and split at `split_at`, which must
+/// come to the same thing without any NULs
+pub fn telnet_input(input: &[u8], split_at: usize) {
+    let mut whole = input.to_vec();
+    let kept = TelnetInput::default().strip(&mut whole);
+    whole.truncate(kept);
+    assert!(!whole.contains(&0), "NUL left in {:?}", whole);
+
+    let (first, second) = input.split_at(split_at.min(input.len()));
+    let mut split = TelnetInput::default();
+    let mut first = first.to_vec();
+    let kept = split.strip(&mut first);
+    first.truncate(kept);
+    let mut second = second.to_vec();
+    let kept = split.strip(&mut second);
+    first.extend_from_slice(&second[..kept]);
+    assert_eq!(whole, first, "stripped differently when split");
+}
+
+/// Sends `chunks` through the registry's input limits and reads them with `read_from_descriptor`
+/// into buffers of `buffer_sizes` (taken in turn), as the game would
+pub fn read_from_descriptor(
+    chunks: &[Vec<u8>],
+    buffer_sizes: &[usize],
+    max_line_length: usize,
+    overlong_lines: OverlongLines,
+) {
+    let connection = Connection::open(
+        "fuzz.example.com",
+        InputLimits {
+            max_line_length: Some(max_line_length),
+            overlong_lines,
+            ..Default::default()
+        },
+    );
+    let mut sizes = buffer_sizes.iter().copied().cycle();
+    let mut read = Vec::new();
+    for chunk in chunks {
+        connection.client.send(chunk).expect("sent");
+        let size = sizes.next().unwrap_or(512);
+        let mut buffer = vec![GUARD_BYTE; size + GUARD];
+        // SAFETY: the registry is open, and the buffer has room for `size` bytes and the guard
+        let result = unsafe {
+            crate::read_from_descriptor(
+                connection.registry,
+                connection.id,
+                buffer.as_mut_ptr(),
+                size,
+            )
+        };
+        assert_guarded(&buffer, size);
+        if result < 0 {
+            assert_eq!(OverlongLines::Disconnect, overlong_lines, "read failed");
+            return;
+        }
+        let result = result as usize;
+        assert!(result <= size, "read {} bytes into {}", result, size);
+        read.extend_from_slice(&buffer[..result]);
+    }
+    if overlong_lines != OverlongLines::Disconnect {
+        for line in read.split(|&byte| byte == b'\r' || byte == b'\n') {
+            assert!(
+                line.len() <= max_line_length,
+                "line of {} bytes read with a limit of {}",
+                line.len(),
+                max_line_length
+            );
+        }
+    }
+}
+
+/// Fetches a descriptor's strings from `hostname` into buffers of `space_left`, each of which
+/// must either fail or hold a NUL-terminated string without writing past its end
+pub fn descriptor_strings(hostname: &str, space_left: usize) {
+    let connection = Connection::open(hostname, InputLimits::default());
+    let getters: [(
+        &str,
+        unsafe extern "C" fn(*mut DescriptorRegistry, u64, *mut u8, usize) -> isize,
+    ); 4] = [
+        ("hostname", crate::get_descriptor_hostname),
+        ("type", crate::get_descriptor_type),
+        ("identity", crate::get_descriptor_identity),
+        ("linked character", crate::get_descriptor_linked_character),
+    ];
+    for (name, get) in getters {
+        let mut buffer = vec![GUARD_BYTE; space_left + GUARD];
+        // SAFETY: the registry is open, and the buffer has room for `space_left` bytes and the
+        // guard
+        let result = unsafe {
+            get(
+                connection.registry,
+                connection.id,
+                buffer.as_mut_ptr(),
+                space_left,
+            )
+        };
+        assert_guarded(&buffer, space_left);
+        if result == 0 {
+            assert!(
+                buffer[..space_left].contains(&0),
+                "{} not NUL-terminated",
+                name
+            );
+        } else {
+            assert_eq!(-1, result, "{} failed", name);
+        }
+    }
+}
+
+/// Passes `messages` from Slack to the game, reading into buffers of `buffer_sizes` (taken in
+/// turn) after each, then reading what's left, which must all come out, a line per message
+pub fn slack_input(messages: &[String], buffer_sizes: &[usize]) {
+    let mut input = SlackInput::default();
+    let mut sizes = buffer_sizes.iter().copied().cycle();
+    let mut read = Vec::new();
+    for message in messages {
+        input.push(message);
+        let mut buffer = vec![0; sizes.next().unwrap_or(512)];
+        let len = input.read(&mut buffer);
+        read.extend_from_slice(&buffer[..len]);
+    }
+    let mut buffer = [0; 64];
+    loop {
+        match input.read(&mut buffer) {
+            0 => break,
+            len => read.extend_from_slice(&buffer[..len]),
+        }
+    }
+    assert!(input.is_empty());
+
+    let mut expected = Vec::new();
+    for message in messages {
+        expected.extend(message.bytes().filter(|&byte| byte != 0));
+        expected.push(b'\n');
+    }
+    assert_eq!(expected, read);
+}
+
+fn assert_guarded(buffer: &[u8], len: usize) {
+    assert!(
+        buffer[len..].iter().all(|&byte| byte == GUARD_BYTE),
+        "written past {} bytes",
+        len
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostname_into_no_space_is_an_error() {
+        // `get_descriptor_hostname` took one from `space_left` before checking it
+        descriptor_strings("player.example.com", 0);
+    }
+
+    #[test]
+    fn test_strings_are_cut_to_fit() {
+        for space_left in [1, 2, 8, 64] {
+            descriptor_strings("player.example.com", space_left);
+        }
+    }
+
+    #[test]
+    fn test_reads_into_small_buffers() {
+        read_from_descriptor(
+            &[b"look\r\n".to_vec(), b"north\r".to_vec(), b"\n".to_vec()],
+            &[0, 1, 3],
+            4,
+            OverlongLines::Truncate,
+        );
+    }
+
+    #[test]
+    fn test_slack_message_longer_than_the_buffer_is_all_read() {
+        // The rest of a message was only handed over once another came
+        slack_input(&["say hello everyone".to_owned()], &[4]);
+        slack_input(&["look\0north".to_owned(), String::new()], &[0, 3]);
+    }
+
+    #[test]
+    fn test_telnet_commands_split_anywhere_strip_the_same() {
+        let input = b"lo\xFF\xFA\x18\x00xterm\xFF\xF0ok\r\0\xFF\xFF\xFF\xFD\x01";
+        for split_at in 0..=input.len() {
+            telnet_input(input, split_at);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+
+use log::info;
+
+/// Bindings between verified transport identities (eg a Slack user) and CircleMUD characters,
+/// stored one `<identity> <character>` pair per line.
+pub struct IdentityLinks {
+    path: PathBuf,
+    links: HashMap<String, String>,
+}
+
+impl IdentityLinks {
+    /// Loads the links stored at `path`, starting with none if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let mut links = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if let Some((identity, character)) = line.trim().split_once(' ') {
+                        links.insert(identity.to_owned(), character.trim().to_owned());
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        info!("Loaded {} identity links from {:?}", links.len(), path);
+        Ok(IdentityLinks { path, links })
+    }
+
+    pub fn get(&self, identity: &str) -> Option<&str> {
+        self.links.get(identity).map(String::as_str)
+    }
+
+    pub fn link(&mut self, identity: &str, character: &str) -> Result<(), std::io::Error> {
+        if identity.contains(char::is_whitespace) || character.contains(char::is_whitespace) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "identities and character names can't contain whitespace",
+            ));
+        }
+        self.links.insert(identity.to_owned(), character.to_owned());
+        self.save()
+    }
+
+    pub fn unlink(&mut self, identity: &str) -> Result<(), std::io::Error> {
+        if self.links.remove(identity).is_some() {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes every identity's link to `character` (compared as the game compares names, ignoring
+    /// case), so whoever next takes the name doesn't inherit them
+    pub fn unlink_character(&mut self, character: &str) -> Result<(), std::io::Error> {
+        let before = self.links.len();
+        self.links
+            .retain(|_, linked| !linked.eq_ignore_ascii_case(character));
+        if self.links.len() != before {
+            info!(
+                "Unlinked {} identities from {}",
+                before - self.links.len(),
+                character
+            );
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn save(&self) -> Result<(), std::io::Error> {
+        // Write a sibling file and rename it over the old one so a crash can't truncate the links
+        let temporary_path = self.path.with_extension("tmp");
+        let mut file = File::create(&temporary_path)?;
+        for (identity, character) in &self.links {
+            writeln!(file, "{} {}", identity, character)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(temporary_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_unlinking_a_character_removes_all_its_links() {
+        let dir = TempDir::new("identity_links").expect("temporary directory");
+        let path = dir.path().join("links");
+        let mut links = IdentityLinks::load(&path).expect("empty links to load");
+        links.link("slack:T0001:U0001", "Foo").expect("link saved");
+        links.link("slack:T0001:U0002", "Foo").expect("link saved");
+        links.link("slack:T0001:U0003", "Bar").expect("link saved");
+
+        links.unlink_character("foo").expect("unlink saved");
+        assert_eq!(None, links.get("slack:T0001:U0001"));
+        assert_eq!(None, links.get("slack:T0001:U0002"));
+        assert_eq!(Some("Bar"), links.get("slack:T0001:U0003"));
+
+        let links = IdentityLinks::load(&path).expect("links to load");
+        assert_eq!(None, links.get("slack:T0001:U0001"));
+        assert_eq!(Some("Bar"), links.get("slack:T0001:U0003"));
+    }
+}
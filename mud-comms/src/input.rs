@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// CircleMUD's `MAX_INPUT_LENGTH`, past which it truncates lines itself
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 256;
+
+/// Sent when a line over `InputLimits::max_line_length` is dropped
+pub const OVERLONG_MESSAGE: &str = "Line too long, ignored.\r\n";
+
+/// What to do with a line longer than `InputLimits::max_line_length`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlongLines {
+    /// Keep the start of the line, up to the limit
+    Truncate,
+    /// Discard the whole line
+    Drop,
+    /// Close the descriptor
+    Disconnect,
+}
+
+impl FromStr for OverlongLines {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(OverlongLines::Truncate),
+            "drop" => Ok(OverlongLines::Drop),
+            "disconnect" => Ok(OverlongLines::Disconnect),
+            other => Err(format!(
+                "{} is not one of truncate, drop or disconnect",
+                other
+            )),
+        }
+    }
+}
+
+/// A sustained rate and how far a client may burst past it, written as `<per second>` or
+/// `<per second>/<burst>`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (per_second, burst) = match s.split_once('/') {
+            Some((per_second, burst)) => (per_second, Some(burst)),
+            None => (s, None),
+        };
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite() && *n > 0.0)
+                .ok_or_else(|| format!("Invalid rate {}", s))
+        };
+        let per_second = parse(per_second)?;
+        let burst = match burst {
+            Some(burst) => parse(burst)?,
+            // Always enough for one whole line or byte
+            None => per_second.max(1.0),
+        };
+        Ok(Rate { per_second, burst })
+    }
+}
+
+/// Limits on what each descriptor's client may send, applied before CircleMUD reads it
+#[derive(Debug)]
+pub struct InputLimits {
+    /// Longest line, in bytes without its line ending, or `None` for no limit
+    pub max_line_length: Option<usize>,
+    pub overlong_lines: OverlongLines,
+    /// Lines released to the game, with further input held back (and so eventually not read
+    /// from the client at all) until there's room for more
+    pub lines: Option<Rate>,
+    pub bytes: Option<Rate>,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        InputLimits {
+            max_line_length: Some(DEFAULT_MAX_LINE_LENGTH),
+            overlong_lines: OverlongLines::Truncate,
+            lines: None,
+            bytes: None,
+        }
+    }
+}
+
+/// How much a descriptor has sent and how often it ran into its `InputLimits`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputStats {
+    /// Bytes read from the client, including any later discarded
+    pub bytes: u64,
+    /// Lines (started being) released to the game
+    pub lines: u64,
+    /// Times input was held back for going over its rate (until it had all been released)
+    pub throttled: u64,
+    pub truncated: u64,
+    pub dropped: u64,
+}
+
+struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        TokenBucket {
+            rate,
+            tokens: rate.burst,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+        self.last_refill = now;
+    }
+}
+
+/// Whether `bucket` (if there is one) has a token left
+fn has_token(bucket: &Option<TokenBucket>) -> bool {
+    bucket.as_ref().is_none_or(|bucket| bucket.tokens >= 1.0)
+}
+
+fn take_token(bucket: &mut Option<TokenBucket>) {
+    if let Some(bucket) = bucket {
+        bucket.tokens -= 1.0;
+    }
+}
+
+/// Applies `InputLimits` to one descriptor's input, holding what it read until it may be released
+pub struct InputFilter {
+    max_line_length: Option<usize>,
+    overlong_lines: OverlongLines,
+    lines: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    /// Input ready for the game, once the rates allow
+    pending: VecDeque<u8>,
+    /// The line being read, when lines are limited (and so held back until they're complete)
+    line: Vec<u8>,
+    /// Whether the rest of the line being read is over the limit and being discarded
+    overlong: bool,
+    /// Whether all of the line being read is being discarded
+    dropping: bool,
+    /// Whether the last byte fed ended a line with a `\r` (and if so whether that line was
+    /// dropped), as a `\n` after it is part of the same line ending
+    after_cr: Option<bool>,
+    /// Whether the next byte released starts a line, which takes one of the line rate's tokens
+    /// (unless it's the `\n` of a CRLF, after `released_cr`)
+    at_line_start: bool,
+    released_cr: bool,
+    throttled: bool,
+    stats: InputStats,
+}
+
+impl InputFilter {
+    pub fn new(limits: &InputLimits, now: Instant) -> Self {
+        InputFilter {
+            max_line_length: limits.max_line_length,
+            overlong_lines: limits.overlong_lines,
+            lines: limits.lines.map(|rate| TokenBucket::new(rate, now)),
+            bytes: limits.bytes.map(|rate| TokenBucket::new(rate, now)),
+            pending: VecDeque::new(),
+            line: Vec::new(),
+            overlong: false,
+            dropping: false,
+            after_cr: None,
+            at_line_start: true,
+            released_cr: false,
+            throttled: false,
+            stats: InputStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> InputStats {
+        self.stats
+    }
+
+    /// Whether input is already waiting for the game, so there's no need to read more yet
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Takes `input` read from the client, returning how many lines it had that were dropped for
+    /// being too long, or an error if the descriptor should be closed for one
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize, std::io::Error> {
+        self.stats.bytes += input.len() as u64;
+        let Some(max_line_length) = self.max_line_length else {
+            self.pending.extend(input);
+            return Ok(0);
+        };
+
+        let mut dropped = 0;
+        for &byte in input {
+            if byte == b'\n' {
+                if let Some(line_dropped) = self.after_cr.take() {
+                    if !line_dropped {
+                        self.pending.push_back(byte);
+                    }
+                    continue;
+                }
+            }
+            self.after_cr = None;
+
+            if is_line_ending(byte) {
+                if !self.dropping {
+                    self.pending.extend(self.line.drain(..));
+                    self.pending.push_back(byte);
+                }
+                if byte == b'\r' {
+                    self.after_cr = Some(self.dropping);
+                }
+                self.line.clear();
+                self.overlong = false;
+                self.dropping = false;
+                continue;
+            }
+
+            if self.overlong {
+                continue;
+            }
+            if self.line.len() < max_line_length {
+                self.line.push(byte);
+                continue;
+            }
+            self.overlong = true;
+            match self.overlong_lines {
+                OverlongLines::Truncate => self.stats.truncated += 1,
+                OverlongLines::Drop => {
+                    self.stats.dropped += 1;
+                    dropped += 1;
+                    self.dropping = true;
+                    self.line.clear();
+                }
+                OverlongLines::Disconnect => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("line longer than {} bytes", max_line_length),
+                    ))
+                }
+            }
+        }
+        Ok(dropped)
+    }
+
+    /// Copies as much pending input into `buf` as the rates allow, returning how many bytes
+    pub fn release(&mut self, buf: &mut [u8], now: Instant) -> usize {
+        for bucket in [&mut self.lines, &mut self.bytes].into_iter().flatten() {
+            bucket.refill(now);
+        }
+
+        let mut released = 0;
+        while released < buf.len() {
+            let Some(&byte) = self.pending.front() else {
+                break;
+            };
+            let new_line = self.at_line_start && !(byte == b'\n' && self.released_cr);
+            if !has_token(&self.bytes) || (new_line && !has_token(&self.lines)) {
+                if !self.throttled {
+                    self.throttled = true;
+                    self.stats.throttled += 1;
+                }
+                return released;
+            }
+            take_token(&mut self.bytes);
+            if new_line {
+                take_token(&mut self.lines);
+                self.stats.lines += 1;
+            }
+            self.at_line_start = is_line_ending(byte);
+            self.released_cr = byte == b'\r';
+            buf[released] = byte;
+            released += 1;
+            self.pending.pop_front();
+        }
+        self.throttled = false;
+        released
+    }
+}
+
+fn is_line_ending(byte: u8) -> bool {
+    byte == b'\r' || byte == b'\n'
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn filter(limits: InputLimits) -> InputFilter {
+        InputFilter::new(&limits, Instant::now())
+    }
+
+    fn release_all(filter: &mut InputFilter, now: Instant) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let released = filter.release(&mut buf, now);
+        buf[..released].to_vec()
+    }
+
+    #[test]
+    fn test_overlong_lines() {
+        let limits = |overlong_lines| InputLimits {
+            max_line_length: Some(4),
+            overlong_lines,
+            ..Default::default()
+        };
+
+        let mut truncating = filter(limits(OverlongLines::Truncate));
+        assert_eq!(0, truncating.feed(b"look\r\nnorthward\r\nsou").unwrap());
+        assert_eq!(0, truncating.feed(b"th\n").unwrap());
+        assert_eq!(
+            b"look\r\nnort\r\nsout\n".to_vec(),
+            release_all(&mut truncating, Instant::now())
+        );
+
+        let mut dropping = filter(limits(OverlongLines::Drop));
+        assert_eq!(1, dropping.feed(b"look\r\nnorthward\r").unwrap());
+        assert_eq!(0, dropping.feed(b"\nup\r\n").unwrap());
+        assert_eq!(
+            b"look\r\nup\r\n".to_vec(),
+            release_all(&mut dropping, Instant::now())
+        );
+        assert_eq!(
+            InputStats {
+                bytes: 21,
+                lines: 2,
+                dropped: 1,
+                ..Default::default()
+            },
+            dropping.stats()
+        );
+
+        let mut disconnecting = filter(limits(OverlongLines::Disconnect));
+        assert!(disconnecting.feed(b"look\r\n").is_ok());
+        assert!(disconnecting.feed(b"northward").is_err());
+    }
+
+    #[test]
+    fn test_partial_lines_wait_for_their_ending() {
+        let mut filter = filter(InputLimits::default());
+        filter.feed(b"north\r\nso").unwrap();
+        assert_eq!(
+            b"north\r\n".to_vec(),
+            release_all(&mut filter, Instant::now())
+        );
+        filter.feed(b"uth\r\n").unwrap();
+        assert_eq!(
+            b"south\r\n".to_vec(),
+            release_all(&mut filter, Instant::now())
+        );
+    }
+
+    #[test]
+    fn test_lines_are_throttled() {
+        let start = Instant::now();
+        let mut filter = InputFilter::new(
+            &InputLimits {
+                lines: Some("2/3".parse().unwrap()),
+                ..Default::default()
+            },
+            start,
+        );
+        filter.feed(b"1\r\n2\r\n3\r\n4\r\n5\r\n").unwrap();
+
+        // The burst goes straight through, then the rest waits for the rate
+        assert_eq!(b"1\r\n2\r\n3\r\n".to_vec(), release_all(&mut filter, start));
+        assert!(filter.has_pending());
+        assert_eq!(Vec::<u8>::new(), release_all(&mut filter, start));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(b"4\r\n".to_vec(), release_all(&mut filter, later));
+        let much_later = start + Duration::from_secs(10);
+        assert_eq!(b"5\r\n".to_vec(), release_all(&mut filter, much_later));
+        assert_eq!(5, filter.stats().lines);
+        // Held back once, until the backlog cleared
+        assert_eq!(1, filter.stats().throttled);
+    }
+
+    #[test]
+    fn test_rates() {
+        assert_eq!(
+            Rate {
+                per_second: 0.5,
+                burst: 1.0
+            },
+            "0.5".parse().unwrap()
+        );
+        assert_eq!(
+            Rate {
+                per_second: 100.0,
+                burst: 400.0
+            },
+            "100/400".parse().unwrap()
+        );
+        assert!("0".parse::<Rate>().is_err());
+        assert!("10/".parse::<Rate>().is_err());
+        assert!("fast".parse::<Rate>().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::mem::size_of;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use libc::c_int;
+use libc::c_void;
+use libc::setsockopt;
+use libc::IPPROTO_TCP;
+use libc::SOL_SOCKET;
+use libc::SO_KEEPALIVE;
+use libc::TCP_KEEPCNT;
+use libc::TCP_KEEPIDLE;
+use libc::TCP_KEEPINTVL;
+
+/// TCP keepalive probing, so connections whose clients silently vanished (eg a laptop closed
+/// mid-session) are eventually noticed and closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long a connection is quiet before it's probed
+    pub idle: Duration,
+    /// How long between unanswered probes
+    pub interval: Duration,
+    /// How many unanswered probes close the connection
+    pub probes: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            idle: Duration::from_secs(300),
+            interval: Duration::from_secs(30),
+            probes: 4,
+        }
+    }
+}
+
+/// Turns keepalive probing on for the connected socket `fd`
+pub fn set_keepalive(fd: RawFd, keepalive: &Keepalive) -> Result<(), std::io::Error> {
+    let seconds = |duration: Duration| c_int::try_from(duration.as_secs().max(1));
+    let too_long = |_| std::io::Error::from(std::io::ErrorKind::InvalidInput);
+    set_option(fd, SOL_SOCKET, SO_KEEPALIVE, 1)?;
+    set_option(
+        fd,
+        IPPROTO_TCP,
+        TCP_KEEPIDLE,
+        seconds(keepalive.idle).map_err(too_long)?,
+    )?;
+    set_option(
+        fd,
+        IPPROTO_TCP,
+        TCP_KEEPINTVL,
+        seconds(keepalive.interval).map_err(too_long)?,
+    )?;
+    set_option(
+        fd,
+        IPPROTO_TCP,
+        TCP_KEEPCNT,
+        c_int::try_from(keepalive.probes).map_err(too_long)?,
+    )
+}
+
+fn set_option(fd: RawFd, level: c_int, name: c_int, value: c_int) -> Result<(), std::io::Error> {
+    // SAFETY: `value` outlives the call and is the size given
+    let result = unsafe {
+        setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const c_int as *const c_void,
+            size_of::<c_int>() as u32,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::os::unix::io::AsRawFd;
+
+    use libc::getsockopt;
+    use libc::socklen_t;
+
+    use super::*;
+
+    fn get_option(fd: RawFd, level: c_int, name: c_int) -> c_int {
+        let mut value: c_int = 0;
+        let mut len = size_of::<c_int>() as socklen_t;
+        let result = unsafe {
+            getsockopt(
+                fd,
+                level,
+                name,
+                &mut value as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(0, result);
+        value
+    }
+
+    #[test]
+    fn test_keepalive_is_set() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).expect("connected");
+        let fd = stream.as_raw_fd();
+        set_keepalive(
+            fd,
+            &Keepalive {
+                idle: Duration::from_secs(120),
+                interval: Duration::from_secs(15),
+                probes: 3,
+            },
+        )
+        .expect("keepalive set");
+
+        assert_ne!(0, get_option(fd, SOL_SOCKET, SO_KEEPALIVE));
+        assert_eq!(120, get_option(fd, IPPROTO_TCP, TCP_KEEPIDLE));
+        assert_eq!(15, get_option(fd, IPPROTO_TCP, TCP_KEEPINTVL));
+        assert_eq!(3, get_option(fd, IPPROTO_TCP, TCP_KEEPCNT));
+    }
+}
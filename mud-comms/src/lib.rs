@@ -1,41 +1,100 @@
+mod admin_socket;
+mod ban;
+mod cidr;
+mod combined;
+mod console;
+mod copyover;
 mod descriptor;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+mod identity;
+mod input;
+mod keepalive;
+mod limits;
+mod listener;
+mod logging;
+pub mod loopback;
+mod metrics;
+mod monitor;
+mod proxy;
+pub mod recording;
+mod registry;
+mod shutdown;
 mod slack;
+mod slack_channels;
+mod slack_socket_mode;
+#[cfg(test)]
+mod slack_test_support;
 mod socket_libc;
 mod socket_std;
+pub mod telnet;
+#[cfg(test)]
+mod telnet_conformance;
 
 use std::cmp::min;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::io::Read;
-use std::io::Write;
 use std::os::raw::c_char;
 use std::os::raw::c_uchar;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
 
 use log::error;
+use log::info;
 
+use descriptor::DescriptorId;
+use registry::DescriptorRegistry;
+
+/// Sends mud-comms' log to `callback` (ie CircleMUD's `log()`) when `MUD_COMMS_LOG_OUTPUT` is
+/// `circle`. Set it before `new_descriptor_manager` so nothing is logged elsewhere first.
+#[no_mangle]
+pub extern "C" fn set_log_callback(callback: Option<logging::LogCallback>) {
+    logging::set_log_callback(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn set_mudlog_callback(callback: Option<logging::MudlogCallback>) {
+    logging::set_mudlog_callback(callback);
+}
+
+/// Hands the records queued since the last call to the `set_mudlog_callback` callback. Call it
+/// once a pass of the game loop, somewhere it's safe for `mudlog()` to walk the descriptor list.
 #[no_mangle]
-pub extern "C" fn new_descriptor_manager(port: u16) -> *mut Box<dyn descriptor::DescriptorManager> {
-    if init_log().is_err() {
-        eprintln!("Failed to initialize logging");
+pub extern "C" fn deliver_mudlogs() {
+    logging::deliver_mudlog();
+}
+
+#[no_mangle]
+pub extern "C" fn new_descriptor_manager(port: u16) -> *mut DescriptorRegistry {
+    if let Err(e) = init_log() {
+        eprintln!("Failed to initialize logging: {}", e);
         return std::ptr::null_mut();
     };
 
-    // std::net socket server
-    //let result = socket_std::SocketDescriptorManager::new(port);
-    // libc socket server
-    //let result = socket_libc::SocketDescriptorManager::new(port);
-    // slack server
-    let result: std::io::Result<_> = Ok(slack::SlackDescriptorManager::new(
-        std::env::var("SLACK_SIGNING_SECRET")
-            .expect("SLACK_SIGNING_SECRET to be in the environment")
-            .as_str(),
-        slack_morphism::SlackApiTokenValue(
-            std::env::var("SLACK_BOT_USER_OAUTH_TOKEN")
-                .expect("SLACK_BOT_USER_OAUTH_TOKEN to be in the environment"),
-        ),
-    ));
-    match result {
-        Ok(manager) => Box::into_raw(Box::new(Box::new(manager))),
+    let transport = std::env::var("MUD_COMMS_TRANSPORT").unwrap_or("slack".to_owned());
+    info!("Using {} transport", transport);
+    match create_descriptor_manager(transport.as_str(), port).and_then(|manager| {
+        Ok(DescriptorRegistry::new(
+            with_admin_socket(manager)?,
+            connection_limits()?,
+            input_limits()?,
+            keepalive()?,
+        ))
+    }) {
+        Ok(mut registry) => {
+            if let Err(e) = serve_metrics(&mut registry) {
+                error!("Cannot serve metrics: {}", e);
+            }
+            if let Ok(dir) = std::env::var("MUD_COMMS_RECORD_SESSIONS") {
+                if let Err(e) = registry.record_sessions(dir.into()) {
+                    error!("Cannot record sessions: {}", e);
+                }
+            }
+            restore_copyover(&mut registry);
+            Box::into_raw(Box::new(registry))
+        }
         // TODO: return an error?
         Err(e) => {
             error!("Cannot create DescriptorManager: {}", e);
@@ -44,34 +103,281 @@ pub extern "C" fn new_descriptor_manager(port: u16) -> *mut Box<dyn descriptor::
     }
 }
 
+fn create_descriptor_manager(
+    transport: &str,
+    port: u16,
+) -> Result<Box<dyn descriptor::DescriptorManager>, Box<dyn std::error::Error + Send + Sync>> {
+    match transport {
+        // std::net socket server
+        "socket-std" => Ok(Box::new(socket_std::SocketDescriptorManager::new(
+            port,
+            trusted_proxies()?,
+        )?)),
+        // libc socket server
+        "socket-libc" => Ok(Box::new(socket_libc::SocketDescriptorManager::new(
+            port,
+            trusted_proxies()?,
+        )?)),
+        // a single player on the terminal the game was started from, for development
+        "console" => Ok(Box::new(console::ConsoleDescriptorManager::default())),
+        // slack server, receiving events from Slack's HTTP callbacks
+        "slack" => Ok(Box::new(slack::SlackDescriptorManager::new(
+            std::env::var("SLACK_SOCKET_ADDR")
+                .unwrap_or("127.0.0.1:8000".to_owned())
+                .parse()
+                .map_err(|e| format!("Invalid SLACK_SOCKET_ADDR provided: {}", e))?,
+            required_env("SLACK_SIGNING_SECRET")?.as_str(),
+            slack_morphism::SlackApiTokenValue(required_env("SLACK_BOT_USER_OAUTH_TOKEN")?),
+            slack_links()?,
+            slack_channels()?,
+            slack_idle_timeout()?,
+        ))),
+        // slack client, receiving events over a Socket Mode WebSocket
+        "slack-socket-mode" => Ok(Box::new(
+            slack_socket_mode::SlackSocketModeDescriptorManager::new(
+                std::env::var("SLACK_API_URL")
+                    .unwrap_or("https://slack.com/api".to_owned())
+                    .parse()
+                    .map_err(|e| format!("Invalid SLACK_API_URL provided: {}", e))?,
+                slack_morphism::SlackApiTokenValue(required_env("SLACK_APP_TOKEN")?),
+                slack_morphism::SlackApiTokenValue(required_env("SLACK_BOT_USER_OAUTH_TOKEN")?),
+                slack_links()?,
+                slack_channels()?,
+                slack_idle_timeout()?,
+            ),
+        )),
+        other => Err(format!("Unknown MUD_COMMS_TRANSPORT {}", other).into()),
+    }
+}
+
+/// Adds the admin socket at `MUD_COMMS_ADMIN_SOCKET` alongside `manager`, if it's set
+fn with_admin_socket(
+    manager: Box<dyn descriptor::DescriptorManager>,
+) -> Result<Box<dyn descriptor::DescriptorManager>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(path) = std::env::var_os("MUD_COMMS_ADMIN_SOCKET") else {
+        return Ok(manager);
+    };
+    let admin = admin_socket::AdminSocketDescriptorManager::new(path.into())
+        .map_err(|e| format!("Cannot listen on MUD_COMMS_ADMIN_SOCKET: {}", e))?;
+    Ok(Box::new(combined::CombinedDescriptorManager::new(vec![
+        manager,
+        Box::new(admin),
+    ])))
+}
+
+/// Restores the descriptors saved by the copyover that exec'd this process, if one did
+fn restore_copyover(registry: &mut DescriptorRegistry) {
+    let Some(state_file) = std::env::var_os(copyover::COPYOVER_ENV) else {
+        return;
+    };
+    // Anything this process runs isn't part of the copyover
+    std::env::remove_var(copyover::COPYOVER_ENV);
+    match copyover::CopyoverState::load(Path::new(&state_file)) {
+        Ok(state) => registry.restore(state),
+        Err(e) => error!("Cannot restore copyover from {:?}: {}", state_file, e),
+    }
+    if let Err(e) = std::fs::remove_file(&state_file) {
+        error!("Cannot remove copyover state {:?}: {}", state_file, e);
+    }
+}
+
+fn copyover_file() -> Result<std::path::PathBuf, std::io::Error> {
+    // Made absolute since the new process may start from another working directory
+    Ok(std::env::current_dir()?
+        .join(std::env::var("MUD_COMMS_COPYOVER_FILE").unwrap_or("etc/copyover".to_owned())))
+}
+
+fn trusted_proxies() -> Result<proxy::TrustedProxies, Box<dyn std::error::Error + Send + Sync>> {
+    proxy::TrustedProxies::parse(&std::env::var("MUD_COMMS_TRUSTED_PROXIES").unwrap_or_default())
+        .map_err(|e| format!("Invalid MUD_COMMS_TRUSTED_PROXIES provided: {}", e).into())
+}
+
+fn connection_limits() -> Result<limits::ConnectionLimits, Box<dyn std::error::Error + Send + Sync>>
+{
+    Ok(limits::ConnectionLimits {
+        per_ip: optional_limit("MUD_COMMS_MAX_CONNECTIONS_PER_IP")?,
+        accepts_per_second: optional_limit("MUD_COMMS_MAX_ACCEPTS_PER_SECOND")?,
+        unauthenticated: optional_limit("MUD_COMMS_MAX_UNAUTHENTICATED")?,
+        login_timeout: match optional_seconds("MUD_COMMS_LOGIN_TIMEOUT")? {
+            Some(timeout) if timeout.is_zero() => None,
+            Some(timeout) => Some(timeout),
+            None => Some(Duration::from_secs(300)),
+        },
+    })
+}
+
+/// TCP keepalive is on unless `MUD_COMMS_KEEPALIVE_IDLE` is 0
+fn keepalive() -> Result<Option<keepalive::Keepalive>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut keepalive = keepalive::Keepalive::default();
+    if let Some(idle) = optional_seconds("MUD_COMMS_KEEPALIVE_IDLE")? {
+        if idle.is_zero() {
+            return Ok(None);
+        }
+        keepalive.idle = idle;
+    }
+    if let Some(interval) = optional_seconds("MUD_COMMS_KEEPALIVE_INTERVAL")? {
+        keepalive.interval = interval;
+    }
+    if let Ok(probes) = std::env::var("MUD_COMMS_KEEPALIVE_PROBES") {
+        keepalive.probes = probes
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_KEEPALIVE_PROBES provided: {}", e))?;
+    }
+    Ok(Some(keepalive))
+}
+
+/// Serves Prometheus metrics on `MUD_COMMS_METRICS_ADDR` (eg `127.0.0.1:9100`), and the same
+/// plus descriptor monitoring on `MUD_COMMS_MONITOR_ADDR`, if they're set
+fn serve_metrics(
+    registry: &mut DescriptorRegistry,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Ok(addr) = std::env::var("MUD_COMMS_METRICS_ADDR") {
+        let addr = addr
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_METRICS_ADDR provided: {}", e))?;
+        registry.serve_metrics(metrics::MetricsServer::start(addr, None)?);
+    }
+    if let Ok(addr) = std::env::var("MUD_COMMS_MONITOR_ADDR") {
+        let addr = addr
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_MONITOR_ADDR provided: {}", e))?;
+        let monitors = registry.monitors();
+        registry.serve_metrics(metrics::MetricsServer::start(addr, Some(monitors))?);
+    }
+    Ok(())
+}
+
+fn optional_seconds(
+    name: &str,
+) -> Result<Option<Duration>, Box<dyn std::error::Error + Send + Sync>> {
+    match std::env::var(name) {
+        Ok(seconds) => seconds
+            .parse()
+            .map(|seconds| Some(Duration::from_secs(seconds)))
+            .map_err(|e| format!("Invalid {} provided: {}", name, e).into()),
+        Err(_) => Ok(None),
+    }
+}
+
+fn optional_limit(name: &str) -> Result<Option<usize>, Box<dyn std::error::Error + Send + Sync>> {
+    match std::env::var(name) {
+        Ok(limit) => limit
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Invalid {} provided: {}", name, e).into()),
+        Err(_) => Ok(None),
+    }
+}
+
+fn input_limits() -> Result<input::InputLimits, Box<dyn std::error::Error + Send + Sync>> {
+    let mut limits = input::InputLimits::default();
+    if let Ok(length) = std::env::var("MUD_COMMS_MAX_LINE_LENGTH") {
+        limits.max_line_length = Some(
+            length
+                .parse()
+                .map_err(|e| format!("Invalid MUD_COMMS_MAX_LINE_LENGTH provided: {}", e))?,
+        );
+    }
+    if let Ok(overlong_lines) = std::env::var("MUD_COMMS_OVERLONG_LINES") {
+        limits.overlong_lines = overlong_lines
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_OVERLONG_LINES provided: {}", e))?;
+    }
+    if let Ok(rate) = std::env::var("MUD_COMMS_INPUT_LINE_RATE") {
+        limits.lines = Some(
+            rate.parse()
+                .map_err(|e| format!("Invalid MUD_COMMS_INPUT_LINE_RATE provided: {}", e))?,
+        );
+    }
+    if let Ok(rate) = std::env::var("MUD_COMMS_INPUT_BYTE_RATE") {
+        limits.bytes = Some(
+            rate.parse()
+                .map_err(|e| format!("Invalid MUD_COMMS_INPUT_BYTE_RATE provided: {}", e))?,
+        );
+    }
+    Ok(limits)
+}
+
+fn required_env(name: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    std::env::var(name).map_err(|_| format!("{} to be in the environment", name).into())
+}
+
+fn slack_links() -> Result<identity::IdentityLinks, std::io::Error> {
+    identity::IdentityLinks::load(
+        std::env::var("SLACK_LINKS_FILE").unwrap_or("etc/slack_links".to_owned()),
+    )
+}
+
+fn slack_channels() -> Result<slack_channels::ChannelBindings, std::io::Error> {
+    slack_channels::ChannelBindings::load(
+        std::env::var("SLACK_CHANNELS_FILE").unwrap_or("etc/slack_channels".to_owned()),
+    )
+}
+
+fn slack_idle_timeout() -> Result<Duration, Box<dyn std::error::Error + Send + Sync>> {
+    match std::env::var("SLACK_IDLE_TIMEOUT") {
+        Ok(seconds) => seconds
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|e| format!("Invalid SLACK_IDLE_TIMEOUT provided: {}", e).into()),
+        Err(_) => Ok(slack::DEFAULT_IDLE_TIMEOUT),
+    }
+}
+
+/// Stops accepting connections and says `message` to every open descriptor, waiting up to
+/// `timeout_seconds` for it to reach them before they're closed
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `message` must
+/// be a NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn close_descriptor_manager(
-    manager: *mut Box<dyn descriptor::DescriptorManager>,
+pub unsafe extern "C" fn shutdown_descriptor_manager(
+    registry: *mut DescriptorRegistry,
+    message: *const c_char,
+    timeout_seconds: u32,
 ) -> i32 {
-    if manager.is_null() {
+    if registry.is_null() || message.is_null() {
+        error!("Cannot shut down DescriptorManager: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let message = CStr::from_ptr(message).to_string_lossy();
+        (*registry).shutdown(&message, Duration::from_secs(timeout_seconds.into()));
+    }
+    0
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager`, and isn't used again once it's closed.
+#[no_mangle]
+pub unsafe extern "C" fn close_descriptor_manager(registry: *mut DescriptorRegistry) -> i32 {
+    if registry.is_null() {
         error!("Cannot close DescriptorManager: already null");
         return -1;
     }
     // SAFETY: `from_raw` can result in a double-free - the pointer is set to null and after
     // dropping and pointer is checked if it is null before dropping
     unsafe {
-        Box::from_raw(manager);
-        return 0;
+        drop(Box::from_raw(registry));
     }
+    0
 }
 
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
 #[no_mangle]
-pub extern "C" fn block_until_descriptor(
-    mut manager: *mut Box<dyn descriptor::DescriptorManager>,
-) -> i32 {
-    if manager.is_null() {
+pub unsafe extern "C" fn block_until_descriptor(registry: *mut DescriptorRegistry) -> i32 {
+    if registry.is_null() {
         error!("Cannot block for descriptor: DescriptorManager is null");
         return -1;
     }
-    // SAFETY: `from_raw` can result in a double-free - the pointer is set to null and after
-    // dropping and pointer is checked if it is null before dropping
+
     unsafe {
-        match (*manager).block_until_descriptor() {
+        match (*registry).manager().block_until_descriptor() {
             Ok(_) => 0,
             Err(e) => {
                 error!("Cannot block for descriptor: {}", e);
@@ -81,102 +387,270 @@ pub extern "C" fn block_until_descriptor(
     }
 }
 
+/// Returns the new descriptor's ID, or 0 if there isn't one
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
 #[no_mangle]
-pub extern "C" fn new_descriptor(
-    manager: *mut Box<dyn descriptor::DescriptorManager>,
-) -> *mut Box<dyn descriptor::Descriptor> {
-    if manager.is_null() {
+pub unsafe extern "C" fn new_descriptor(registry: *mut DescriptorRegistry) -> u64 {
+    if registry.is_null() {
         error!("Cannot get new descriptor: DescriptorManager is null");
-        return std::ptr::null_mut();
+        return 0;
     }
-
     unsafe {
-        // TODO: Change DescriptorManager::new_descriptor to return
-        // Result<Option<Box<Descriptor>>, Error> and have callers return None descriptors for
-        // non-error cases like TryRecvError::Empty
-        match (*manager).new_descriptor() {
-            Ok(descriptor) => Box::into_raw(Box::new(descriptor)),
-            Err(ref e) => {
-                // Silently return null if they're not actual errors
-                if let Some(ref error) = e.downcast_ref::<std::io::Error>() {
-                    if error.kind() != std::io::ErrorKind::WouldBlock {
-                        return std::ptr::null_mut();
-                    }
-                }
-
-                if let Some(ref error) = e.downcast_ref::<crossbeam_channel::TryRecvError>() {
-                    if error.is_empty() {
-                        return std::ptr::null_mut();
-                    }
-                }
+        match (*registry).accept() {
+            Ok(Some(DescriptorId(id))) => id,
+            Ok(None) => 0,
+            Err(e) => {
                 error!("Cannot create new descriptor: {}", e);
-                std::ptr::null_mut()
+                0
             }
         }
     }
 }
 
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
 #[no_mangle]
-pub extern "C" fn close_descriptor(
-    mut _manager: *mut Box<dyn descriptor::DescriptorManager>,
-    descriptor: *mut Box<dyn descriptor::Descriptor>,
+pub unsafe extern "C" fn close_descriptor(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
 ) -> i32 {
-    if descriptor.is_null() {
-        error!("Cannot close descriptor: Descriptor is null");
+    if registry.is_null() {
+        error!("Cannot close descriptor: DescriptorManager is null");
         return -1;
     }
+
     unsafe {
-        Box::from_raw(descriptor);
-        return 0;
+        match (*registry).close(DescriptorId(descriptor)) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot close descriptor: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Fills `ids` with up to `len` open descriptor IDs, returning how many are open in total
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `ids` must be
+/// writable for `len` IDs.
+#[no_mangle]
+pub unsafe extern "C" fn list_descriptors(
+    registry: *mut DescriptorRegistry,
+    ids: *mut u64,
+    len: usize,
+) -> isize {
+    if registry.is_null() || (ids.is_null() && len > 0) {
+        error!("Cannot list descriptors: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let mut count = 0;
+        for id in (*registry).ids() {
+            if count < len {
+                *ids.add(count) = id.0;
+            }
+            count += 1;
+        }
+        isize::try_from(count).unwrap_or(-1)
     }
 }
 
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `read_point`
+/// must be writable for `space_left` bytes.
 #[no_mangle]
-pub extern "C" fn get_descriptor_hostname(
-    mut manager: *mut Box<dyn descriptor::DescriptorManager>,
-    mut descriptor: *mut Box<dyn descriptor::Descriptor>,
+pub unsafe extern "C" fn get_descriptor_type(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
     read_point: *mut c_uchar,
     space_left: usize,
 ) -> isize {
-    if manager.is_null() || descriptor.is_null() || read_point.is_null() {
+    if registry.is_null() || read_point.is_null() {
+        error!("Cannot get descriptor type: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry)
+            .get(DescriptorId(descriptor))
+            .and_then(|descriptor| write_c_string(descriptor.get_type(), read_point, space_left))
+        {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot get descriptor type: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `read_point`
+/// must be writable for `space_left` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_hostname(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    read_point: *mut c_uchar,
+    space_left: usize,
+) -> isize {
+    if registry.is_null() || read_point.is_null() {
         error!("Cannot get descriptor hostname: argument is null");
         return -1;
     }
 
     unsafe {
-        let hostname_bytes = (*descriptor).get_hostname().as_bytes();
-        match CString::new(&hostname_bytes[..min(hostname_bytes.len(), space_left - 1)]) {
-            Ok(c_hostname) => {
-                let buffer = std::slice::from_raw_parts_mut(
+        match (*registry)
+            .get(DescriptorId(descriptor))
+            .and_then(|descriptor| {
+                write_c_string(descriptor.get_hostname(), read_point, space_left)
+            }) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot get descriptor hostname: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `read_point`
+/// must be writable for `space_left` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_identity(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    read_point: *mut c_uchar,
+    space_left: usize,
+) -> isize {
+    if registry.is_null() || read_point.is_null() {
+        error!("Cannot get descriptor identity: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry)
+            .get(DescriptorId(descriptor))
+            .and_then(|descriptor| {
+                write_c_string(
+                    descriptor.get_identity().unwrap_or(""),
                     read_point,
-                    min(space_left, c_hostname.as_bytes_with_nul().len()),
-                );
-                buffer.copy_from_slice(c_hostname.as_bytes_with_nul());
-                0
+                    space_left,
+                )
+            }) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot get descriptor identity: {}", e);
+                -1
             }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `read_point`
+/// must be writable for `space_left` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_linked_character(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    read_point: *mut c_uchar,
+    space_left: usize,
+) -> isize {
+    if registry.is_null() || read_point.is_null() {
+        error!("Cannot get descriptor linked character: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry)
+            .get(DescriptorId(descriptor))
+            .and_then(|descriptor| {
+                let character = descriptor.get_linked_character().unwrap_or_default();
+                write_c_string(&character, read_point, space_left)
+            }) {
+            Ok(()) => 0,
             Err(e) => {
-                error!("Cannot get descriptor hostname: {}", e);
+                error!("Cannot get descriptor linked character: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `character`
+/// must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn link_descriptor_character(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    character: *const c_char,
+) -> i32 {
+    if registry.is_null() {
+        error!("Cannot link descriptor character: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        // A null character unlinks the descriptor's identity
+        let character = if character.is_null() {
+            None
+        } else {
+            match CStr::from_ptr(character).to_str() {
+                Ok(character) => Some(character),
+                Err(e) => {
+                    error!("Cannot link descriptor character: {}", e);
+                    return -1;
+                }
+            }
+        };
+        match (*registry)
+            .get_mut(DescriptorId(descriptor))
+            .and_then(|descriptor| descriptor.link_character(character))
+        {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot link descriptor character: {}", e);
                 -1
             }
         }
     }
 }
 
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `read_point`
+/// must be writable for `space_left` bytes.
 #[no_mangle]
-pub extern "C" fn read_from_descriptor(
-    mut manager: *mut Box<dyn descriptor::DescriptorManager>,
-    mut descriptor: *mut Box<dyn descriptor::Descriptor>,
+pub unsafe extern "C" fn read_from_descriptor(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
     read_point: *mut c_uchar,
     space_left: usize,
 ) -> isize {
-    if manager.is_null() || descriptor.is_null() || read_point.is_null() {
+    if registry.is_null() || read_point.is_null() {
         error!("Cannot read from descriptor: argument is null");
         return -1;
     }
 
     unsafe {
         let buffer = std::slice::from_raw_parts_mut(read_point, space_left);
-        match (*descriptor).read(buffer) {
+        match (*registry).read(DescriptorId(descriptor), buffer) {
             Ok(bytes) => isize::try_from(bytes).unwrap_or(-1),
             Err(ref e)
                 if e.kind() == std::io::ErrorKind::WouldBlock
@@ -184,6 +658,25 @@ pub extern "C" fn read_from_descriptor(
             {
                 0
             }
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                info!("Descriptor {} hung up", descriptor);
+                -1
+            }
+            // Login timeouts, idle sessions, resets and overlong lines are clients going away,
+            // not faults worth telling immortals about
+            Err(ref e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::BrokenPipe
+                        | std::io::ErrorKind::InvalidData
+                ) =>
+            {
+                info!("Descriptor {} disconnected: {}", descriptor, e);
+                -1
+            }
             Err(e) => {
                 error!("Cannot read from descriptor: {}", e);
                 -1
@@ -192,18 +685,23 @@ pub extern "C" fn read_from_descriptor(
     }
 }
 
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `content` must
+/// be a NUL-terminated string.
 #[no_mangle]
-pub extern "C" fn write_to_descriptor(
-    manager: *mut Box<dyn descriptor::DescriptorManager>,
-    descriptor: *mut Box<dyn descriptor::Descriptor>,
+pub unsafe extern "C" fn write_to_descriptor(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
     content: *const c_char,
 ) -> isize {
-    if manager.is_null() || descriptor.is_null() || content.is_null() {
+    if registry.is_null() || content.is_null() {
         error!("Cannot write to descriptor: argument is null");
         return -1;
     }
+
     unsafe {
-        match (*descriptor).write(CStr::from_ptr(content).to_bytes()) {
+        match (*registry).write(DescriptorId(descriptor), CStr::from_ptr(content).to_bytes()) {
             Ok(written) => isize::try_from(written).unwrap_or(-1),
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
             Err(e) => {
@@ -214,36 +712,653 @@ pub extern "C" fn write_to_descriptor(
     }
 }
 
-fn init_log() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    use fern::colors::{Color, ColoredLevelConfig};
-
-    let colors_level = ColoredLevelConfig::new()
-        .info(Color::Green)
-        .warn(Color::Magenta);
-
-    fern::Dispatch::new()
-        // Perform allocation-free log formatting
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}{}\x1B[0m",
-                chrono::Local::now().format("%b %e %H:%M:%S :: "),
-                record.target(),
-                colors_level.color(record.level()),
-                format_args!(
-                    "\x1B[{}m",
-                    colors_level.get_color(&record.level()).to_fg_str()
-                ),
-                message
-            ))
-        })
-        // Add blanket level filter -
-        .level(log::LevelFilter::Info)
-        // - and per-module overrides
-        .level_for("hyper", log::LevelFilter::Info)
-        // Output to stdout, files, and other Dispatch configurations
-        .chain(std::io::stdout())
-        // Apply globally
-        .apply()?;
+/// Writes a summary of every transport's connections and traffic, for people to read
+///
+/// # Safety
+///
+/// `read_point` must be writable for `space_left` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn get_transport_stats(read_point: *mut c_uchar, space_left: usize) -> i32 {
+    if read_point.is_null() {
+        error!("Cannot get transport stats: buffer is null");
+        return -1;
+    }
+
+    unsafe {
+        match write_c_string(&metrics::metrics().summary(), read_point, space_left) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot get transport stats: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Fills `stats` with how much a descriptor has sent, and how often it went over its input limits
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `stats` must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_input_stats(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    stats: *mut input::InputStats,
+) -> i32 {
+    if registry.is_null() || stats.is_null() {
+        error!("Cannot get descriptor input stats: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry).input_stats(DescriptorId(descriptor)) {
+            Ok(input_stats) => {
+                *stats = input_stats;
+                0
+            }
+            Err(e) => {
+                error!("Cannot get descriptor input stats: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Seconds since a descriptor last sent any input, or -1 if it can't be found
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_idle_seconds(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+) -> i64 {
+    if registry.is_null() {
+        error!("Cannot get descriptor idle time: registry is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry).idle_time(DescriptorId(descriptor)) {
+            Ok(idle) => idle.as_secs() as i64,
+            Err(e) => {
+                error!("Cannot get descriptor idle time: {}", e);
+                -1
+            }
+        }
+    }
+}
 
+/// Tags (or with a null `tag`, untags) a descriptor for finding its place again after a copyover
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `tag` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tag_descriptor(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+    tag: *const c_char,
+) -> i32 {
+    if registry.is_null() {
+        error!("Cannot tag descriptor: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let tag = if tag.is_null() {
+            None
+        } else {
+            match CStr::from_ptr(tag).to_str() {
+                Ok(tag) => Some(tag.to_owned()),
+                Err(e) => {
+                    error!("Cannot tag descriptor: {}", e);
+                    return -1;
+                }
+            }
+        };
+        match (*registry).tag(DescriptorId(descriptor), tag) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot tag descriptor: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Marks a descriptor as logged in, exempting it from the limit on connections still logging in
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
+#[no_mangle]
+pub unsafe extern "C" fn mark_descriptor_authenticated(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+) -> i32 {
+    if registry.is_null() {
+        error!("Cannot mark descriptor authenticated: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry).mark_authenticated(DescriptorId(descriptor)) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot mark descriptor authenticated: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Replaces the bans with those stored at `path` (ie CircleMUD's `lib/etc/badsites`), which any
+/// later changes are saved to
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `path` must be
+/// a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn load_bans(registry: *mut DescriptorRegistry, path: *const c_char) -> i32 {
+    if registry.is_null() || path.is_null() {
+        error!("Cannot load bans: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let path = Path::new(std::ffi::OsStr::from_bytes(CStr::from_ptr(path).to_bytes()));
+        match ban::BanList::load(path) {
+            Ok(bans) => {
+                *(*registry).bans_mut() = bans;
+                0
+            }
+            Err(e) => {
+                error!("Cannot load bans from {:?}: {}", path, e);
+                -1
+            }
+        }
+    }
+}
+
+/// The strictest ban (as a `BAN_*` constant) on the site a descriptor is connected from
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
+#[no_mangle]
+pub unsafe extern "C" fn get_descriptor_ban(
+    registry: *mut DescriptorRegistry,
+    descriptor: u64,
+) -> i32 {
+    if registry.is_null() {
+        error!("Cannot get descriptor ban: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (*registry).ban_on(DescriptorId(descriptor)) {
+            Ok(kind) => kind as i32,
+            Err(e) => {
+                error!("Cannot get descriptor ban: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Bans `site` (with a `BAN_*` constant) on behalf of `name`, for `duration` (eg `7d`) or with a
+/// null `duration`, for good. If it can't be, `error` is filled with why to tell `name`.
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `site`, `duration`
+/// and `name` must be NUL-terminated strings, and `error` must be writable for `error_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ban_site(
+    registry: *mut DescriptorRegistry,
+    ban_type: i32,
+    site: *const c_char,
+    duration: *const c_char,
+    name: *const c_char,
+    error: *mut c_uchar,
+    error_len: usize,
+) -> i32 {
+    if registry.is_null() || site.is_null() || name.is_null() || error.is_null() {
+        error!("Cannot ban site: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let now = ban::now();
+        let ban = (|| -> Result<ban::Ban, Box<dyn std::error::Error + Send + Sync>> {
+            let expires = if duration.is_null() {
+                None
+            } else {
+                Some(ban::expiry(now, CStr::from_ptr(duration).to_str()?)?)
+            };
+            Ok(ban::Ban {
+                kind: ban::BanType::try_from(ban_type)?,
+                site: CStr::from_ptr(site).to_str()?.parse()?,
+                date: now,
+                name: CStr::from_ptr(name).to_str()?.to_owned(),
+                expires,
+            })
+        })();
+        let result = ban.and_then(|ban| Ok((*registry).bans_mut().add(ban, now)?));
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                if let Err(e) = write_c_string(&e.to_string(), error, error_len) {
+                    error!("Cannot ban site: {}", e);
+                }
+                -1
+            }
+        }
+    }
+}
+
+/// Removes the ban on `site`, returning the type (a `BAN_*` constant) it had, or 0 if it had none
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `site` must be
+/// a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unban_site(registry: *mut DescriptorRegistry, site: *const c_char) -> i32 {
+    if registry.is_null() || site.is_null() {
+        error!("Cannot unban site: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let site = match CStr::from_ptr(site).to_str() {
+            Ok(site) => site,
+            Err(e) => {
+                error!("Cannot unban site: {}", e);
+                return -1;
+            }
+        };
+        match (*registry).bans_mut().remove(site, ban::now()) {
+            Ok(Some(ban)) => ban.kind as i32,
+            Ok(None) => 0,
+            Err(e) => {
+                error!("Cannot unban site: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Fills in the `index`th ban in force, newest first, returning its type (a `BAN_*` constant), or
+/// 0 if there are no more. `expires` is set to 0 for bans that never do.
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `site` must be
+/// writable for `site_len` bytes, `name` must be writable for `name_len` bytes, and `date` and
+/// `expires` must be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn get_ban(
+    registry: *mut DescriptorRegistry,
+    index: usize,
+    site: *mut c_uchar,
+    site_len: usize,
+    name: *mut c_uchar,
+    name_len: usize,
+    date: *mut i64,
+    expires: *mut i64,
+) -> i32 {
+    if registry.is_null() || site.is_null() || name.is_null() || date.is_null() || expires.is_null()
+    {
+        error!("Cannot get ban: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let Some(ban) = (*registry).bans().active(ban::now()).nth(index) else {
+            return 0;
+        };
+        match write_c_string(&ban.site.to_string(), site, site_len)
+            .and_then(|_| write_c_string(&ban.name, name, name_len))
+        {
+            Ok(()) => {
+                *date = ban.date;
+                *expires = ban.expires.unwrap_or(0);
+                ban.kind as i32
+            }
+            Err(e) => {
+                error!("Cannot get ban: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Forgets every ban until they're next loaded, leaving the ban file as it is
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed.
+#[no_mangle]
+pub unsafe extern "C" fn clear_bans(registry: *mut DescriptorRegistry) -> i32 {
+    if registry.is_null() {
+        error!("Cannot clear bans: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        (*registry).bans_mut().clear();
+    }
+    0
+}
+
+/// Saves the open descriptors and replaces this process by running the null terminated `argv`
+/// from `dir`, which restores them. Only returns (with -1) if the copyover failed.
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `dir` must be a
+/// NUL-terminated string, and `argv` must be a null-terminated array of NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn exec_copyover(
+    registry: *mut DescriptorRegistry,
+    dir: *const c_char,
+    argv: *const *const c_char,
+) -> i32 {
+    if registry.is_null() || dir.is_null() || argv.is_null() || unsafe { (*argv).is_null() } {
+        error!("Cannot copyover: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let dir = Path::new(std::ffi::OsStr::from_bytes(CStr::from_ptr(dir).to_bytes()));
+        let mut args = Vec::new();
+        let mut arg = argv;
+        while !(*arg).is_null() {
+            args.push(std::ffi::OsStr::from_bytes(CStr::from_ptr(*arg).to_bytes()));
+            arg = arg.add(1);
+        }
+        // A program named without a path is looked up in PATH, just as it was to start the game
+        let program = if args[0].as_bytes().contains(&b'/') {
+            dir.join(args[0])
+        } else {
+            args[0].into()
+        };
+        let state_file = match copyover_file() {
+            Ok(state_file) => state_file,
+            Err(e) => {
+                error!("Cannot copyover: {}", e);
+                return -1;
+            }
+        };
+        let mut command = Command::new(program);
+        command.args(&args[1..]).current_dir(dir);
+        let e = (*registry).exec_copyover(&state_file, &mut command);
+        error!("Cannot copyover: {}", e);
+        -1
+    }
+}
+
+/// Gets the next descriptor restored by a copyover, and its tag, returning 1 if there was one
+/// and 0 once they have all been reattached
+///
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `tag` must be
+/// writable for `tag_len` bytes, and `descriptor` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn next_restored_descriptor(
+    registry: *mut DescriptorRegistry,
+    descriptor: *mut u64,
+    tag: *mut c_uchar,
+    tag_len: usize,
+) -> i32 {
+    if registry.is_null() || descriptor.is_null() || tag.is_null() {
+        error!("Cannot get next restored descriptor: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let Some(id) = (*registry).next_restored() else {
+            return 0;
+        };
+        *descriptor = id.0;
+        match write_c_string((*registry).get_tag(id).unwrap_or(""), tag, tag_len) {
+            Ok(()) => 1,
+            Err(e) => {
+                error!("Cannot get next restored descriptor: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `target` and
+/// `text` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn mirror_to_channels(
+    registry: *mut DescriptorRegistry,
+    target: *const c_char,
+    text: *const c_char,
+) -> i32 {
+    if registry.is_null() || target.is_null() || text.is_null() {
+        error!("Cannot mirror to channels: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        match (
+            CStr::from_ptr(target).to_str(),
+            CStr::from_ptr(text).to_str(),
+        ) {
+            (Ok(target), Ok(text)) => {
+                (*registry).manager().mirror(target, text);
+                0
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Cannot mirror to channels: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `character`
+/// must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unlink_character(
+    registry: *mut DescriptorRegistry,
+    character: *const c_char,
+) -> i32 {
+    if registry.is_null() || character.is_null() {
+        error!("Cannot unlink character: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let character = match CStr::from_ptr(character).to_str() {
+            Ok(character) => character,
+            Err(e) => {
+                error!("Cannot unlink character: {}", e);
+                return -1;
+            }
+        };
+        match (*registry).manager().unlink_character(character) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot unlink character: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `target` must be
+/// writable for `target_len` bytes, `character` must be writable for `character_len` bytes, and
+/// `text` must be writable for `text_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn next_channel_message(
+    registry: *mut DescriptorRegistry,
+    target: *mut c_uchar,
+    target_len: usize,
+    character: *mut c_uchar,
+    character_len: usize,
+    text: *mut c_uchar,
+    text_len: usize,
+) -> i32 {
+    if registry.is_null() || target.is_null() || character.is_null() || text.is_null() {
+        error!("Cannot get next channel message: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let Some(message) = (*registry).manager().next_channel_message() else {
+            return 0;
+        };
+        match write_c_string(&message.target, target, target_len)
+            .and_then(|_| write_c_string(&message.character, character, character_len))
+            .and_then(|_| write_c_string(&message.text, text, text_len))
+        {
+            Ok(()) => 1,
+            Err(e) => {
+                error!("Cannot get next channel message: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, `command` must be
+/// writable for `command_len` bytes, and `id` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn next_admin_command(
+    registry: *mut DescriptorRegistry,
+    id: *mut u64,
+    command: *mut c_uchar,
+    command_len: usize,
+) -> i32 {
+    if registry.is_null() || id.is_null() || command.is_null() {
+        error!("Cannot get next admin command: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let Some((next_id, text)) = (*registry).next_admin_command() else {
+            return 0;
+        };
+        *id = next_id;
+        match write_c_string(&text, command, command_len) {
+            Ok(()) => 1,
+            Err(e) => {
+                error!("Cannot get next admin command: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// `registry` must come from `new_descriptor_manager` and not have been closed, and `reply` must be
+/// a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn reply_admin_command(
+    registry: *mut DescriptorRegistry,
+    id: u64,
+    reply: *const c_char,
+) -> i32 {
+    if registry.is_null() || reply.is_null() {
+        error!("Cannot reply to admin command: argument is null");
+        return -1;
+    }
+
+    unsafe {
+        let reply = CStr::from_ptr(reply).to_string_lossy();
+        match (*registry).reply_admin_command(id, &reply) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Cannot reply to admin command: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Copies `value` into the C buffer at `read_point` as a null terminated string, truncating it to
+/// fit in `space_left` bytes
+///
+/// # Safety
+///
+/// `read_point` must be valid for writes of `space_left` bytes
+unsafe fn write_c_string(
+    value: &str,
+    read_point: *mut c_uchar,
+    space_left: usize,
+) -> Result<(), std::io::Error> {
+    if space_left == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no space for the null terminator",
+        ));
+    }
+    let bytes = value.as_bytes();
+    let c_value = CString::new(&bytes[..min(bytes.len(), space_left - 1)])?;
+    let buffer = std::slice::from_raw_parts_mut(read_point, c_value.as_bytes_with_nul().len());
+    buffer.copy_from_slice(c_value.as_bytes_with_nul());
     Ok(())
 }
+
+/// Sets up logging as configured by `MUD_COMMS_LOG` (levels, eg `info,hyper=warn`),
+/// `MUD_COMMS_LOG_FORMAT` (`plain` or `json`) and `MUD_COMMS_LOG_OUTPUT` (`stdout`, `circle`
+/// for CircleMUD's own log, or a file path, rotated at `MUD_COMMS_LOG_MAX_SIZE` bytes keeping
+/// `MUD_COMMS_LOG_KEEP` old files, defaulting to `circle` for the console transport)
+fn init_log() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = logging::LogConfig::default();
+    if let Ok(levels) = std::env::var("MUD_COMMS_LOG") {
+        config.levels = levels
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_LOG provided: {}", e))?;
+    }
+    if let Ok(format) = std::env::var("MUD_COMMS_LOG_FORMAT") {
+        config.format = format
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_LOG_FORMAT provided: {}", e))?;
+    }
+    let output = std::env::var("MUD_COMMS_LOG_OUTPUT").ok().or_else(|| {
+        // The console transport's player is on stdout
+        (std::env::var("MUD_COMMS_TRANSPORT").as_deref() == Ok("console"))
+            .then(|| "circle".to_owned())
+    });
+    if let Some(output) = output {
+        config.output = match output.as_str() {
+            "stdout" => logging::LogOutput::Stdout,
+            "circle" => logging::LogOutput::Circle,
+            path => logging::LogOutput::File {
+                path: path.into(),
+                max_size: optional_limit("MUD_COMMS_LOG_MAX_SIZE")?.unwrap_or(10 << 20) as u64,
+                keep: optional_limit("MUD_COMMS_LOG_KEEP")?.unwrap_or(5),
+            },
+        };
+    }
+    if let Ok(level) = std::env::var("MUD_COMMS_MUDLOG") {
+        config.mudlog = level
+            .parse()
+            .map_err(|e| format!("Invalid MUD_COMMS_MUDLOG provided: {}", e))?;
+    }
+    logging::init(config)
+}
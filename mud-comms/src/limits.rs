@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Sent to connections refused by `ConnectionLimits` before they're closed
+pub const REFUSED_MESSAGE: &str =
+    "Sorry, too many connections from your site right now... please try again later!\r\n";
+
+/// Limits on new connections, checked before the game ever sees them. `None` is unlimited.
+#[derive(Debug, Default)]
+pub struct ConnectionLimits {
+    /// Connections open at once from one address
+    pub per_ip: Option<usize>,
+    /// New connections from one address in any one second, counting refused ones so a flood
+    /// stays refused until it stops
+    pub accepts_per_second: Option<usize>,
+    /// Connections open at once that haven't logged in yet, from anywhere
+    pub unauthenticated: Option<usize>,
+    /// How long a connection may take to log in before it's closed
+    pub login_timeout: Option<Duration>,
+}
+
+/// Applies `ConnectionLimits`, remembering each address's recent connection attempts
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    recent_accepts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        ConnectionLimiter {
+            limits,
+            recent_accepts: HashMap::new(),
+        }
+    }
+
+    pub fn login_timeout(&self) -> Option<Duration> {
+        self.limits.login_timeout
+    }
+
+    /// Checks a new connection from `ip` (if its transport has addresses), given how many are
+    /// already open from there and how many open ones haven't logged in, returning why it's
+    /// refused if it is
+    pub fn admit(
+        &mut self,
+        ip: Option<IpAddr>,
+        open_from_ip: usize,
+        unauthenticated: usize,
+        now: Instant,
+    ) -> Result<(), String> {
+        if let Some(ip) = ip {
+            let recent = self.record_accept(ip, now);
+            if let Some(limit) = self.limits.accepts_per_second {
+                if recent > limit {
+                    return Err(format!("more than {} connections a second", limit));
+                }
+            }
+            if let Some(limit) = self.limits.per_ip {
+                if open_from_ip >= limit {
+                    return Err(format!("already {} connections open", open_from_ip));
+                }
+            }
+        }
+        if let Some(limit) = self.limits.unauthenticated {
+            if unauthenticated >= limit {
+                return Err(format!(
+                    "already {} connections logging in",
+                    unauthenticated
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts an attempt from `ip`, returning how many it made in the last second
+    fn record_accept(&mut self, ip: IpAddr, now: Instant) -> usize {
+        let window = Duration::from_secs(1);
+        self.recent_accepts.retain(|_, accepts| {
+            while accepts
+                .front()
+                .is_some_and(|accept| now.duration_since(*accept) >= window)
+            {
+                accepts.pop_front();
+            }
+            !accepts.is_empty()
+        });
+        let accepts = self.recent_accepts.entry(ip).or_default();
+        accepts.push_back(now);
+        accepts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_accept_rate_is_per_address_and_second() {
+        let mut limiter = ConnectionLimiter::new(ConnectionLimits {
+            accepts_per_second: Some(2),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 0, start).is_ok());
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 0, start).is_ok());
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 0, start).is_err());
+        assert!(limiter.admit(ip("192.0.2.2"), 0, 0, start).is_ok());
+
+        // Refused attempts still count against the flood
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 0, later).is_err());
+        let after_flood = start + Duration::from_millis(1600);
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 0, after_flood).is_ok());
+    }
+
+    #[test]
+    fn test_open_connection_limits() {
+        let mut limiter = ConnectionLimiter::new(ConnectionLimits {
+            per_ip: Some(3),
+            unauthenticated: Some(10),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.admit(ip("192.0.2.1"), 2, 0, now).is_ok());
+        assert!(limiter.admit(ip("192.0.2.1"), 3, 0, now).is_err());
+        assert!(limiter.admit(ip("192.0.2.1"), 0, 10, now).is_err());
+        // Transports without addresses (eg Slack) only have the overall limit
+        assert!(limiter.admit(None, 3, 9, now).is_ok());
+        assert!(limiter.admit(None, 0, 10, now).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::os::unix::io::RawFd;
+
+use libc::getsockopt;
+use libc::socklen_t;
+use libc::SOL_SOCKET;
+use libc::SO_ACCEPTCONN;
+use log::*;
+
+use crate::copyover::reclaim_after_exec;
+
+/// Names a listening socket this process was started with, for whatever started it (eg
+/// `autorun` or a copyover) to keep the port open across restarts
+pub const LISTEN_FD_ENV: &str = "MUD_COMMS_LISTEN_FD";
+
+/// The first file descriptor passed by systemd's socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The listening socket this process inherited, if it was started with one: either named by
+/// `MUD_COMMS_LISTEN_FD` or passed by systemd (`LISTEN_PID` and `LISTEN_FDS`). The variables are
+/// cleared so that nothing this process starts takes the socket for its own.
+pub fn inherited_listener() -> Result<Option<RawFd>, std::io::Error> {
+    let listen_fd = take_env(LISTEN_FD_ENV);
+    let systemd_fd = systemd_listener()?;
+    let fd = match (listen_fd, systemd_fd) {
+        (Some(fd), _) => fd.parse().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid {} provided: {}", LISTEN_FD_ENV, e),
+            )
+        })?,
+        (None, Some(fd)) => fd,
+        (None, None) => return Ok(None),
+    };
+    reclaim_after_exec(fd)?;
+    if !is_listening(fd)? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("inherited socket {} isn't listening", fd),
+        ));
+    }
+    info!("Listening on inherited socket {}", fd);
+    Ok(Some(fd))
+}
+
+/// The first socket passed by systemd, if it passed any to this process (rather than to a
+/// parent that left them in the environment)
+fn systemd_listener() -> Result<Option<RawFd>, std::io::Error> {
+    let pid = take_env("LISTEN_PID");
+    let fds = take_env("LISTEN_FDS");
+    take_env("LISTEN_FDNAMES");
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(None);
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+    match fds.parse::<RawFd>() {
+        Ok(0) => Ok(None),
+        Ok(1) => Ok(Some(SD_LISTEN_FDS_START)),
+        Ok(count) => {
+            warn!("Using the first of {} sockets passed by systemd", count);
+            Ok(Some(SD_LISTEN_FDS_START))
+        }
+        Err(e) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid LISTEN_FDS provided: {}", e),
+        )),
+    }
+}
+
+fn take_env(name: &str) -> Option<String> {
+    let value = std::env::var(name).ok();
+    std::env::remove_var(name);
+    value
+}
+
+fn is_listening(fd: RawFd) -> Result<bool, std::io::Error> {
+    let mut listening: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as socklen_t;
+    unsafe {
+        if getsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_ACCEPTCONN,
+            &mut listening as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        ) < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(listening != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    // The environment is shared by every test thread, so it's only changed in this one test
+    #[test]
+    fn test_inherited_listener() {
+        assert_eq!(None, inherited_listener().expect("nothing inherited"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        std::env::set_var(LISTEN_FD_ENV, listener.as_raw_fd().to_string());
+        assert_eq!(
+            Some(listener.as_raw_fd()),
+            inherited_listener().expect("listener inherited")
+        );
+        assert!(std::env::var(LISTEN_FD_ENV).is_err());
+
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).expect("connected");
+        std::env::set_var(LISTEN_FD_ENV, stream.as_raw_fd().to_string());
+        assert!(inherited_listener().is_err());
+
+        // Sockets systemd passed to another process aren't ours to take
+        std::env::set_var("LISTEN_PID", (std::process::id() + 1).to_string());
+        std::env::set_var("LISTEN_FDS", "1");
+        assert_eq!(None, inherited_listener().expect("nothing inherited"));
+        assert!(std::env::var("LISTEN_FDS").is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use fern::colors::Color;
+use fern::colors::ColoredLevelConfig;
+use log::Level;
+use log::LevelFilter;
+
+/// Called with each log line when logging to `LogOutput::Circle`, ie CircleMUD's `log()`
+pub type LogCallback = extern "C" fn(*const c_char);
+
+/// The callback registered by `set_log_callback`, if any
+static LOG_CALLBACK: Mutex<Option<LogCallback>> = Mutex::new(None);
+
+pub fn set_log_callback(callback: Option<LogCallback>) {
+    *LOG_CALLBACK
+        .lock()
+        .expect("Unable to get lock on log callback") = callback;
+}
+
+/// Called with the `mudlog()` type (ie `BRF`, `NRM` or `CMP`) and text of each record at or
+/// above `LogConfig::mudlog`, so immortals watching the syslog see them
+pub type MudlogCallback = extern "C" fn(c_int, *const c_char);
+
+/// `mudlog()`'s types, from `utils.h`
+const BRF: c_int = 1;
+const NRM: c_int = 2;
+const CMP: c_int = 3;
+
+/// How many records are kept for `deliver_mudlog` before the oldest are dropped
+const MUDLOG_QUEUE_LIMIT: usize = 100;
+
+/// The callback registered by `set_mudlog_callback`, if any
+static MUDLOG_CALLBACK: Mutex<Option<MudlogCallback>> = Mutex::new(None);
+
+/// Records waiting for `deliver_mudlog`, since they may be logged from any thread or in the middle
+/// of the game changing its descriptor list
+static MUDLOG_QUEUE: Mutex<VecDeque<(Level, String)>> = Mutex::new(VecDeque::new());
+
+pub fn set_mudlog_callback(callback: Option<MudlogCallback>) {
+    *MUDLOG_CALLBACK
+        .lock()
+        .expect("Unable to get lock on mudlog callback") = callback;
+}
+
+fn queue_mudlog(level: Level, line: String) {
+    if MUDLOG_CALLBACK
+        .lock()
+        .expect("Unable to get lock on mudlog callback")
+        .is_none()
+    {
+        return;
+    }
+    let mut queue = MUDLOG_QUEUE
+        .lock()
+        .expect("Unable to get lock on mudlog queue");
+    if queue.len() >= MUDLOG_QUEUE_LIMIT {
+        queue.pop_front();
+    }
+    queue.push_back((level, line));
+}
+
+/// Hands the queued records to the `set_mudlog_callback` callback. Only call this from the game's
+/// thread, somewhere it's safe for `mudlog()` to walk the descriptor list.
+pub fn deliver_mudlog() {
+    let Some(callback) = *MUDLOG_CALLBACK
+        .lock()
+        .expect("Unable to get lock on mudlog callback")
+    else {
+        return;
+    };
+    // Taken first so anything the callback logs is queued for next time rather than deadlocking
+    let queued = std::mem::take(
+        &mut *MUDLOG_QUEUE
+            .lock()
+            .expect("Unable to get lock on mudlog queue"),
+    );
+    for (level, line) in queued {
+        let kind = match level {
+            Level::Error => BRF,
+            Level::Warn => NRM,
+            _ => CMP,
+        };
+        if let Ok(line) = CString::new(line.replace('\0', "")) {
+            callback(kind, line.as_ptr());
+        }
+    }
+}
+
+/// How and where to log, as configured by the `MUD_COMMS_LOG*` environment variables
+#[derive(Debug, PartialEq)]
+pub struct LogConfig {
+    pub levels: LogLevels,
+    pub format: LogFormat,
+    pub output: LogOutput,
+    /// The least severe records passed on to `set_mudlog_callback`'s callback
+    pub mudlog: LevelFilter,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            levels: LogLevels {
+                default: LevelFilter::Info,
+                modules: Vec::new(),
+            },
+            format: LogFormat::Plain,
+            output: LogOutput::Stdout,
+            mudlog: LevelFilter::Warn,
+        }
+    }
+}
+
+/// A default level and per-module overrides, written like `info,hyper=warn,mud_comms::slack=debug`
+#[derive(Debug, PartialEq)]
+pub struct LogLevels {
+    pub default: LevelFilter,
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = LogLevels {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse = |level: &str| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format!("unknown log level {:?}", level))
+            };
+            match directive.split_once('=') {
+                Some((module, level)) => levels.modules.push((module.to_owned(), parse(level)?)),
+                None => levels.default = parse(directive)?,
+            }
+        }
+        Ok(levels)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogFormat {
+    Plain,
+    /// One JSON object a line, with `time`, `level`, `target` and `message` fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("expected plain or json, not {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogOutput {
+    Stdout,
+    /// Appends to a file, moved aside to `<path>.1` (and so on, up to `keep` of them) once it
+    /// reaches `max_size` bytes
+    File {
+        path: PathBuf,
+        max_size: u64,
+        keep: usize,
+    },
+    /// Hands each line to the callback from `set_log_callback` (ie CircleMUD's `log()`), so
+    /// they end up in the game's own log
+    Circle,
+}
+
+/// Sets up the global logger
+pub fn init(config: LogConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut levels = fern::Dispatch::new().level(config.levels.default);
+    for (module, level) in config.levels.modules {
+        levels = levels.level_for(module, level);
+    }
+    let mut dispatch = fern::Dispatch::new();
+
+    // Color only makes sense for people watching a terminal
+    let colored = config.format == LogFormat::Plain
+        && config.output == LogOutput::Stdout
+        && std::io::stdout().is_terminal();
+    let timestamped = config.output != LogOutput::Circle;
+    let colors_level = ColoredLevelConfig::new()
+        .info(Color::Green)
+        .warn(Color::Magenta);
+    dispatch = match config.format {
+        LogFormat::Json => dispatch.format(|out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                serde_json::json!({
+                    "time": chrono::Local::now().to_rfc3339(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message.to_string(),
+                })
+            ))
+        }),
+        LogFormat::Plain => dispatch.format(move |out, message, record| {
+            // CircleMUD's log() adds its own timestamp
+            let time = if timestamped {
+                chrono::Local::now()
+                    .format("%b %e %H:%M:%S :: ")
+                    .to_string()
+            } else {
+                String::new()
+            };
+            if colored {
+                out.finish(format_args!(
+                    "{}[{}][{}] \x1B[{}m{}\x1B[0m",
+                    time,
+                    record.target(),
+                    colors_level.color(record.level()),
+                    colors_level.get_color(&record.level()).to_fg_str(),
+                    message
+                ))
+            } else {
+                out.finish(format_args!(
+                    "{}[{}][{}] {}",
+                    time,
+                    record.target(),
+                    record.level(),
+                    message
+                ))
+            }
+        }),
+    };
+
+    dispatch = match config.output {
+        LogOutput::Stdout => dispatch.chain(std::io::stdout()),
+        LogOutput::File {
+            path,
+            max_size,
+            keep,
+        } => dispatch
+            .chain(Box::new(RotatingFile::open(path, max_size, keep)?) as Box<dyn Write + Send>),
+        LogOutput::Circle => dispatch.chain(fern::Output::call(|record| {
+            let callback = *LOG_CALLBACK
+                .lock()
+                .expect("Unable to get lock on log callback");
+            let line = record.args().to_string();
+            match (callback, CString::new(line.replace('\0', ""))) {
+                (Some(callback), Ok(line)) => callback(line.as_ptr()),
+                // Nowhere better to put it before CircleMUD has given us somewhere
+                _ => println!("{}", line),
+            }
+        })),
+    };
+    let mudlog = fern::Dispatch::new()
+        .level(config.mudlog)
+        .chain(fern::Output::call(|record| {
+            queue_mudlog(
+                record.level(),
+                format!("{}: {}", record.target(), record.args()),
+            )
+        }));
+    levels.chain(dispatch).chain(mudlog).apply()?;
+    Ok(())
+}
+
+/// A log file that's moved aside once it gets too big. Lines are only ever written whole since
+/// it's only rotated when flushed, which fern does after every line.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    /// Moves each old file up one (dropping the oldest) and starts a new one
+    fn rotate(&mut self) -> Result<(), std::io::Error> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_levels_parse_per_module() {
+        assert_eq!(
+            LogLevels {
+                default: LevelFilter::Warn,
+                modules: vec![
+                    ("hyper".to_owned(), LevelFilter::Error),
+                    ("mud_comms::slack".to_owned(), LevelFilter::Debug),
+                ],
+            },
+            "hyper=error, warn,mud_comms::slack=debug".parse().unwrap()
+        );
+        assert!("hyper=loud".parse::<LogLevels>().is_err());
+    }
+
+    #[test]
+    fn test_files_rotate_between_lines() {
+        let dir = TempDir::new("logging").expect("temporary directory");
+        let path = dir.path().join("mud-comms.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).expect("log file");
+        for line in ["first line\n", "second\n", "third\n", "fourth line\n"] {
+            // fern writes a line in pieces, then flushes
+            let (start, end) = line.split_at(3);
+            file.write_all(start.as_bytes()).unwrap();
+            file.write_all(end.as_bytes()).unwrap();
+            file.flush().unwrap();
+        }
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap_or_default();
+        assert_eq!("", read(path.clone()));
+        assert_eq!("fourth line\n", read(dir.path().join("mud-comms.log.1")));
+        assert_eq!("second\nthird\n", read(dir.path().join("mud-comms.log.2")));
+        assert!(!dir.path().join("mud-comms.log.3").exists());
+    }
+
+    static DELIVERED: Mutex<Vec<(c_int, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn record_mudlog(kind: c_int, message: *const c_char) {
+        let message = unsafe { std::ffi::CStr::from_ptr(message) };
+        DELIVERED
+            .lock()
+            .unwrap()
+            .push((kind, message.to_string_lossy().into_owned()));
+    }
+
+    #[test]
+    fn test_mudlog_is_delivered_with_types() {
+        queue_mudlog(Level::Error, "dropped before a callback is set".to_owned());
+        set_mudlog_callback(Some(record_mudlog));
+        queue_mudlog(Level::Error, "mud_comms: Cannot create".to_owned());
+        queue_mudlog(Level::Warn, "mud_comms::slack: Retrying".to_owned());
+        queue_mudlog(Level::Info, "mud_comms: Using slack".to_owned());
+        assert!(DELIVERED.lock().unwrap().is_empty());
+
+        deliver_mudlog();
+        set_mudlog_callback(None);
+        assert_eq!(
+            vec![
+                (BRF, "mud_comms: Cannot create".to_owned()),
+                (NRM, "mud_comms::slack: Retrying".to_owned()),
+                (CMP, "mud_comms: Using slack".to_owned()),
+            ],
+            *DELIVERED.lock().unwrap()
+        );
+    }
+}
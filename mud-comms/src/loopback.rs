@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+
+use crossbeam_channel::Receiver;
+use crossbeam_channel::RecvTimeoutError;
+use crossbeam_channel::Sender;
+use crossbeam_channel::TryRecvError;
+
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+
+/// An in-memory transport, for testing what's built on descriptors (the registry, its limits,
+/// monitoring and so on) without sockets: each `LoopbackConnector::connect` queues a descriptor
+/// for the manager to hand out and returns the client's end of it.
+pub struct LoopbackDescriptorManager {
+    connections: Receiver<LoopbackDescriptor>,
+    /// Descriptors taken off the channel while blocking
+    waiting: RefCell<VecDeque<LoopbackDescriptor>>,
+}
+
+/// Opens connections to a `LoopbackDescriptorManager`, from whichever thread
+#[derive(Clone)]
+pub struct LoopbackConnector {
+    connections: Sender<LoopbackDescriptor>,
+}
+
+impl LoopbackDescriptorManager {
+    pub fn new() -> (Self, LoopbackConnector) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        (
+            LoopbackDescriptorManager {
+                connections: receiver,
+                waiting: RefCell::new(VecDeque::new()),
+            },
+            LoopbackConnector {
+                connections: sender,
+            },
+        )
+    }
+}
+
+impl LoopbackConnector {
+    /// Connects as a client from `hostname`
+    pub fn connect(&self, hostname: &str) -> Result<LoopbackClient, std::io::Error> {
+        let (input_sender, input_receiver) = crossbeam_channel::unbounded();
+        let (output_sender, output_receiver) = crossbeam_channel::unbounded();
+        self.connections
+            .send(LoopbackDescriptor {
+                hostname: hostname.to_owned(),
+                input: input_receiver,
+                unread: Vec::new(),
+                output: output_sender,
+            })
+            .map_err(|_| std::io::Error::from(ErrorKind::ConnectionRefused))?;
+        Ok(LoopbackClient {
+            input: input_sender,
+            output: output_receiver,
+            received: Vec::new(),
+        })
+    }
+}
+
+impl DescriptorManager for LoopbackDescriptorManager {
+    fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+        if !self.waiting.borrow().is_empty() {
+            return Ok(());
+        }
+        match self.connections.recv() {
+            Ok(descriptor) => {
+                self.waiting.borrow_mut().push_back(descriptor);
+                Ok(())
+            }
+            Err(_) => Err(std::io::Error::new(
+                ErrorKind::NotConnected,
+                "every loopback connector is gone",
+            )),
+        }
+    }
+
+    fn new_descriptor(
+        &self,
+    ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(descriptor) = self.waiting.borrow_mut().pop_front() {
+            return Ok(Box::new(descriptor));
+        }
+        match self.connections.try_recv() {
+            Ok(descriptor) => Ok(Box::new(descriptor)),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+/// The game's end of a loopback connection. It reads as closed once the client's dropped.
+pub struct LoopbackDescriptor {
+    hostname: String,
+    input: Receiver<Vec<u8>>,
+    /// What's left of a chunk too big for the last read
+    unread: Vec<u8>,
+    output: Sender<Vec<u8>>,
+}
+
+impl Descriptor for LoopbackDescriptor {
+    fn get_hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    fn get_type(&self) -> &'static str {
+        "LOOPBACK"
+    }
+}
+
+impl Read for LoopbackDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.unread.is_empty() {
+            self.unread = match self.input.try_recv() {
+                Ok(chunk) => chunk,
+                Err(TryRecvError::Empty) => return Err(ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            };
+        }
+        let len = buf.len().min(self.unread.len());
+        buf[..len].copy_from_slice(&self.unread[..len]);
+        self.unread.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for LoopbackDescriptor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output
+            .send(buf.to_vec())
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The client's end of a loopback connection, which waits for what it expects rather than
+/// assuming it all comes at once
+pub struct LoopbackClient {
+    input: Sender<Vec<u8>>,
+    output: Receiver<Vec<u8>>,
+    /// Output received but not yet expected
+    received: Vec<u8>,
+}
+
+impl LoopbackClient {
+    pub fn send(&self, input: &[u8]) -> Result<(), std::io::Error> {
+        self.input
+            .send(input.to_vec())
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))
+    }
+
+    /// Waits up to `timeout` for `expected` (eg a prompt) to be sent, returning everything sent
+    /// up to and including it
+    pub fn expect(
+        &mut self,
+        expected: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(start) = self
+                .received
+                .windows(expected.len().max(1))
+                .position(|window| window == expected)
+            {
+                let rest = self.received.split_off(start + expected.len());
+                return Ok(std::mem::replace(&mut self.received, rest));
+            }
+            match self.output.recv_deadline(deadline) {
+                Ok(chunk) => self.received.extend(chunk),
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        match e {
+                            RecvTimeoutError::Timeout => ErrorKind::TimedOut,
+                            RecvTimeoutError::Disconnected => ErrorKind::UnexpectedEof,
+                        },
+                        format!(
+                            "expected {:?} but got {:?}",
+                            String::from_utf8_lossy(expected),
+                            String::from_utf8_lossy(&self.received)
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the game to close the connection, returning whatever it sent
+    /// that wasn't expected
+    pub fn expect_closed(&mut self, timeout: Duration) -> Result<Vec<u8>, std::io::Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.output.recv_deadline(deadline) {
+                Ok(chunk) => self.received.extend(chunk),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Ok(std::mem::take(&mut self.received))
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use hyper::server::conn::AddrIncoming;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use log::*;
+use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+
+use crate::descriptor::DescriptorId;
+use crate::monitor::Monitors;
+use crate::shutdown::join_before;
+
+/// Upper bounds (in seconds) of the Slack API latency histogram's buckets
+const SLACK_API_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The process's metrics, shared by every transport and whichever thread they're updated from
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Counts what every transport has carried, keyed by descriptor type (eg `TELNET` or `SLACK`),
+/// and how long Slack's API takes to answer
+#[derive(Default)]
+pub struct Metrics {
+    transports: Mutex<BTreeMap<&'static str, TransportMetrics>>,
+    slack_api: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+/// The metrics kept for each transport: their names, types, help and values
+type TransportFamily = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&TransportMetrics) -> u64,
+);
+
+const TRANSPORT_FAMILIES: [TransportFamily; 7] = [
+    (
+        "mud_comms_connections_open",
+        "gauge",
+        "Connections open now",
+        |m| m.open,
+    ),
+    (
+        "mud_comms_connections_total",
+        "counter",
+        "Connections handed to the game",
+        |m| m.opened,
+    ),
+    (
+        "mud_comms_connections_refused_total",
+        "counter",
+        "Connections refused by bans or connection limits",
+        |m| m.refused,
+    ),
+    (
+        "mud_comms_received_bytes_total",
+        "counter",
+        "Bytes read from clients",
+        |m| m.bytes_in,
+    ),
+    (
+        "mud_comms_sent_bytes_total",
+        "counter",
+        "Bytes written to clients",
+        |m| m.bytes_out,
+    ),
+    (
+        "mud_comms_read_failures_total",
+        "counter",
+        "Reads that failed, closing the connection",
+        |m| m.read_failures,
+    ),
+    (
+        "mud_comms_write_failures_total",
+        "counter",
+        "Writes that failed, closing the connection",
+        |m| m.write_failures,
+    ),
+];
+
+#[derive(Clone, Copy, Default)]
+struct TransportMetrics {
+    open: u64,
+    opened: u64,
+    refused: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    read_failures: u64,
+    write_failures: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Histogram {
+    buckets: [u64; SLACK_API_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn opened(&self, transport: &'static str) {
+        self.update(transport, |metrics| {
+            metrics.open += 1;
+            metrics.opened += 1;
+        });
+    }
+
+    pub fn closed(&self, transport: &'static str) {
+        self.update(transport, |metrics| {
+            metrics.open = metrics.open.saturating_sub(1)
+        });
+    }
+
+    /// A connection closed before the game saw it, by a ban or the `ConnectionLimits`
+    pub fn refused(&self, transport: &'static str) {
+        self.update(transport, |metrics| metrics.refused += 1);
+    }
+
+    pub fn read(&self, transport: &'static str, bytes: usize) {
+        self.update(transport, |metrics| metrics.bytes_in += bytes as u64);
+    }
+
+    pub fn written(&self, transport: &'static str, bytes: usize) {
+        self.update(transport, |metrics| metrics.bytes_out += bytes as u64);
+    }
+
+    pub fn read_failed(&self, transport: &'static str) {
+        self.update(transport, |metrics| metrics.read_failures += 1);
+    }
+
+    pub fn write_failed(&self, transport: &'static str) {
+        self.update(transport, |metrics| metrics.write_failures += 1);
+    }
+
+    /// Records how long a call to the Slack API `method` (eg `chat.postMessage`) took, whether
+    /// or not it succeeded
+    pub fn slack_api_call(&self, method: &'static str, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut slack_api = self
+            .slack_api
+            .lock()
+            .expect("Unable to get lock on metrics");
+        let histogram = slack_api.entry(method).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(SLACK_API_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    fn update(&self, transport: &'static str, update: impl FnOnce(&mut TransportMetrics)) {
+        update(
+            self.transports
+                .lock()
+                .expect("Unable to get lock on metrics")
+                .entry(transport)
+                .or_default(),
+        )
+    }
+
+    /// Everything in Prometheus' text exposition format
+    pub fn render(&self) -> String {
+        let transports = self
+            .transports
+            .lock()
+            .expect("Unable to get lock on metrics")
+            .clone();
+        let mut out = String::new();
+        for (name, kind, help, value) in TRANSPORT_FAMILIES {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (transport, metrics) in &transports {
+                let _ = writeln!(
+                    out,
+                    "{}{{transport=\"{}\"}} {}",
+                    name,
+                    transport,
+                    value(metrics)
+                );
+            }
+        }
+
+        let name = "mud_comms_slack_api_request_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time taken by Slack API calls\n# TYPE {} histogram",
+            name, name
+        );
+        for (method, histogram) in self
+            .slack_api
+            .lock()
+            .expect("Unable to get lock on metrics")
+            .iter()
+        {
+            for (count, bound) in histogram.buckets.iter().zip(SLACK_API_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                    name, method, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{method=\"{}\",le=\"+Inf\"}} {}\n\
+                 {}_sum{{method=\"{}\"}} {}\n\
+                 {}_count{{method=\"{}\"}} {}",
+                name,
+                method,
+                histogram.count,
+                name,
+                method,
+                histogram.sum,
+                name,
+                method,
+                histogram.count
+            );
+        }
+        out
+    }
+
+    /// A few lines for people (ie the `show stats` command) rather than Prometheus
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        for (transport, metrics) in self
+            .transports
+            .lock()
+            .expect("Unable to get lock on metrics")
+            .iter()
+        {
+            let _ = write!(
+                out,
+                "  {:<8} {:5} open      {:5} total     {:5} refused\r\n",
+                transport, metrics.open, metrics.opened, metrics.refused
+            );
+            let _ = write!(
+                out,
+                "  {:<8} {:9} bytes in {:9} bytes out {} read and {} write failures\r\n",
+                "",
+                metrics.bytes_in,
+                metrics.bytes_out,
+                metrics.read_failures,
+                metrics.write_failures
+            );
+        }
+        for (method, histogram) in self
+            .slack_api
+            .lock()
+            .expect("Unable to get lock on metrics")
+            .iter()
+        {
+            let _ = write!(
+                out,
+                "  Slack {} {} calls, {:.3}s average\r\n",
+                method,
+                histogram.count,
+                histogram.sum / histogram.count as f64
+            );
+        }
+        out
+    }
+}
+
+/// Serves `metrics()` from `/metrics` for Prometheus to scrape, and given `Monitors`, lists
+/// descriptors from `/descriptors` and streams what one's sending and being sent from
+/// `/descriptors/<id>/monitor`. Anyone who can connect can watch players type, so only serve
+/// those on an address admins alone can reach.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    server: Option<(thread::JoinHandle<()>, oneshot::Sender<()>)>,
+}
+
+impl MetricsServer {
+    pub fn start(addr: SocketAddr, monitors: Option<Monitors>) -> Result<Self, std::io::Error> {
+        let runtime = Runtime::new()?;
+        // Binding needs the runtime even though it isn't async
+        let incoming = {
+            let _runtime = runtime.enter();
+            AddrIncoming::bind(&addr).map_err(std::io::Error::other)?
+        };
+        let addr = incoming.local_addr();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let thread = thread::spawn(move || {
+            match monitors {
+                Some(_) => info!("Serving metrics and monitoring on {}", addr),
+                None => info!("Serving metrics on {}", addr),
+            }
+            let service = make_service_fn(move |_| {
+                let monitors = monitors.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |request| {
+                        let response = respond(request, monitors.as_ref());
+                        async { Ok::<_, hyper::Error>(response) }
+                    }))
+                }
+            });
+            let result = runtime.block_on(
+                hyper::Server::builder(incoming)
+                    .serve(service)
+                    .with_graceful_shutdown(async {
+                        let _ = stopped.await;
+                    }),
+            );
+            if let Err(e) = result {
+                error!("Metrics server failed: {}", e);
+            }
+        });
+        Ok(MetricsServer {
+            addr,
+            server: Some((thread, stop)),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn shutdown(&mut self, deadline: Instant) {
+        if let Some((thread, stop)) = self.server.take() {
+            let _ = stop.send(());
+            if join_before(thread, deadline).is_none() {
+                warn!("Metrics server still running at shutdown");
+            }
+        }
+    }
+}
+
+fn respond(request: Request<Body>, monitors: Option<&Monitors>) -> Response<Body> {
+    let path = request.uri().path();
+    let watched = path
+        .strip_prefix("/descriptors/")
+        .and_then(|rest| rest.strip_suffix("/monitor"))
+        .and_then(|id| id.parse().ok())
+        .map(DescriptorId);
+    match (request.method(), path, monitors, watched) {
+        (&Method::GET, "/metrics", _, _) => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(metrics().render().into()),
+        (&Method::GET, "/descriptors", Some(monitors), _) => Response::builder()
+            .header("Content-Type", "text/plain")
+            .body(monitors.list().into()),
+        (&Method::GET, _, Some(monitors), Some(id)) => match monitors.watch(id) {
+            Some(watch) => Response::builder()
+                .header("Content-Type", "application/octet-stream")
+                .body(Body::wrap_stream(futures_util::stream::unfold(
+                    watch,
+                    |mut watch| async {
+                        let chunk = watch.recv().await?;
+                        Some((Ok::<_, std::io::Error>(chunk), watch))
+                    },
+                ))),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty()),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    }
+    .expect("static response parts to be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use super::*;
+
+    #[test]
+    fn test_metrics_render_in_prometheus_format() {
+        let metrics = Metrics::default();
+        metrics.opened("TELNET");
+        metrics.opened("TELNET");
+        metrics.closed("TELNET");
+        metrics.written("TELNET", 120);
+        metrics.refused("SLACK");
+        metrics.slack_api_call("chat.postMessage", Duration::from_millis(200));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE mud_comms_connections_open gauge\n"));
+        assert!(rendered.contains("mud_comms_connections_open{transport=\"TELNET\"} 1\n"));
+        assert!(rendered.contains("mud_comms_connections_total{transport=\"TELNET\"} 2\n"));
+        assert!(rendered.contains("mud_comms_sent_bytes_total{transport=\"TELNET\"} 120\n"));
+        assert!(rendered.contains("mud_comms_connections_refused_total{transport=\"SLACK\"} 1\n"));
+        let histogram = "mud_comms_slack_api_request_duration_seconds";
+        assert!(rendered.contains(&format!(
+            "{}_bucket{{method=\"chat.postMessage\",le=\"0.1\"}} 0\n",
+            histogram
+        )));
+        assert!(rendered.contains(&format!(
+            "{}_bucket{{method=\"chat.postMessage\",le=\"0.25\"}} 1\n",
+            histogram
+        )));
+        assert!(rendered.contains(&format!(
+            "{}_count{{method=\"chat.postMessage\"}} 1\n",
+            histogram
+        )));
+    }
+
+    #[test]
+    fn test_server_serves_metrics() {
+        let mut server =
+            MetricsServer::start("127.0.0.1:0".parse().unwrap(), None).expect("server");
+        metrics().opened("TEST");
+
+        let mut stream = TcpStream::connect(server.addr()).expect("connected");
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .expect("request sent");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("response");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("mud_comms_connections_total{transport=\"TEST\"}"));
+
+        // Monitoring is only served when asked for
+        let mut stream = TcpStream::connect(server.addr()).expect("connected");
+        stream
+            .write_all(b"GET /descriptors HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .expect("request sent");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("response");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+        server.shutdown(Instant::now() + Duration::from_secs(5));
+        assert!(TcpStream::connect(server.addr()).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+
+use crate::copyover::SavedDescriptor;
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorId;
+use crate::keepalive::Keepalive;
+use crate::recording::Direction;
+use crate::telnet;
+
+/// How many chunks a watcher can fall behind by before what it misses is dropped, so a slow
+/// watcher never holds up the game
+const WATCHER_BACKLOG: usize = 256;
+
+/// Every registered descriptor, for admins to list and watch whatever they're sending and being
+/// sent, whatever state the game has them in. Shared between the game's thread, which publishes,
+/// and whichever serves the admins.
+#[derive(Clone, Default)]
+pub struct Monitors {
+    descriptors: Arc<Mutex<BTreeMap<DescriptorId, Monitored>>>,
+}
+
+struct Monitored {
+    kind: &'static str,
+    hostname: String,
+    connected: Instant,
+    /// Whether the client was last told not to echo, ie it's typing a password
+    echo_off: bool,
+    watchers: Vec<mpsc::Sender<Vec<u8>>>,
+}
+
+impl Monitors {
+    /// One line for each descriptor: its ID, type, host and how long it's been connected
+    pub fn list(&self) -> String {
+        let mut out = String::new();
+        for (id, monitored) in self
+            .descriptors
+            .lock()
+            .expect("Unable to get lock on monitors")
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "{} {} {} {}s",
+                id,
+                monitored.kind,
+                monitored.hostname,
+                monitored.connected.elapsed().as_secs()
+            );
+        }
+        out
+    }
+
+    /// Everything sent to and from descriptor `id` from now on until it's closed. Output is
+    /// passed on as it was sent and input as `<<< ` lines, hiding passwords.
+    pub fn watch(&self, id: DescriptorId) -> Option<mpsc::Receiver<Vec<u8>>> {
+        let (sender, receiver) = mpsc::channel(WATCHER_BACKLOG);
+        self.descriptors
+            .lock()
+            .expect("Unable to get lock on monitors")
+            .get_mut(&id)?
+            .watchers
+            .push(sender);
+        Some(receiver)
+    }
+
+    /// Ends every watch, eg so a server streaming them can shut down
+    pub fn close_all(&self) {
+        for monitored in self
+            .descriptors
+            .lock()
+            .expect("Unable to get lock on monitors")
+            .values_mut()
+        {
+            monitored.watchers.clear();
+        }
+    }
+
+    fn publish(&self, id: DescriptorId, direction: Direction, data: &[u8]) {
+        let mut descriptors = self
+            .descriptors
+            .lock()
+            .expect("Unable to get lock on monitors");
+        let Some(monitored) = descriptors.get_mut(&id) else {
+            return;
+        };
+        let chunk = match direction {
+            Direction::Output => {
+                if let Some(echo_off) = telnet::echo_change(data) {
+                    monitored.echo_off = echo_off;
+                }
+                data.to_vec()
+            }
+            Direction::Input if monitored.echo_off => b"\r\n<<< (hidden)\r\n".to_vec(),
+            Direction::Input => format!(
+                "\r\n<<< {}\r\n",
+                String::from_utf8_lossy(data).trim_end().escape_debug()
+            )
+            .into_bytes(),
+        };
+        monitored.watchers.retain(|watcher| {
+            !matches!(
+                watcher.try_send(chunk.clone()),
+                Err(mpsc::error::TrySendError::Closed(_))
+            )
+        });
+    }
+}
+
+/// Wraps a registered descriptor, publishing everything read from and written to it to its
+/// `Monitors` until it's dropped
+pub(crate) struct MonitoredDescriptor {
+    descriptor: Box<dyn Descriptor>,
+    id: DescriptorId,
+    monitors: Monitors,
+}
+
+impl MonitoredDescriptor {
+    pub fn new(descriptor: Box<dyn Descriptor>, id: DescriptorId, monitors: Monitors) -> Self {
+        monitors
+            .descriptors
+            .lock()
+            .expect("Unable to get lock on monitors")
+            .insert(
+                id,
+                Monitored {
+                    kind: descriptor.get_type(),
+                    hostname: descriptor.get_hostname().to_owned(),
+                    connected: Instant::now(),
+                    echo_off: false,
+                    watchers: Vec::new(),
+                },
+            );
+        MonitoredDescriptor {
+            descriptor,
+            id,
+            monitors,
+        }
+    }
+}
+
+impl Drop for MonitoredDescriptor {
+    fn drop(&mut self) {
+        self.monitors
+            .descriptors
+            .lock()
+            .expect("Unable to get lock on monitors")
+            .remove(&self.id);
+    }
+}
+
+impl Descriptor for MonitoredDescriptor {
+    fn get_hostname(&self) -> &str {
+        self.descriptor.get_hostname()
+    }
+
+    fn get_ip(&self) -> Option<IpAddr> {
+        self.descriptor.get_ip()
+    }
+
+    fn get_type(&self) -> &'static str {
+        self.descriptor.get_type()
+    }
+
+    fn get_identity(&self) -> Option<&str> {
+        self.descriptor.get_identity()
+    }
+
+    fn get_linked_character(&self) -> Option<String> {
+        self.descriptor.get_linked_character()
+    }
+
+    fn link_character(&mut self, character: Option<&str>) -> Result<(), std::io::Error> {
+        self.descriptor.link_character(character)
+    }
+
+    fn set_keepalive(&self, keepalive: &Keepalive) -> Result<(), std::io::Error> {
+        self.descriptor.set_keepalive(keepalive)
+    }
+
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        self.descriptor.finish()
+    }
+
+    fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+        self.descriptor.save()
+    }
+}
+
+impl Read for MonitoredDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.descriptor.read(buf)?;
+        if read > 0 {
+            self.monitors
+                .publish(self.id, Direction::Input, &buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl Write for MonitoredDescriptor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.descriptor.write(buf)?;
+        if written > 0 {
+            self.monitors
+                .publish(self.id, Direction::Output, &buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.descriptor.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Reads back `input` a chunk at a time and swallows whatever's written
+    struct ScriptedDescriptor {
+        input: VecDeque<&'static [u8]>,
+    }
+
+    impl Descriptor for ScriptedDescriptor {
+        fn get_hostname(&self) -> &str {
+            "scripted"
+        }
+
+        fn get_type(&self) -> &'static str {
+            "FAKE"
+        }
+    }
+
+    impl Read for ScriptedDescriptor {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let chunk = self.input.pop_front().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for ScriptedDescriptor {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_watchers_see_both_ways_without_passwords() {
+        let monitors = Monitors::default();
+        let descriptor = ScriptedDescriptor {
+            input: VecDeque::from([&b"Foo\r\n"[..], b"hunter2\r\n", b"look\r\n"]),
+        };
+        let mut monitored =
+            MonitoredDescriptor::new(Box::new(descriptor), DescriptorId(3), monitors.clone());
+        assert!(monitors.list().starts_with("3 FAKE scripted "));
+        assert!(monitors.watch(DescriptorId(4)).is_none());
+
+        let mut watcher = monitors.watch(DescriptorId(3)).expect("watching");
+        let mut buf = [0; 64];
+        monitored.write_all(b"Name? ").unwrap();
+        assert_eq!(5, monitored.read(&mut buf).unwrap());
+        monitored.write_all(b"Password: \xFF\xFB\x01").unwrap();
+        assert_eq!(9, monitored.read(&mut buf).unwrap());
+        monitored.write_all(b"\xFF\xFC\x01\r\n> ").unwrap();
+        assert_eq!(6, monitored.read(&mut buf).unwrap());
+        drop(monitored);
+
+        let mut seen = Vec::new();
+        while let Some(chunk) = watcher.blocking_recv() {
+            seen.extend(chunk);
+        }
+        assert_eq!(
+            &b"Name? \r\n<<< Foo\r\nPassword: \xFF\xFB\x01\r\n<<< (hidden)\r\n\
+               \xFF\xFC\x01\r\n> \r\n<<< look\r\n"[..],
+            &seen[..]
+        );
+        assert_eq!("", monitors.list());
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::ErrorKind;
+use std::io::Read;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::error;
+use log::warn;
+
+use crate::cidr::Cidr;
+
+/// How long a trusted proxy gets to send its whole header before the connection is dropped
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest PROXY protocol v1 header, including its CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The load balancers (eg HAProxy) allowed to say which client a connection is really from, by
+/// starting it with a PROXY protocol header. With none trusted, no connection is read that way.
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma or space separated list of addresses and CIDR blocks
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let proxies = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|proxy| !proxy.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(TrustedProxies { proxies })
+    }
+
+    /// Whether connections from `ip` must start with a PROXY header, naming their real client
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|proxy| proxy.contains(ip))
+    }
+}
+
+/// Reads the PROXY header that the trusted proxy at `proxy` started `stream` with on a thread of
+/// its own, so a slow or silent proxy holds up nothing but its own connection, then passes the
+/// connection to `hand_over` with the client the header names. The connection is dropped instead
+/// if the whole header hasn't arrived, and been valid, within `HEADER_TIMEOUT`.
+pub fn hand_over_once_read(
+    mut stream: TcpStream,
+    proxy: IpAddr,
+    hand_over: impl FnOnce(TcpStream, IpAddr) + Send + 'static,
+) {
+    let deadline = Instant::now() + HEADER_TIMEOUT;
+    let spawned = thread::Builder::new()
+        .name("proxy-header".to_owned())
+        .spawn(move || match read_client_ip(&mut stream, proxy, deadline) {
+            Ok(client) => hand_over(stream, client),
+            Err(e) => warn!("Cannot read PROXY header from {}: {}", proxy, e),
+        });
+    if let Err(e) = spawned {
+        error!("Cannot read PROXY header from {}: {}", proxy, e);
+    }
+}
+
+/// Reads the header a trusted proxy started `stream` with, which must all arrive by `deadline`
+fn read_client_ip(
+    stream: &mut TcpStream,
+    proxy: IpAddr,
+    deadline: Instant,
+) -> Result<IpAddr, std::io::Error> {
+    stream.set_nonblocking(false)?;
+    let source = read_header(&mut ReadBefore { stream, deadline });
+    stream.set_read_timeout(None)?;
+    // Without a source (eg the proxy's own health checks) the proxy is the client
+    Ok(source?.unwrap_or(proxy))
+}
+
+/// Reads a stream with only what's left until `deadline` as the timeout for each read, so a
+/// header sent a byte at a time still has to have arrived by then
+struct ReadBefore<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for ReadBefore<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Reads exactly one PROXY protocol v1 or v2 header, returning the source address it gives
+pub fn read_header(stream: &mut impl Read) -> Result<Option<IpAddr>, std::io::Error> {
+    let mut first = [0; 1];
+    stream.read_exact(&mut first)?;
+    match first[0] {
+        b'P' => read_v1(stream),
+        b'\r' => read_v2(stream),
+        _ => Err(invalid("connection didn't start with a PROXY header")),
+    }
+}
+
+/// eg `PROXY TCP4 203.0.113.9 10.0.0.1 51234 4000\r\n`, after the leading `P`
+fn read_v1(stream: &mut impl Read) -> Result<Option<IpAddr>, std::io::Error> {
+    // Byte at a time, so nothing the client sent after the header is consumed
+    let mut line = vec![b'P'];
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(invalid("PROXY v1 header is too long"));
+        }
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY v1 header isn't text"))?;
+    let mut fields = line.split(' ');
+    if fields.next() != Some("PROXY") {
+        return Err(invalid("PROXY v1 header has no PROXY"));
+    }
+    match (fields.next(), fields.next()) {
+        (Some("TCP4"), Some(source)) => source
+            .parse::<Ipv4Addr>()
+            .map(|source| Some(IpAddr::V4(source)))
+            .map_err(|_| invalid("PROXY v1 header has an invalid TCP4 source")),
+        (Some("TCP6"), Some(source)) => source
+            .parse::<Ipv6Addr>()
+            .map(|source| Some(IpAddr::V6(source)))
+            .map_err(|_| invalid("PROXY v1 header has an invalid TCP6 source")),
+        (Some("UNKNOWN"), _) => Ok(None),
+        _ => Err(invalid("PROXY v1 header has an unknown protocol")),
+    }
+}
+
+/// The binary header, after the leading `\r` of its signature
+fn read_v2(stream: &mut impl Read) -> Result<Option<IpAddr>, std::io::Error> {
+    let mut fixed = [0; 15];
+    stream.read_exact(&mut fixed)?;
+    if fixed[..11] != V2_SIGNATURE[1..] {
+        return Err(invalid("PROXY v2 header has an invalid signature"));
+    }
+    let (version_command, family) = (fixed[11], fixed[12]);
+    let mut addresses = vec![0; usize::from(u16::from_be_bytes([fixed[13], fixed[14]]))];
+    stream.read_exact(&mut addresses)?;
+    if version_command >> 4 != 2 {
+        return Err(invalid("PROXY v2 header has an unknown version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL, sent by the proxy on its own behalf
+        0 => return Ok(None),
+        // PROXY
+        1 => (),
+        _ => return Err(invalid("PROXY v2 header has an unknown command")),
+    }
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let source: [u8; 4] = addresses[..4].try_into().expect("4 bytes");
+            Ok(Some(IpAddr::V4(Ipv4Addr::from(source))))
+        }
+        2 if addresses.len() >= 36 => {
+            let source: [u8; 16] = addresses[..16].try_into().expect("16 bytes");
+            Ok(Some(IpAddr::V6(Ipv6Addr::from(source))))
+        }
+        1 | 2 => Err(invalid("PROXY v2 header is too short for its addresses")),
+        // UNSPEC, or a Unix socket, neither of which says where the client is
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_v1_headers() {
+        let mut stream: &[u8] = b"PROXY TCP4 203.0.113.9 10.0.0.1 51234 4000\r\nhello";
+        assert_eq!(Some(ip("203.0.113.9")), read_header(&mut stream).unwrap());
+        assert_eq!(b"hello", stream);
+
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::9 2001:db8::1 51234 4000\r\n";
+        assert_eq!(Some(ip("2001:db8::9")), read_header(&mut stream).unwrap());
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(None, read_header(&mut stream).unwrap());
+
+        let mut stream: &[u8] = b"PROXY TCP4 not-an-address 10.0.0.1 51234 4000\r\n";
+        assert!(read_header(&mut stream).is_err());
+
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LENGTH));
+        assert!(read_header(&mut long.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_v2_headers() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([203, 0, 113, 9, 10, 0, 0, 1, 0xc8, 0x22, 0x0f, 0xa0]);
+        header.extend(b"hello");
+        let mut stream = header.as_slice();
+        assert_eq!(Some(ip("203.0.113.9")), read_header(&mut stream).unwrap());
+        assert_eq!(b"hello", stream);
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x21, 0, 36]);
+        header.extend(ip_octets("2001:db8::9"));
+        header.extend(ip_octets("2001:db8::1"));
+        header.extend([0xc8, 0x22, 0x0f, 0xa0]);
+        assert_eq!(
+            Some(ip("2001:db8::9")),
+            read_header(&mut header.as_slice()).unwrap()
+        );
+
+        // LOCAL connections, eg health checks, carry no client (nor do TLVs need parsing)
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20, 0x00, 0, 3, 1, 2, 3]);
+        assert_eq!(None, read_header(&mut header.as_slice()).unwrap());
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 4, 203, 0, 113, 9]);
+        assert!(read_header(&mut header.as_slice()).is_err());
+    }
+
+    fn ip_octets(s: &str) -> [u8; 16] {
+        s.parse::<Ipv6Addr>().unwrap().octets()
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 127.0.0.1").expect("proxies");
+        assert!(proxies.trusts(ip("10.1.2.3")));
+        assert!(proxies.trusts(ip("127.0.0.1")));
+        assert!(!proxies.trusts(ip("127.0.0.2")));
+        assert!(!TrustedProxies::default().trusts(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn test_connection_is_handed_over_once_its_header_is_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).expect("connected");
+        let (stream, _) = listener.accept().expect("accepted");
+        let (sender, handed_over) = crossbeam_channel::unbounded();
+        hand_over_once_read(stream, ip("127.0.0.1"), move |stream, client| {
+            sender.send((stream, client)).unwrap()
+        });
+        assert!(handed_over.try_recv().is_err());
+
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 51234 4000\r\nhello")
+            .expect("header written");
+        let (mut stream, client) = handed_over
+            .recv_timeout(Duration::from_secs(5))
+            .expect("handed over");
+        assert_eq!(ip("203.0.113.9"), client);
+        let mut hello = [0; 5];
+        stream
+            .read_exact(&mut hello)
+            .expect("read after the header");
+        assert_eq!(b"hello", &hello);
+    }
+
+    #[test]
+    fn test_header_sent_slowly_is_cut_off_at_the_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).expect("connected");
+        let (mut stream, _) = listener.accept().expect("accepted");
+        let sender = thread::spawn(move || {
+            // Every byte arrives well within the time left, but the header as a whole doesn't
+            for byte in b"PROXY TCP4 203.0.113.9 127.0.0.1 51234 4000\r\n" {
+                if client.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(200);
+        let error = read_client_ip(&mut stream, ip("127.0.0.1"), deadline).expect_err("timed out");
+        assert!(
+            matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock),
+            "{}",
+            error
+        );
+        assert!(started.elapsed() < Duration::from_millis(500));
+        drop(stream);
+        sender.join().expect("sender finished");
+    }
+}
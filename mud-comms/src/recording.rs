@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::LineWriter;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::copyover::SavedDescriptor;
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorId;
+use crate::keepalive::Keepalive;
+use crate::telnet;
+
+/// Which way an `Event`'s data went
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from the client, recorded as asciicast's `i`
+    Input,
+    /// Written to the client, recorded as asciicast's `o`
+    Output,
+}
+
+/// Something sent one way or the other during a recorded session
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    /// Seconds since the session started
+    pub time: f64,
+    pub direction: Direction,
+    pub data: String,
+}
+
+/// Reads the events of a recording made by `RecordedDescriptor`, in order
+pub fn read_events(path: &Path) -> Result<Vec<Event>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: Value = serde_json::from_str(&lines.next().ok_or("recording is empty")??)?;
+    if header["version"] != 2 {
+        return Err("recording is not asciicast version 2".into());
+    }
+    let mut events = Vec::new();
+    for line in lines {
+        let (time, code, data): (f64, String, String) = serde_json::from_str(&line?)?;
+        let direction = match code.as_str() {
+            "i" => Direction::Input,
+            "o" => Direction::Output,
+            // Markers and resizes mean nothing to a MUD
+            _ => continue,
+        };
+        events.push(Event {
+            time,
+            direction,
+            data,
+        });
+    }
+    Ok(events)
+}
+
+/// An asciicast v2 file being written, which `asciinema play` can show and the `replay` binary
+/// can send to a server again. Bytes that aren't UTF-8 (eg telnet negotiation) are recorded as
+/// U+FFFD.
+pub(crate) struct Recording {
+    file: LineWriter<File>,
+    started: Instant,
+}
+
+impl Recording {
+    /// Starts a recording of `descriptor` in `dir`, named for when it started and its `id`, which
+    /// only the game's user can read
+    pub fn create(
+        dir: &Path,
+        id: DescriptorId,
+        descriptor: &dyn Descriptor,
+    ) -> Result<Self, std::io::Error> {
+        let now = chrono::Local::now();
+        let path: PathBuf = dir.join(format!("{}-{}.cast", now.format("%Y%m%d-%H%M%S"), id));
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        let mut file = LineWriter::new(file);
+        let header = json!({
+            "version": 2,
+            "width": 80,
+            "height": 24,
+            "timestamp": now.timestamp(),
+            "title": format!("{} {} from {}", descriptor.get_type(), id, descriptor.get_hostname()),
+        });
+        writeln!(file, "{}", header)?;
+        Ok(Recording {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> Result<(), std::io::Error> {
+        let code = match direction {
+            Direction::Input => "i",
+            Direction::Output => "o",
+        };
+        let event = json!([
+            self.started.elapsed().as_secs_f64(),
+            code,
+            String::from_utf8_lossy(data),
+        ]);
+        writeln!(self.file, "{}", event)
+    }
+}
+
+/// Wraps a descriptor, recording everything read from and written to it, save for what's typed
+/// while the client isn't echoing (ie passwords)
+pub(crate) struct RecordedDescriptor {
+    descriptor: Box<dyn Descriptor>,
+    /// Whether the client was last told not to echo, ie it's typing a password
+    echo_off: bool,
+    /// `None` once writing to it has failed, so the session carries on unrecorded
+    recording: Option<Recording>,
+}
+
+impl RecordedDescriptor {
+    pub fn new(descriptor: Box<dyn Descriptor>, recording: Recording) -> Self {
+        RecordedDescriptor {
+            descriptor,
+            echo_off: false,
+            recording: Some(recording),
+        }
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        if data.is_empty() {
+            return;
+        }
+        let data = match direction {
+            Direction::Output => {
+                if let Some(echo_off) = telnet::echo_change(data) {
+                    self.echo_off = echo_off;
+                }
+                data
+            }
+            Direction::Input if self.echo_off => b"(hidden)\r\n",
+            Direction::Input => data,
+        };
+        if let Err(e) = recording.record(direction, data) {
+            warn!(
+                "Cannot record session from {}, no longer recording: {}",
+                self.descriptor.get_hostname(),
+                e
+            );
+            self.recording = None;
+        }
+    }
+}
+
+impl Descriptor for RecordedDescriptor {
+    fn get_hostname(&self) -> &str {
+        self.descriptor.get_hostname()
+    }
+
+    fn get_ip(&self) -> Option<IpAddr> {
+        self.descriptor.get_ip()
+    }
+
+    fn get_type(&self) -> &'static str {
+        self.descriptor.get_type()
+    }
+
+    fn get_identity(&self) -> Option<&str> {
+        self.descriptor.get_identity()
+    }
+
+    fn get_linked_character(&self) -> Option<String> {
+        self.descriptor.get_linked_character()
+    }
+
+    fn link_character(&mut self, character: Option<&str>) -> Result<(), std::io::Error> {
+        self.descriptor.link_character(character)
+    }
+
+    fn set_keepalive(&self, keepalive: &Keepalive) -> Result<(), std::io::Error> {
+        self.descriptor.set_keepalive(keepalive)
+    }
+
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        self.descriptor.finish()
+    }
+
+    fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+        self.descriptor.save()
+    }
+}
+
+impl Read for RecordedDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.descriptor.read(buf)?;
+        self.record(Direction::Input, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl Write for RecordedDescriptor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.descriptor.write(buf)?;
+        self.record(Direction::Output, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.descriptor.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    /// Reads back `input` a chunk at a time and swallows whatever's written
+    struct ScriptedDescriptor {
+        input: VecDeque<&'static [u8]>,
+    }
+
+    impl Descriptor for ScriptedDescriptor {
+        fn get_hostname(&self) -> &str {
+            "scripted"
+        }
+
+        fn get_type(&self) -> &'static str {
+            "FAKE"
+        }
+    }
+
+    impl Read for ScriptedDescriptor {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let chunk = self.input.pop_front().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for ScriptedDescriptor {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sessions_are_recorded_both_ways_without_passwords() {
+        let dir = TempDir::new("recording").expect("temporary directory");
+        let descriptor = ScriptedDescriptor {
+            input: VecDeque::from([&b"Foo\r\n"[..], b"secret\r\n", b"look\r\n"]),
+        };
+        let recording =
+            Recording::create(dir.path(), DescriptorId(7), &descriptor).expect("recording started");
+        let mut recorded = RecordedDescriptor::new(Box::new(descriptor), recording);
+        let mut buf = [0; 64];
+        recorded
+            .write_all(b"By what name do you wish to be known? ")
+            .unwrap();
+        assert_eq!(5, recorded.read(&mut buf).unwrap());
+        recorded.write_all(b"Password: \xFF\xFB\x01").unwrap();
+        assert_eq!(8, recorded.read(&mut buf).unwrap());
+        recorded.write_all(b"\xFF\xFC\x01Welcome").unwrap();
+        assert_eq!(6, recorded.read(&mut buf).unwrap());
+        // Nothing read is nothing recorded
+        assert_eq!(0, recorded.read(&mut buf).unwrap());
+        drop(recorded);
+
+        let path = std::fs::read_dir(dir.path())
+            .unwrap()
+            .next()
+            .expect("a recording")
+            .unwrap()
+            .path();
+        assert!(path.to_string_lossy().ends_with("-7.cast"));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        let events = read_events(&path).expect("recording read");
+        let sent: Vec<_> = events
+            .iter()
+            .map(|event| (event.direction, event.data.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Direction::Output, "By what name do you wish to be known? "),
+                (Direction::Input, "Foo\r\n"),
+                (Direction::Output, "Password: \u{FFFD}\u{FFFD}\u{1}"),
+                (Direction::Input, "(hidden)\r\n"),
+                (Direction::Output, "\u{FFFD}\u{FFFD}\u{1}Welcome"),
+                (Direction::Input, "look\r\n"),
+            ],
+            sent
+        );
+        assert!(events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    }
+}
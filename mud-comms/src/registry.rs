@@ -0,0 +1,887 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::*;
+
+use crate::ban::BanList;
+use crate::ban::BanType;
+use crate::copyover::CopyoverEntry;
+use crate::copyover::CopyoverState;
+use crate::copyover::COPYOVER_ENV;
+use crate::descriptor::no_new_descriptor;
+use crate::descriptor::AdminCommand;
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorId;
+use crate::descriptor::DescriptorManager;
+use crate::input::InputFilter;
+use crate::input::InputLimits;
+use crate::input::InputStats;
+use crate::input::OVERLONG_MESSAGE;
+use crate::keepalive::Keepalive;
+use crate::limits::ConnectionLimiter;
+use crate::limits::ConnectionLimits;
+use crate::limits::REFUSED_MESSAGE;
+use crate::listener::LISTEN_FD_ENV;
+use crate::metrics::metrics;
+use crate::metrics::MetricsServer;
+use crate::monitor::MonitoredDescriptor;
+use crate::monitor::Monitors;
+use crate::recording::RecordedDescriptor;
+use crate::recording::Recording;
+use crate::shutdown::wait_until;
+
+/// Owns a manager's descriptors so callers (ie CircleMUD) can refer to them by stable
+/// `DescriptorId`s rather than pointers that must be freed exactly once.
+pub struct DescriptorRegistry {
+    manager: Box<dyn DescriptorManager>,
+    descriptors: BTreeMap<DescriptorId, Box<dyn Descriptor>>,
+    tags: HashMap<DescriptorId, String>,
+    restored: VecDeque<DescriptorId>,
+    limiter: ConnectionLimiter,
+    authenticated: HashSet<DescriptorId>,
+    input_limits: InputLimits,
+    inputs: HashMap<DescriptorId, InputFilter>,
+    bans: BanList,
+    keepalive: Option<Keepalive>,
+    activity: HashMap<DescriptorId, Activity>,
+    metrics_servers: Vec<MetricsServer>,
+    monitors: Monitors,
+    /// Where to record every session, if they're being recorded
+    recordings: Option<PathBuf>,
+    /// Admin commands handed to the game and waiting for its answer
+    admin_commands: HashMap<u64, AdminCommand>,
+    next_admin_command_id: u64,
+    shut_down: bool,
+    next_id: u64,
+}
+
+/// When a descriptor connected and last sent anything
+struct Activity {
+    connected: Instant,
+    last_input: Instant,
+}
+
+/// Sent to connections closed by `ConnectionLimits::login_timeout`
+const LOGIN_TIMEOUT_MESSAGE: &str = "\r\nTimed out... goodbye.\r\n";
+
+impl DescriptorRegistry {
+    pub fn new(
+        manager: Box<dyn DescriptorManager>,
+        limits: ConnectionLimits,
+        input_limits: InputLimits,
+        keepalive: Option<Keepalive>,
+    ) -> Self {
+        DescriptorRegistry {
+            manager,
+            descriptors: BTreeMap::new(),
+            tags: HashMap::new(),
+            restored: VecDeque::new(),
+            limiter: ConnectionLimiter::new(limits),
+            authenticated: HashSet::new(),
+            input_limits,
+            inputs: HashMap::new(),
+            bans: BanList::default(),
+            keepalive,
+            activity: HashMap::new(),
+            metrics_servers: Vec::new(),
+            monitors: Monitors::default(),
+            recordings: None,
+            admin_commands: HashMap::new(),
+            next_admin_command_id: 1,
+            shut_down: false,
+            // 0 is left free for C to mean "no descriptor"
+            next_id: 1,
+        }
+    }
+
+    pub fn manager(&self) -> &dyn DescriptorManager {
+        self.manager.as_ref()
+    }
+
+    /// Keeps `server` running until the registry is shut down
+    pub fn serve_metrics(&mut self, server: MetricsServer) {
+        self.metrics_servers.push(server);
+    }
+
+    /// The next admin command for the game to run, and the ID to answer it by with
+    /// `reply_admin_command`
+    pub fn next_admin_command(&mut self) -> Option<(u64, String)> {
+        let command = self.manager.next_admin_command()?;
+        let id = self.next_admin_command_id;
+        self.next_admin_command_id += 1;
+        let text = command.command.clone();
+        self.admin_commands.insert(id, command);
+        Some((id, text))
+    }
+
+    pub fn reply_admin_command(&mut self, id: u64, reply: &str) -> Result<(), std::io::Error> {
+        self.admin_commands
+            .remove(&id)
+            .ok_or_else(|| {
+                std::io::Error::new(ErrorKind::NotFound, format!("no admin command {}", id))
+            })?
+            .reply(reply)
+    }
+
+    /// Every registered descriptor, for admins to watch
+    pub fn monitors(&self) -> Monitors {
+        self.monitors.clone()
+    }
+
+    /// Records the sessions of descriptors registered from now on into `dir`, creating it if need
+    /// be (see `RecordedDescriptor`). Only the game's user can read what's in a new `dir`.
+    pub fn record_sessions(&mut self, dir: PathBuf) -> Result<(), std::io::Error> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+        self.recordings = Some(dir);
+        Ok(())
+    }
+
+    /// Registers the manager's next new descriptor, if it has one waiting. Descriptors from sites
+    /// banned outright or over the `ConnectionLimits` are refused and closed, never reaching the
+    /// caller.
+    pub fn accept(
+        &mut self,
+    ) -> Result<Option<DescriptorId>, Box<dyn std::error::Error + Send + Sync>> {
+        if self.shut_down {
+            return Ok(None);
+        }
+        loop {
+            match self.manager.new_descriptor() {
+                Ok(descriptor) => {
+                    if let Some(descriptor) = self.admit(descriptor) {
+                        return Ok(Some(self.insert(descriptor)));
+                    }
+                }
+                Err(e) if no_new_descriptor(e.as_ref()) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Checks a new descriptor against the bans and `ConnectionLimits`, refusing it (with a
+    /// message if it's only over the limits) if it must be
+    fn admit(&mut self, mut descriptor: Box<dyn Descriptor>) -> Option<Box<dyn Descriptor>> {
+        let ip = descriptor.get_ip();
+        if self
+            .bans
+            .check(descriptor.get_hostname(), ip, crate::ban::now())
+            == BanType::All
+        {
+            warn!(
+                "Connection attempt denied from [{}]",
+                descriptor.get_hostname()
+            );
+            metrics().refused(descriptor.get_type());
+            return None;
+        }
+        let open_from_ip = match ip {
+            Some(ip) => self
+                .descriptors
+                .values()
+                .filter(|open| open.get_ip() == Some(ip))
+                .count(),
+            None => 0,
+        };
+        let unauthenticated = self.descriptors.len() - self.authenticated.len();
+        match self
+            .limiter
+            .admit(ip, open_from_ip, unauthenticated, Instant::now())
+        {
+            Ok(()) => Some(descriptor),
+            Err(reason) => {
+                warn!(
+                    "Refusing connection from {}: {}",
+                    descriptor.get_hostname(),
+                    reason
+                );
+                // Closing it matters more than whether the message got through
+                let _ = descriptor.write_all(REFUSED_MESSAGE.as_bytes());
+                metrics().refused(descriptor.get_type());
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, descriptor: Box<dyn Descriptor>) -> DescriptorId {
+        let id = DescriptorId(self.next_id);
+        self.next_id += 1;
+        self.register(id, descriptor);
+        id
+    }
+
+    fn register(&mut self, id: DescriptorId, mut descriptor: Box<dyn Descriptor>) {
+        if let Some(dir) = &self.recordings {
+            match Recording::create(dir, id, descriptor.as_ref()) {
+                Ok(recording) => {
+                    descriptor = Box::new(RecordedDescriptor::new(descriptor, recording))
+                }
+                Err(e) => warn!("Cannot record descriptor {}: {}", id, e),
+            }
+        }
+        let descriptor = Box::new(MonitoredDescriptor::new(
+            descriptor,
+            id,
+            self.monitors.clone(),
+        ));
+        if let Some(keepalive) = &self.keepalive {
+            if let Err(e) = descriptor.set_keepalive(keepalive) {
+                warn!("Cannot set keepalive on descriptor {}: {}", id, e);
+            }
+        }
+        metrics().opened(descriptor.get_type());
+        let now = Instant::now();
+        self.activity.insert(
+            id,
+            Activity {
+                connected: now,
+                last_input: now,
+            },
+        );
+        self.descriptors.insert(id, descriptor);
+    }
+
+    pub fn get(&self, id: DescriptorId) -> Result<&dyn Descriptor, std::io::Error> {
+        self.descriptors
+            .get(&id)
+            .map(Box::as_ref)
+            .ok_or_else(|| not_found(id))
+    }
+
+    pub fn get_mut(
+        &mut self,
+        id: DescriptorId,
+    ) -> Result<&mut Box<dyn Descriptor>, std::io::Error> {
+        self.descriptors.get_mut(&id).ok_or_else(|| not_found(id))
+    }
+
+    /// Closes the descriptor by dropping it, after which its ID is never valid again
+    pub fn close(&mut self, id: DescriptorId) -> Result<(), std::io::Error> {
+        self.tags.remove(&id);
+        self.authenticated.remove(&id);
+        self.activity.remove(&id);
+        if let Some(input) = self.inputs.remove(&id) {
+            let stats = input.stats();
+            if stats.throttled > 0 || stats.truncated > 0 || stats.dropped > 0 {
+                info!(
+                    "Descriptor {} sent {} bytes in {} lines, was throttled {} times and had {} \
+                     lines truncated and {} dropped",
+                    id, stats.bytes, stats.lines, stats.throttled, stats.truncated, stats.dropped
+                );
+            }
+        }
+        self.descriptors
+            .remove(&id)
+            .map(|descriptor| metrics().closed(descriptor.get_type()))
+            .ok_or_else(|| not_found(id))
+    }
+
+    /// Writes as much of `bytes` as the descriptor can take right now
+    pub fn write(&mut self, id: DescriptorId, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        let descriptor = self.get_mut(id)?;
+        match descriptor.write(bytes) {
+            Ok(written) => {
+                metrics().written(descriptor.get_type(), written);
+                Ok(written)
+            }
+            Err(e) => {
+                if e.kind() != ErrorKind::WouldBlock {
+                    metrics().write_failed(descriptor.get_type());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads a descriptor's input into `buf` as its `InputLimits` allow, returning how many bytes
+    /// (0 if there are none ready yet). Lines are only ever handed over whole, up to `buf`'s size.
+    pub fn read(&mut self, id: DescriptorId, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let descriptor = self.descriptors.get_mut(&id).ok_or_else(|| not_found(id))?;
+        let activity = self.activity.get_mut(&id).ok_or_else(|| not_found(id))?;
+        if let Some(timeout) = self.limiter.login_timeout() {
+            if !self.authenticated.contains(&id) && activity.connected.elapsed() > timeout {
+                // Closing it matters more than whether the message got through
+                let _ = descriptor.write_all(LOGIN_TIMEOUT_MESSAGE.as_bytes());
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("not logged in after {:?}", timeout),
+                ));
+            }
+        }
+        let input = self
+            .inputs
+            .entry(id)
+            .or_insert_with(|| InputFilter::new(&self.input_limits, Instant::now()));
+        // Held back input is released before any more is read, so a flooding client is left
+        // waiting on its own socket
+        if !input.has_pending() {
+            let mut raw = [0; 4096];
+            let len = buf.len().min(raw.len());
+            let read = descriptor.read(&mut raw[..len]).inspect_err(|e| {
+                if e.kind() != ErrorKind::WouldBlock {
+                    metrics().read_failed(descriptor.get_type());
+                }
+            })?;
+            // Transports say when there's nothing to read yet with `WouldBlock`, so reading
+            // nothing into room for it is the client hanging up
+            if read == 0 && len > 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "client hung up",
+                ));
+            }
+            if read > 0 {
+                activity.last_input = Instant::now();
+                metrics().read(descriptor.get_type(), read);
+            }
+            if input.feed(&raw[..read])? > 0 {
+                descriptor.write_all(OVERLONG_MESSAGE.as_bytes())?;
+            }
+        }
+        Ok(input.release(buf, Instant::now()))
+    }
+
+    /// How long since a descriptor last sent anything (or connected, if it never has)
+    pub fn idle_time(&self, id: DescriptorId) -> Result<Duration, std::io::Error> {
+        self.activity
+            .get(&id)
+            .map(|activity| activity.last_input.elapsed())
+            .ok_or_else(|| not_found(id))
+    }
+
+    /// How much a descriptor has sent, and how often it went over its `InputLimits`
+    pub fn input_stats(&self, id: DescriptorId) -> Result<InputStats, std::io::Error> {
+        if !self.descriptors.contains_key(&id) {
+            return Err(not_found(id));
+        }
+        Ok(self
+            .inputs
+            .get(&id)
+            .map(InputFilter::stats)
+            .unwrap_or_default())
+    }
+
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    /// The strictest ban on the site a descriptor is connected from
+    pub fn ban_on(&self, id: DescriptorId) -> Result<BanType, std::io::Error> {
+        let descriptor = self.get(id)?;
+        Ok(self.bans.check(
+            descriptor.get_hostname(),
+            descriptor.get_ip(),
+            crate::ban::now(),
+        ))
+    }
+
+    /// Tags (or with `None`, untags) a descriptor with whatever the game needs to find its place
+    /// again after a copyover (ie its character's name)
+    pub fn tag(&mut self, id: DescriptorId, tag: Option<String>) -> Result<(), std::io::Error> {
+        if !self.descriptors.contains_key(&id) {
+            return Err(not_found(id));
+        }
+        match tag {
+            Some(tag) => self.tags.insert(id, tag),
+            None => self.tags.remove(&id),
+        };
+        Ok(())
+    }
+
+    pub fn get_tag(&self, id: DescriptorId) -> Option<&str> {
+        self.tags.get(&id).map(String::as_str)
+    }
+
+    /// Marks a descriptor as logged in, so it no longer counts against the limit on
+    /// unauthenticated connections
+    pub fn mark_authenticated(&mut self, id: DescriptorId) -> Result<(), std::io::Error> {
+        if !self.descriptors.contains_key(&id) {
+            return Err(not_found(id));
+        }
+        self.authenticated.insert(id);
+        Ok(())
+    }
+
+    /// Saves every descriptor that can outlive the process to `state_file`, then replaces the
+    /// process with `command`, which restores them under the same IDs. Descriptors that can't be
+    /// saved are left to be closed by the exec. Only returns if the copyover failed.
+    pub fn exec_copyover(&self, state_file: &Path, command: &mut Command) -> std::io::Error {
+        let state = self.copyover_state();
+        if let Err(e) = state.save(state_file) {
+            return e;
+        }
+        match self.manager.save_listener() {
+            Ok(Some(fd)) => {
+                command.env(LISTEN_FD_ENV, fd.to_string());
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Cannot keep listener open for copyover: {}", e),
+        }
+        info!(
+            "Copyover saved {} descriptors to {:?}, executing {:?}",
+            state.descriptors.len(),
+            state_file,
+            command
+        );
+        command.env(COPYOVER_ENV, state_file).exec()
+    }
+
+    fn copyover_state(&self) -> CopyoverState {
+        let mut descriptors = Vec::new();
+        for (id, descriptor) in &self.descriptors {
+            match descriptor.save() {
+                Ok(saved) => descriptors.push(CopyoverEntry {
+                    id: id.0,
+                    tag: self.tags.get(id).cloned(),
+                    descriptor: saved,
+                }),
+                Err(e) => warn!("Cannot save descriptor {} for copyover: {}", id, e),
+            }
+        }
+        CopyoverState {
+            next_id: self.next_id,
+            descriptors,
+        }
+    }
+
+    /// Restores the descriptors a copyover saved under their old IDs, queueing them for
+    /// `next_restored` so the game can reattach them
+    pub fn restore(&mut self, state: CopyoverState) {
+        self.next_id = self.next_id.max(state.next_id);
+        for entry in state.descriptors {
+            let id = DescriptorId(entry.id);
+            match self.manager.restore_descriptor(entry.descriptor) {
+                Ok(descriptor) => {
+                    self.register(id, descriptor);
+                    if let Some(tag) = entry.tag {
+                        self.tags.insert(id, tag);
+                    }
+                    // Restored descriptors were all logged in, any others were closed
+                    self.authenticated.insert(id);
+                    self.restored.push_back(id);
+                }
+                Err(e) => error!("Cannot restore descriptor {}: {}", id, e),
+            }
+        }
+        info!(
+            "Restored {} descriptors after copyover",
+            self.restored.len()
+        );
+    }
+
+    /// The next descriptor restored by a copyover that the game hasn't reattached yet
+    pub fn next_restored(&mut self) -> Option<DescriptorId> {
+        self.restored.pop_front()
+    }
+
+    /// Every open descriptor's ID, oldest first
+    pub fn ids(&self) -> impl Iterator<Item = DescriptorId> + '_ {
+        self.descriptors.keys().copied()
+    }
+
+    /// Stops accepting connections and ends the manager's background work, then sends every open
+    /// descriptor `message` and waits for their clients to hang up, giving up on any still going
+    /// after `timeout`. The descriptors themselves are left for the caller to close as usual.
+    pub fn shutdown(&mut self, message: &str, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        self.shut_down = true;
+        self.manager.shutdown(deadline);
+        // Watches would otherwise keep the server they're streamed from running
+        self.monitors.close_all();
+        for server in self.metrics_servers.iter_mut() {
+            server.shutdown(deadline);
+        }
+
+        let mut finishing = Vec::new();
+        for (id, descriptor) in self.descriptors.iter_mut() {
+            if let Err(e) = write_before(descriptor.as_mut(), message.as_bytes(), deadline) {
+                warn!("Cannot say goodbye to descriptor {}: {}", id, e);
+                continue;
+            }
+            match descriptor.finish() {
+                Ok(true) => finishing.push(*id),
+                Ok(false) => {}
+                Err(e) => warn!("Cannot finish descriptor {}: {}", id, e),
+            }
+        }
+
+        // Their input is read (and ignored) until they hang up, so closing them doesn't throw
+        // away output still on its way
+        let mut buf = [0; 4096];
+        let descriptors = &mut self.descriptors;
+        let finished = wait_until(deadline, || {
+            finishing.retain(
+                |id| match descriptors.get_mut(id).map(|d| d.read(&mut buf)) {
+                    Some(Ok(0)) | None => false,
+                    Some(Ok(_)) => true,
+                    Some(Err(e)) => e.kind() == ErrorKind::WouldBlock,
+                },
+            );
+            finishing.is_empty()
+        });
+        if !finished {
+            warn!(
+                "{} descriptors still connected after {:?}",
+                finishing.len(),
+                timeout
+            );
+        }
+    }
+}
+
+/// Writes all of `bytes`, waiting until `deadline` for room to when the descriptor can't take
+/// them yet
+fn write_before(
+    descriptor: &mut dyn Descriptor,
+    mut bytes: &[u8],
+    deadline: Instant,
+) -> Result<(), std::io::Error> {
+    let mut result = Ok(());
+    let written = wait_until(deadline, || match descriptor.write(bytes) {
+        Ok(0) => {
+            result = Err(ErrorKind::WriteZero.into());
+            true
+        }
+        Ok(written) => {
+            bytes = &bytes[written..];
+            bytes.is_empty()
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+        Err(e) => {
+            result = Err(e);
+            true
+        }
+    });
+    if !written {
+        return Err(ErrorKind::TimedOut.into());
+    }
+    result
+}
+
+fn not_found(id: DescriptorId) -> std::io::Error {
+    std::io::Error::new(ErrorKind::NotFound, format!("no descriptor {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::copyover::SavedDescriptor;
+    use crate::input::OverlongLines;
+    use crate::loopback::LoopbackDescriptorManager;
+
+    use super::*;
+
+    struct FakeDescriptor;
+
+    impl Descriptor for FakeDescriptor {
+        fn get_hostname(&self) -> &str {
+            "fake"
+        }
+
+        fn get_type(&self) -> &'static str {
+            "FAKE"
+        }
+
+        fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+            Ok(SavedDescriptor::Telnet {
+                fd: -1,
+                hostname: "fake".to_owned(),
+                ip: None,
+                input: Default::default(),
+            })
+        }
+    }
+
+    impl Read for FakeDescriptor {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for FakeDescriptor {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Hands out one `FakeDescriptor` per call to `new_descriptor`, until `pending` runs out
+    struct FakeManager {
+        pending: std::cell::Cell<usize>,
+    }
+
+    impl DescriptorManager for FakeManager {
+        fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn new_descriptor(
+            &self,
+        ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
+            match self.pending.get() {
+                0 => Err(Box::new(std::io::Error::from(ErrorKind::WouldBlock))),
+                pending => {
+                    self.pending.set(pending - 1);
+                    Ok(Box::new(FakeDescriptor))
+                }
+            }
+        }
+
+        fn restore_descriptor(
+            &self,
+            _saved: SavedDescriptor,
+        ) -> Result<Box<dyn Descriptor>, std::io::Error> {
+            Ok(Box::new(FakeDescriptor))
+        }
+    }
+
+    fn registry(pending: usize) -> DescriptorRegistry {
+        limited_registry(pending, ConnectionLimits::default())
+    }
+
+    fn limited_registry(pending: usize, limits: ConnectionLimits) -> DescriptorRegistry {
+        DescriptorRegistry::new(
+            Box::new(FakeManager {
+                pending: std::cell::Cell::new(pending),
+            }),
+            limits,
+            InputLimits::default(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_ids_are_never_reused() {
+        let mut registry = registry(3);
+        let first = registry.accept().expect("accepted").expect("a descriptor");
+        let second = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(DescriptorId(1), first);
+        assert_eq!(DescriptorId(2), second);
+
+        registry.close(first).expect("first closed");
+        let third = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(DescriptorId(3), third);
+        assert_eq!(vec![second, third], registry.ids().collect::<Vec<_>>());
+        assert!(registry.accept().expect("nothing waiting").is_none());
+    }
+
+    #[test]
+    fn test_shutdown_stops_accepting() {
+        let mut registry = registry(2);
+        let id = registry.accept().expect("accepted").expect("a descriptor");
+
+        registry.shutdown("Goodbye!\r\n", Duration::from_secs(5));
+        assert!(registry.accept().expect("nothing accepted").is_none());
+        // Closing what's open is still left to the game
+        registry.close(id).expect("closed");
+    }
+
+    #[test]
+    fn test_closed_ids_are_not_found() {
+        let mut registry = registry(1);
+        let id = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(
+            "FAKE",
+            registry.get(id).expect("open descriptor").get_type()
+        );
+
+        registry.close(id).expect("closed");
+        assert_eq!(
+            ErrorKind::NotFound,
+            registry.get_mut(id).err().unwrap().kind()
+        );
+        assert_eq!(ErrorKind::NotFound, registry.close(id).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_recordings_are_kept_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let parent = tempdir::TempDir::new("recordings").expect("temporary directory");
+        let dir = parent.path().join("sessions");
+        let mut registry = registry(1);
+        registry.record_sessions(dir.clone()).expect("recording");
+        registry.accept().expect("accepted").expect("a descriptor");
+
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(0o700, mode & 0o777);
+        assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    }
+
+    #[test]
+    fn test_unauthenticated_descriptors_are_limited() {
+        let mut registry = limited_registry(
+            4,
+            ConnectionLimits {
+                unauthenticated: Some(2),
+                ..Default::default()
+            },
+        );
+        let first = registry.accept().expect("accepted").expect("a descriptor");
+        registry.accept().expect("accepted").expect("a descriptor");
+        registry.mark_authenticated(first).expect("first logged in");
+        let third = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(DescriptorId(3), third);
+
+        // The fourth is refused and closed, leaving nothing waiting
+        assert!(registry.accept().expect("nothing waiting").is_none());
+        assert_eq!(3, registry.ids().count());
+        assert_eq!(
+            ErrorKind::NotFound,
+            registry
+                .mark_authenticated(DescriptorId(4))
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn test_logins_time_out() {
+        let mut registry = limited_registry(
+            2,
+            ConnectionLimits {
+                login_timeout: Some(Duration::ZERO),
+                ..Default::default()
+            },
+        );
+        let first = registry.accept().expect("accepted").expect("a descriptor");
+        let second = registry.accept().expect("accepted").expect("a descriptor");
+        registry.mark_authenticated(first).expect("first logged in");
+        std::thread::sleep(Duration::from_millis(1));
+
+        let mut buf = [0; 16];
+        assert_eq!(
+            ErrorKind::WouldBlock,
+            registry.read(first, &mut buf).unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::TimedOut,
+            registry.read(second, &mut buf).unwrap_err().kind()
+        );
+        assert!(registry.idle_time(first).expect("open descriptor") > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_sites_banned_outright_are_refused() {
+        let mut registry = registry(2);
+        let ban = |kind, site: &str| crate::ban::Ban {
+            kind,
+            site: site.parse().expect("valid site"),
+            date: 0,
+            name: "Foo".to_owned(),
+            expires: None,
+        };
+        let now = crate::ban::now();
+        registry
+            .bans_mut()
+            .add(ban(BanType::New, "fake"), now)
+            .expect("banned");
+        let id = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(BanType::New, registry.ban_on(id).expect("open descriptor"));
+
+        registry.bans_mut().clear();
+        registry
+            .bans_mut()
+            .add(ban(BanType::All, "fak?"), now)
+            .expect("banned");
+        assert!(registry.accept().expect("nothing waiting").is_none());
+    }
+
+    #[test]
+    fn test_copyover_restores_ids_and_tags() {
+        let mut previous = registry(3);
+        let first = previous.accept().expect("accepted").expect("a descriptor");
+        let second = previous.accept().expect("accepted").expect("a descriptor");
+        let third = previous.accept().expect("accepted").expect("a descriptor");
+        previous.close(second).expect("second closed");
+        previous
+            .tag(third, Some("Foo".to_owned()))
+            .expect("third tagged");
+
+        let mut restored = registry(1);
+        restored.restore(previous.copyover_state());
+        assert_eq!(Some(first), restored.next_restored());
+        assert_eq!(Some(third), restored.next_restored());
+        assert_eq!(None, restored.next_restored());
+        assert_eq!(None, restored.get_tag(first));
+        assert_eq!(Some("Foo"), restored.get_tag(third));
+
+        let fourth = restored.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(DescriptorId(4), fourth);
+    }
+
+    #[test]
+    fn test_loopback_clients_talk_through_the_registry() {
+        let timeout = Duration::from_secs(5);
+        let (manager, connector) = LoopbackDescriptorManager::new();
+        let mut registry = DescriptorRegistry::new(
+            Box::new(manager),
+            ConnectionLimits::default(),
+            InputLimits {
+                max_line_length: Some(4),
+                overlong_lines: OverlongLines::Drop,
+                ..Default::default()
+            },
+            None,
+        );
+        let mut client = connector.connect("player.example.com").expect("connected");
+        registry
+            .manager()
+            .block_until_descriptor()
+            .expect("a client");
+        let id = registry.accept().expect("accepted").expect("a descriptor");
+        assert_eq!(
+            "player.example.com",
+            registry.get(id).unwrap().get_hostname()
+        );
+
+        registry.write(id, b"By what name? ").expect("written");
+        assert_eq!(
+            b"By what name? ",
+            &client.expect(b"? ", timeout).unwrap()[..]
+        );
+        client.send(b"Fo").unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(0, registry.read(id, &mut buf).expect("half a line"));
+        client.send(b"o\r\nnorthward\r\n").unwrap();
+        let read = registry.read(id, &mut buf).expect("a line");
+        assert_eq!(b"Foo\r\n", &buf[..read]);
+        assert_eq!(
+            OVERLONG_MESSAGE.as_bytes(),
+            &client.expect(b"\r\n", timeout).unwrap()[..]
+        );
+
+        // A client hanging up is an error reading from it, and closing the descriptor hangs up on
+        // a client
+        drop(client);
+        assert_eq!(
+            ErrorKind::UnexpectedEof,
+            registry.read(id, &mut buf).unwrap_err().kind()
+        );
+        let mut client = connector.connect("player.example.com").expect("connected");
+        let id = registry.accept().expect("accepted").expect("a descriptor");
+        registry.write(id, b"Goodbye.\r\n").expect("written");
+        registry.close(id).expect("closed");
+        assert_eq!(b"Goodbye.\r\n", &client.expect_closed(timeout).unwrap()[..]);
+    }
+}
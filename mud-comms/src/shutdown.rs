@@ -0,0 +1,46 @@
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often to check on something being waited for during a shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Waits until `done` returns true, giving up at `deadline`. Returns whether it finished.
+pub fn wait_until(deadline: Instant, mut done: impl FnMut() -> bool) -> bool {
+    loop {
+        if done() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Joins `thread` if it finishes by `deadline`, otherwise leaves it to end with the process and
+/// returns `None`
+pub fn join_before<T>(thread: JoinHandle<T>, deadline: Instant) -> Option<std::thread::Result<T>> {
+    if !wait_until(deadline, || thread.is_finished()) {
+        return None;
+    }
+    Some(thread.join())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threads_are_only_waited_for_until_the_deadline() {
+        let quick = std::thread::spawn(|| ());
+        assert!(join_before(quick, Instant::now() + Duration::from_millis(500)).is_some());
+
+        let (_stop, stopped) = crossbeam_channel::bounded::<()>(0);
+        let stuck = std::thread::spawn(move || stopped.recv());
+        let start = Instant::now();
+        assert!(join_before(stuck, start + Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crossbeam_channel::Receiver;
+use log::*;
+use slack_morphism_models::SlackChannelId;
+
+use crate::descriptor::ChannelMessage;
+use crate::slack::Poster;
+
+/// Bindings between Slack channels and game targets (`room:<vnum>` or a chat channel like
+/// `gossip`), stored one `<slack channel id> <target>` pair per line.
+pub struct ChannelBindings {
+    bindings: Vec<(SlackChannelId, String)>,
+}
+
+impl ChannelBindings {
+    /// Loads the bindings stored at `path`, leaving channel mode off if the file doesn't exist
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let mut bindings = Vec::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    match line.split_once(char::is_whitespace) {
+                        Some((channel, target)) => bindings
+                            .push((SlackChannelId(channel.to_owned()), target.trim().to_owned())),
+                        None => warn!("Ignoring Slack channel binding without a target: {}", line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        info!(
+            "Loaded {} Slack channel bindings from {:?}",
+            bindings.len(),
+            path
+        );
+        Ok(ChannelBindings { bindings })
+    }
+
+    /// The game target that `channel` is bound to
+    pub fn target(&self, channel: &SlackChannelId) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| bound == channel)
+            .map(|(_, target)| target.as_str())
+    }
+
+    /// The Slack channels that mirror `target`
+    pub fn channels<'a>(&'a self, target: &'a str) -> impl Iterator<Item = &'a SlackChannelId> {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| bound == target)
+            .map(|(channel, _)| channel)
+    }
+}
+
+/// The game's side of channel mode: posts mirrored output to bound Slack channels and hands
+/// back what linked characters said in them.
+pub(crate) struct SlackChannels {
+    bindings: Arc<ChannelBindings>,
+    posts: Poster,
+    messages: Receiver<ChannelMessage>,
+}
+
+impl SlackChannels {
+    /// `posts` are sent from their own thread so mirroring doesn't hold up the game loop
+    pub(crate) fn new(
+        bindings: Arc<ChannelBindings>,
+        posts: Poster,
+        messages: Receiver<ChannelMessage>,
+    ) -> Self {
+        SlackChannels {
+            bindings,
+            posts,
+            messages,
+        }
+    }
+
+    pub(crate) fn mirror(&self, target: &str, text: &str) {
+        for channel in self.bindings.channels(target) {
+            if self.posts.post(channel.clone(), text.to_owned()).is_err() {
+                error!("Cannot mirror to {:?}: Slack poster has stopped", channel);
+            }
+        }
+    }
+
+    pub(crate) fn next_message(&self) -> Option<ChannelMessage> {
+        self.messages.try_recv().ok()
+    }
+
+    /// Waits until everything posted by any session so far has been sent, giving up at
+    /// `deadline`. Returns whether it was.
+    pub(crate) fn wait_until_posted(&self, deadline: Instant) -> bool {
+        self.posts.wait_until_sent(deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_bindings_map_channels_and_targets() {
+        let dir = TempDir::new("slack_channels").expect("temporary directory");
+        let path = dir.path().join("channels");
+        let mut file = File::create(&path).expect("bindings file");
+        writeln!(
+            file,
+            "# Midgaard temple square\nC0001 room:3001\nC0002 gossip\n\nC0003 gossip"
+        )
+        .expect("bindings written");
+
+        let bindings = ChannelBindings::load(&path).expect("bindings to load");
+        assert_eq!(
+            Some("room:3001"),
+            bindings.target(&SlackChannelId("C0001".to_owned()))
+        );
+        assert_eq!(None, bindings.target(&SlackChannelId("C0004".to_owned())));
+        let gossip: Vec<_> = bindings.channels("gossip").map(|c| c.0.as_str()).collect();
+        assert_eq!(vec!["C0002", "C0003"], gossip);
+        assert_eq!(0, bindings.channels("auction").count());
+    }
+
+    #[test]
+    fn test_missing_bindings_file_disables_channels() {
+        let dir = TempDir::new("slack_channels").expect("temporary directory");
+        let bindings = ChannelBindings::load(dir.path().join("channels")).expect("no bindings");
+        assert_eq!(0, bindings.channels("gossip").count());
+    }
+}
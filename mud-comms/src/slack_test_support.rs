@@ -0,0 +1,47 @@
+//! What the tests of both Slack transports need to start a manager and talk to its sessions
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use tempdir::TempDir;
+
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+use crate::identity::IdentityLinks;
+use crate::slack_channels::ChannelBindings;
+
+/// Empty identity links and the channel `bindings` (in the file's format), kept in a temporary
+/// directory that lasts as long as the `TempDir` does
+pub fn links_and_bindings(bindings: &str) -> (IdentityLinks, ChannelBindings, TempDir) {
+    let links_dir = TempDir::new("slack_links").expect("temporary directory");
+    let links = IdentityLinks::load(links_dir.path().join("links")).expect("empty links to load");
+    std::fs::write(links_dir.path().join("channels"), bindings).expect("channel bindings written");
+    let bindings =
+        ChannelBindings::load(links_dir.path().join("channels")).expect("bindings to load");
+    (links, bindings, links_dir)
+}
+
+pub fn wait_for_descriptor(manager: &dyn DescriptorManager) -> Box<dyn Descriptor> {
+    let started = Instant::now();
+    loop {
+        if let Ok(descriptor) = manager.new_descriptor() {
+            return descriptor;
+        }
+        if started.elapsed() > Duration::from_secs(5) {
+            panic!("no descriptor was created");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// What's waiting to be read, if anything
+pub fn read_input(descriptor: &mut Box<dyn Descriptor>) -> String {
+    let mut buffer = [0; 512];
+    match descriptor.read(&mut buffer) {
+        Ok(read) => String::from_utf8_lossy(&buffer[..read]).into_owned(),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => String::new(),
+        Err(e) => panic!("read failed: {}", e),
+    }
+}
@@ -3,9 +3,21 @@ use std::io::Read;
 use std::io::Write;
 use std::mem;
 use std::mem::size_of;
+use std::mem::ManuallyDrop;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Instant;
 
+use crossbeam_channel::Receiver;
+use crossbeam_channel::Sender;
 use libc::accept;
 use libc::bind;
 use libc::c_int;
@@ -13,11 +25,14 @@ use libc::c_void;
 use libc::close;
 use libc::fcntl;
 use libc::fd_set;
+use libc::getsockname;
 use libc::in_addr;
 use libc::listen;
 use libc::read;
 use libc::select;
 use libc::send;
+use libc::setsockopt;
+use libc::shutdown;
 use libc::sockaddr;
 use libc::sockaddr_in;
 use libc::socket;
@@ -30,13 +45,34 @@ use libc::F_SETFL;
 use libc::INADDR_ANY;
 use libc::O_NONBLOCK;
 use libc::PF_INET;
+use libc::SHUT_WR;
+use libc::SOCK_CLOEXEC;
 use libc::SOCK_STREAM;
+use libc::SOL_SOCKET;
+use libc::SO_REUSEADDR;
+use log::error;
 
+use crate::copyover::keep_across_exec;
+use crate::copyover::reclaim_after_exec;
+use crate::copyover::SavedDescriptor;
 use crate::descriptor::Descriptor;
 use crate::descriptor::DescriptorManager;
+use crate::keepalive::set_keepalive;
+use crate::keepalive::Keepalive;
+use crate::listener::inherited_listener;
+use crate::proxy;
+use crate::proxy::TrustedProxies;
+use crate::telnet::TelnetInput;
 
 pub struct SocketDescriptorManager {
     pub(crate) socket: c_int,
+    proxies: TrustedProxies,
+    /// Connections from trusted proxies, with their clients, once their headers have been read
+    proxied_sender: Sender<(TcpStream, IpAddr)>,
+    proxied: Receiver<(TcpStream, IpAddr)>,
+    /// Sent a byte ahead of each proxied connection, so `block_until_descriptor` wakes for it
+    proxied_ready_sender: Arc<UnixStream>,
+    proxied_ready: UnixStream,
 }
 
 // byte ordering helpers from sys::common::net
@@ -53,11 +89,157 @@ pub fn ntohl(u: u32) -> u32 {
 }
 
 impl SocketDescriptorManager {
-    pub(crate) fn new(listen_port: u16) -> Result<SocketDescriptorManager, std::io::Error> {
+    /// Listens on `listen_port`, unless the process inherited a listening socket to use instead.
+    /// Connections from `proxies` are taken to be from the client named by their PROXY header.
+    pub(crate) fn new(
+        listen_port: u16,
+        proxies: TrustedProxies,
+    ) -> Result<SocketDescriptorManager, std::io::Error> {
+        if let Some(fd) = inherited_listener()? {
+            // SAFETY: the inherited socket was handed over for this manager alone to own
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            return SocketDescriptorManager::with_listener(listener, proxies);
+        }
+        let socket = SocketDescriptorManager::bind(listen_port)?;
+        SocketDescriptorManager::with_socket(socket, proxies)
+    }
+
+    /// Accepts connections from an already listening `listener`
+    pub(crate) fn with_listener(
+        listener: TcpListener,
+        proxies: TrustedProxies,
+    ) -> Result<SocketDescriptorManager, std::io::Error> {
+        let socket = SocketDescriptorManager::check_listener(listener.into_raw_fd())?;
+        SocketDescriptorManager::with_socket(socket, proxies)
+    }
+
+    fn with_socket(
+        socket: c_int,
+        proxies: TrustedProxies,
+    ) -> Result<SocketDescriptorManager, std::io::Error> {
+        let (proxied_sender, proxied) = crossbeam_channel::unbounded();
+        let (proxied_ready_sender, proxied_ready) = UnixStream::pair()?;
+        proxied_ready.set_nonblocking(true)?;
+        Ok(SocketDescriptorManager {
+            socket,
+            proxies,
+            proxied_sender,
+            proxied,
+            proxied_ready_sender: Arc::new(proxied_ready_sender),
+            proxied_ready,
+        })
+    }
+
+    /// Reads the PROXY header of a connection from a trusted proxy in the background, queueing
+    /// the connection for `new_descriptor` once it's read
+    fn read_proxy_header(&self, stream: TcpStream, proxy: IpAddr) {
+        let sender = self.proxied_sender.clone();
+        let ready = Arc::clone(&self.proxied_ready_sender);
+        proxy::hand_over_once_read(stream, proxy, move |stream, client| {
+            // The byte goes first, so there's always one to take with the connection
+            if let Err(e) = (&*ready).write_all(&[0]) {
+                error!("Cannot queue proxied connection: {}", e);
+                return;
+            }
+            // The manager is gone if this fails, and the connection with it
+            let _ = sender.send((stream, client));
+        });
+    }
+
+    /// A descriptor for a connection from `ip`, named by its reverse DNS where it has one
+    fn descriptor(file_descriptor: c_int, ip: IpAddr) -> SocketDescriptor {
+        let hostname = dns_lookup::lookup_addr(&ip)
+            .or::<std::io::Error>(Ok(ip.to_string()))
+            .expect("lookup with ip fallback to be infallible");
+        SocketDescriptor {
+            file_descriptor,
+            hostname,
+            ip,
+            telnet: TelnetInput::default(),
+        }
+    }
+
+    /// Accepts a connection waiting on the listener, returning its socket and peer
+    fn accept(&self) -> Result<(c_int, IpAddr), std::io::Error> {
         unsafe {
-            let s = socket(PF_INET, SOCK_STREAM, 0);
+            // Maybe use FD_ZERO?
+            let mut input_set: fd_set = mem::zeroed();
+            let mut output_set: fd_set = mem::zeroed();
+            let mut exc_set: fd_set = mem::zeroed();
+            let mut timeout: timeval = mem::zeroed();
+            FD_SET(self.socket, &mut input_set as *mut fd_set);
+            if select(
+                self.socket + 1,
+                &mut input_set as *mut fd_set,
+                &mut output_set as *mut fd_set,
+                &mut exc_set as *mut fd_set,
+                &mut timeout as *mut timeval,
+            ) < 0
+            {
+                return Err(std::io::Error::other("libc::select failed"));
+            }
+
+            if !FD_ISSET(self.socket, &input_set as *const fd_set) {
+                return Err(std::io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "select would have blocked",
+                ));
+            }
+
+            // there is a descriptor
+            let mut peer: sockaddr_in = mem::zeroed();
+            let mut peer_len = size_of::<sockaddr_in>();
+            let file_descriptor = accept(
+                self.socket,
+                &mut peer as *mut sockaddr_in as *mut sockaddr,
+                &mut peer_len as *mut usize as *mut u32,
+            );
+            if file_descriptor < 0 {
+                return Err(std::io::Error::other("libc::accept failed"));
+            }
+
+            let ip_addr = IpAddr::V4(Ipv4Addr::from(ntohl(peer.sin_addr.s_addr)));
+            Ok((file_descriptor, ip_addr))
+        }
+    }
+
+    fn check_listener(s: c_int) -> Result<c_int, std::io::Error> {
+        unsafe {
+            // `new_descriptor` only understands IPv4 peers
+            let mut addr: sockaddr = mem::zeroed();
+            let mut addr_len = size_of::<sockaddr>() as u32;
+            if getsockname(s, &mut addr as *mut sockaddr, &mut addr_len) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if c_int::from(addr.sa_family) != AF_INET {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "inherited socket isn't listening for IPv4",
+                ));
+            }
+            set_nonblocking(s)?;
+        }
+        Ok(s)
+    }
+
+    fn bind(listen_port: u16) -> Result<c_int, std::io::Error> {
+        unsafe {
+            // Only descriptors saved for a copyover should outlive an exec, not the listener
+            let s = socket(PF_INET, SOCK_STREAM | SOCK_CLOEXEC, 0);
             if s < 0 {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::socket failed"));
+                return Err(std::io::Error::other("libc::socket failed"));
+            }
+            // Connections restored after a copyover still hold the port
+            let reuse: c_int = 1;
+            if setsockopt(
+                s,
+                SOL_SOCKET,
+                SO_REUSEADDR,
+                &reuse as *const c_int as *const c_void,
+                size_of::<c_int>() as u32,
+            ) < 0
+            {
+                return Err(std::io::Error::other("libc::setsockopt failed"));
             }
             // set_sendbuf
             let addr_in = sockaddr_in {
@@ -74,36 +256,42 @@ impl SocketDescriptorManager {
                 size_of::<sockaddr_in>() as u32,
             ) < 0
             {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::bind failed"));
-            }
-            // set nonblocking
-            let mut flags = fcntl(s, F_GETFL, 0);
-            if flags < 0 {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::fcntl failed"));
-            }
-            flags |= O_NONBLOCK;
-            if fcntl(s, F_SETFL, flags) < 0 {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::fcntl failed"));
+                return Err(std::io::Error::other("libc::bind failed"));
             }
+            set_nonblocking(s)?;
 
             // listen on the socket
             if listen(s, 5) < 0 {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::listen failed"));
+                return Err(std::io::Error::other("libc::listen failed"));
             }
-            Ok(SocketDescriptorManager { socket: s })
+            Ok(s)
         }
     }
 }
 
+unsafe fn set_nonblocking(s: c_int) -> Result<(), std::io::Error> {
+    let mut flags = fcntl(s, F_GETFL, 0);
+    if flags < 0 {
+        return Err(std::io::Error::other("libc::fcntl failed"));
+    }
+    flags |= O_NONBLOCK;
+    if fcntl(s, F_SETFL, flags) < 0 {
+        return Err(std::io::Error::other("libc::fcntl failed"));
+    }
+    Ok(())
+}
+
 impl DescriptorManager for SocketDescriptorManager {
     fn block_until_descriptor(&self) -> Result<(), std::io::Error> {
         unsafe {
             let mut input_set: fd_set = mem::zeroed();
             let mut output_set: fd_set = mem::zeroed();
             let mut exc_set: fd_set = mem::zeroed();
+            let proxied_ready = self.proxied_ready.as_raw_fd();
             FD_SET(self.socket, &mut input_set as *mut fd_set);
+            FD_SET(proxied_ready, &mut input_set as *mut fd_set);
             if select(
-                self.socket + 1,
+                self.socket.max(proxied_ready) + 1,
                 &mut input_set as *mut fd_set,
                 &mut output_set as *mut fd_set,
                 &mut exc_set as *mut fd_set,
@@ -119,64 +307,90 @@ impl DescriptorManager for SocketDescriptorManager {
     fn new_descriptor(
         &self,
     ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
-        unsafe {
-            // Maybe use FD_ZERO?
-            let mut input_set: fd_set = mem::zeroed();
-            let mut output_set: fd_set = mem::zeroed();
-            let mut exc_set: fd_set = mem::zeroed();
-            let mut timeout: timeval = mem::zeroed();
-            FD_SET(self.socket, &mut input_set as *mut fd_set);
-            if select(
-                self.socket + 1,
-                &mut input_set as *mut fd_set,
-                &mut output_set as *mut fd_set,
-                &mut exc_set as *mut fd_set,
-                &mut timeout as *mut timeval,
-            ) < 0
-            {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::Other,
-                    "libc::select failed",
-                )));
+        if let Ok((stream, client)) = self.proxied.try_recv() {
+            // Take the byte sent ahead of it, which is already there
+            let _ = (&self.proxied_ready).read(&mut [0]);
+            // The header was read blocking, and the game mustn't wait on this client
+            if let Err(e) = stream.set_nonblocking(true) {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Err(Box::new(e));
             }
-
-            if !FD_ISSET(self.socket, &input_set as *const fd_set) {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::WouldBlock,
-                    "select would have blocked",
+            return Ok(Box::new(SocketDescriptorManager::descriptor(
+                stream.into_raw_fd(),
+                client,
+            )));
+        }
+        loop {
+            let (file_descriptor, ip) = self.accept()?;
+            if !self.proxies.trusts(ip) {
+                return Ok(Box::new(SocketDescriptorManager::descriptor(
+                    file_descriptor,
+                    ip,
                 )));
             }
+            // SAFETY: the connection was just accepted, so nothing else owns it
+            let stream = unsafe { TcpStream::from_raw_fd(file_descriptor) };
+            self.read_proxy_header(stream, ip);
+        }
+    }
 
-            // there is a descriptor
-            let mut peer: sockaddr_in = mem::zeroed();
-            let mut peer_len = size_of::<sockaddr_in>();
-            let file_descriptor = accept(
-                self.socket,
-                &mut peer as *mut sockaddr_in as *mut sockaddr,
-                &mut peer_len as *mut usize as *mut u32,
-            );
-            if file_descriptor < 0 {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::Other,
-                    "libc::accept failed",
-                )));
+    fn restore_descriptor(
+        &self,
+        saved: SavedDescriptor,
+    ) -> Result<Box<dyn Descriptor>, std::io::Error> {
+        match saved {
+            SavedDescriptor::Telnet {
+                fd,
+                hostname,
+                ip,
+                input,
+            } => {
+                reclaim_after_exec(fd)?;
+                let ip = match ip {
+                    Some(ip) => ip,
+                    None => {
+                        // SAFETY: borrowed only to ask for the peer, the descriptor owns `fd`
+                        let stream = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+                        stream.peer_addr()?.ip()
+                    }
+                };
+                Ok(Box::new(SocketDescriptor {
+                    file_descriptor: fd,
+                    hostname,
+                    ip,
+                    telnet: input,
+                }))
             }
+            other => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("not a socket descriptor: {:?}", other),
+            )),
+        }
+    }
 
-            let ip_addr = IpAddr::V4(Ipv4Addr::from(ntohl(peer.sin_addr.s_addr)));
-            let hostname = dns_lookup::lookup_addr(&ip_addr)
-                .or::<std::io::Error>(Ok(ip_addr.to_string()))
-                .expect("lookup with ip fallback to be infallible");
+    fn save_listener(&self) -> Result<Option<RawFd>, std::io::Error> {
+        keep_across_exec(self.socket)?;
+        Ok(Some(self.socket))
+    }
 
-            Ok(Box::new(SocketDescriptor {
-                file_descriptor,
-                hostname,
-            }))
+    fn shutdown(&mut self, _deadline: Instant) {
+        if self.socket < 0 {
+            return;
+        }
+        unsafe {
+            if close(self.socket) < 0 {
+                error!("Cannot close listener: {}", std::io::Error::last_os_error());
+            }
         }
+        self.socket = -1;
     }
 }
 
 impl Drop for SocketDescriptorManager {
     fn drop(&mut self) {
+        if self.socket < 0 {
+            return;
+        }
         unsafe {
             if close(self.socket) < 0 {
                 todo!("handle socket closing failures");
@@ -188,6 +402,8 @@ impl Drop for SocketDescriptorManager {
 pub struct SocketDescriptor {
     pub(crate) file_descriptor: c_int,
     pub(crate) hostname: String,
+    pub(crate) ip: IpAddr,
+    telnet: TelnetInput,
 }
 
 impl Drop for SocketDescriptor {
@@ -204,6 +420,37 @@ impl Descriptor for SocketDescriptor {
     fn get_hostname(&self) -> &str {
         self.hostname.as_str()
     }
+
+    fn get_ip(&self) -> Option<IpAddr> {
+        Some(self.ip)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "TELNET"
+    }
+
+    fn set_keepalive(&self, keepalive: &Keepalive) -> Result<(), std::io::Error> {
+        set_keepalive(self.file_descriptor, keepalive)
+    }
+
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        unsafe {
+            if shutdown(self.file_descriptor, SHUT_WR) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+        keep_across_exec(self.file_descriptor)?;
+        Ok(SavedDescriptor::Telnet {
+            fd: self.file_descriptor,
+            hostname: self.hostname.clone(),
+            ip: Some(self.ip),
+            input: self.telnet.clone(),
+        })
+    }
 }
 
 impl Read for SocketDescriptor {
@@ -223,7 +470,7 @@ impl Read for SocketDescriptor {
                 &mut timeout as *mut timeval,
             ) < 0
             {
-                return Err(std::io::Error::new(ErrorKind::Other, "libc::select failed"));
+                return Err(std::io::Error::other("libc::select failed"));
             }
 
             if FD_ISSET(self.file_descriptor, &input_set as *const fd_set) {
@@ -233,12 +480,16 @@ impl Read for SocketDescriptor {
                     buf.len(),
                 );
                 if retval < 0 {
-                    Err(std::io::Error::last_os_error())
-                } else {
-                    Ok(retval as usize)
+                    return Err(std::io::Error::last_os_error());
+                }
+                let read = retval as usize;
+                match self.telnet.strip(&mut buf[..read]) {
+                    // Nothing but negotiation, which isn't the end of the input
+                    0 if read > 0 => Err(ErrorKind::WouldBlock.into()),
+                    kept => Ok(kept),
                 }
             } else {
-                Ok(0)
+                Err(ErrorKind::WouldBlock.into())
             }
         }
     }
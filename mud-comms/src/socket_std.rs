@@ -1,50 +1,116 @@
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
+use std::net::IpAddr;
+use std::net::Shutdown;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
 use std::thread::JoinHandle;
+use std::time::Instant;
 
 use crossbeam_channel::Receiver;
 use dns_lookup::lookup_addr;
+use log::error;
+use log::warn;
 
+use crate::copyover::keep_across_exec;
+use crate::copyover::reclaim_after_exec;
+use crate::copyover::SavedDescriptor;
 use crate::descriptor::Descriptor;
 use crate::descriptor::DescriptorManager;
+use crate::keepalive::set_keepalive;
+use crate::keepalive::Keepalive;
+use crate::listener::inherited_listener;
+use crate::proxy;
+use crate::proxy::TrustedProxies;
+use crate::shutdown::join_before;
+use crate::shutdown::wait_until;
+use crate::telnet::TelnetInput;
 
 pub struct SocketDescriptorManager {
-    listener_thread: JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
-    stream_receiver: Receiver<TcpStream>,
+    listener_thread: Option<JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>>,
+    listener_fd: RawFd,
+    stopping: Arc<AtomicBool>,
+    stream_receiver: Receiver<(TcpStream, IpAddr)>,
     descriptors_waiting_condition: Arc<(Condvar, Mutex<bool>)>,
 }
 
 impl SocketDescriptorManager {
-    pub fn new(listen_port: u16) -> Result<Self, std::io::Error> {
+    /// Listens on `listen_port`, unless the process inherited a listening socket to use instead.
+    /// Connections from `proxies` are taken to be from the client named by their PROXY header.
+    pub fn new(listen_port: u16, proxies: TrustedProxies) -> Result<Self, std::io::Error> {
+        let listener = match inherited_listener()? {
+            // SAFETY: the inherited socket was handed over for this manager alone to own
+            Some(fd) => unsafe { TcpListener::from_raw_fd(fd) },
+            None => TcpListener::bind(("0.0.0.0", listen_port))?,
+        };
+        SocketDescriptorManager::with_listener(listener, proxies)
+    }
+
+    pub(crate) fn with_listener(
+        listener: TcpListener,
+        proxies: TrustedProxies,
+    ) -> Result<Self, std::io::Error> {
+        // An inherited socket may have been left nonblocking
+        listener.set_nonblocking(false)?;
+        let listener_fd = listener.as_raw_fd();
         let (sender, receiver) = crossbeam_channel::bounded(5);
         let conditional_and_predicate = Arc::new((Condvar::new(), Mutex::new(true)));
         let sender_conditional_and_predicate = Arc::clone(&conditional_and_predicate);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let listener_stopping = Arc::clone(&stopping);
 
         let listener_thread = std::thread::spawn(move || {
-            let listener = TcpListener::bind(("0.0.0.0", listen_port))?;
-            let (condition, predicate) = &*sender_conditional_and_predicate;
-
             for connection in listener.incoming() {
-                match connection {
-                    Ok(stream) => {
-                        sender.send(stream)?;
+                if listener_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match connection {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Cannot accept connection: {}", e);
+                        continue;
+                    }
+                };
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer.ip(),
+                    Err(e) => {
+                        error!("Cannot get address of connection: {}", e);
+                        continue;
+                    }
+                };
+                let sender = sender.clone();
+                let waiting = Arc::clone(&sender_conditional_and_predicate);
+                let hand_over = move |stream, client| {
+                    // The manager is gone if this fails, and the connection with it
+                    if sender.send((stream, client)).is_ok() {
+                        let (condition, predicate) = &*waiting;
                         let mut empty = predicate.lock().unwrap();
                         *empty = sender.is_empty();
                         condition.notify_one();
                     }
-                    Err(e) => (),
+                };
+                if proxies.trusts(peer) {
+                    proxy::hand_over_once_read(stream, peer, hand_over);
+                } else {
+                    hand_over(stream, peer);
                 }
             }
             Ok(())
         });
 
         Ok(SocketDescriptorManager {
-            listener_thread,
+            listener_thread: Some(listener_thread),
+            listener_fd,
+            stopping,
             stream_receiver: receiver,
             descriptors_waiting_condition: conditional_and_predicate,
         })
@@ -57,7 +123,7 @@ impl DescriptorManager for SocketDescriptorManager {
         let empty = predicate.lock().unwrap();
         if *empty {
             // subject to spurious unwraps but this is called in a loop anyways
-            condition.wait(empty).unwrap();
+            let _empty = condition.wait(empty).unwrap();
         }
         Ok(())
     }
@@ -66,7 +132,7 @@ impl DescriptorManager for SocketDescriptorManager {
         &self,
     ) -> Result<Box<dyn Descriptor>, Box<dyn std::error::Error + Send + Sync>> {
         match self.stream_receiver.try_recv() {
-            Ok(stream) => {
+            Ok((stream, client)) => {
                 let (condition, predicate) = &*self.descriptors_waiting_condition;
                 let mut empty = predicate.lock().unwrap();
                 *empty = self.stream_receiver.is_empty();
@@ -74,37 +140,136 @@ impl DescriptorManager for SocketDescriptorManager {
 
                 if stream.set_nonblocking(true).is_err() {
                     stream.shutdown(std::net::Shutdown::Both)?;
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(Box::new(std::io::Error::other(
                         "Couldn't set stream to nonblocking",
                     )));
                 }
 
-                let socket_addr = stream.peer_addr()?;
-                let hostname = lookup_addr(&socket_addr.ip())
-                    .or::<std::io::Error>(Ok(socket_addr.ip().to_string()))
+                let hostname = lookup_addr(&client)
+                    .or::<std::io::Error>(Ok(client.to_string()))
                     .expect("lookup with ip fallback to be infallible");
-                Ok(Box::new(SocketDescriptor { stream, hostname }))
+                Ok(Box::new(SocketDescriptor {
+                    stream,
+                    hostname,
+                    ip: client,
+                    telnet: TelnetInput::default(),
+                }))
             }
             Err(e) => Err(Box::new(e)),
         }
     }
+
+    fn restore_descriptor(
+        &self,
+        saved: SavedDescriptor,
+    ) -> Result<Box<dyn Descriptor>, std::io::Error> {
+        match saved {
+            SavedDescriptor::Telnet {
+                fd,
+                hostname,
+                ip,
+                input,
+            } => {
+                reclaim_after_exec(fd)?;
+                // SAFETY: the previous process left `fd` open for this process alone to take over
+                let stream = unsafe { TcpStream::from_raw_fd(fd) };
+                stream.set_nonblocking(true)?;
+                let ip = match ip {
+                    Some(ip) => ip,
+                    None => stream.peer_addr()?.ip(),
+                };
+                Ok(Box::new(SocketDescriptor {
+                    stream,
+                    hostname,
+                    ip,
+                    telnet: input,
+                }))
+            }
+            other => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not a socket descriptor: {:?}", other),
+            )),
+        }
+    }
+
+    fn save_listener(&self) -> Result<Option<RawFd>, std::io::Error> {
+        keep_across_exec(self.listener_fd)?;
+        Ok(Some(self.listener_fd))
+    }
+
+    fn shutdown(&mut self, deadline: Instant) {
+        let Some(thread) = self.listener_thread.take() else {
+            return;
+        };
+        self.stopping.store(true, Ordering::SeqCst);
+        // SAFETY: the listener thread still owns the socket, so the fd hasn't been reused. This
+        // wakes it from `accept` without closing the socket under it.
+        if unsafe { libc::shutdown(self.listener_fd, libc::SHUT_RD) } < 0 {
+            error!("Cannot stop listening: {}", std::io::Error::last_os_error());
+        }
+        // Connections it accepted but the game never took are closed, which also makes room for
+        // one it may be blocked handing over
+        wait_until(deadline, || {
+            while self.stream_receiver.try_recv().is_ok() {}
+            thread.is_finished()
+        });
+        match join_before(thread, deadline) {
+            Some(Ok(Ok(()))) => {}
+            Some(Ok(Err(e))) => error!("Listener thread failed: {}", e),
+            Some(Err(_)) => error!("Listener thread panicked"),
+            None => warn!("Listener thread still running at shutdown"),
+        }
+    }
 }
 
 pub struct SocketDescriptor {
     stream: TcpStream,
     hostname: String,
+    ip: IpAddr,
+    telnet: TelnetInput,
 }
 
 impl Descriptor for SocketDescriptor {
     fn get_hostname(&self) -> &str {
         self.hostname.as_str()
     }
+
+    fn get_ip(&self) -> Option<IpAddr> {
+        Some(self.ip)
+    }
+
+    fn get_type(&self) -> &'static str {
+        "TELNET"
+    }
+
+    fn set_keepalive(&self, keepalive: &Keepalive) -> Result<(), std::io::Error> {
+        set_keepalive(self.stream.as_raw_fd(), keepalive)
+    }
+
+    fn finish(&mut self) -> Result<bool, std::io::Error> {
+        self.stream.shutdown(Shutdown::Write)?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<SavedDescriptor, std::io::Error> {
+        keep_across_exec(self.stream.as_raw_fd())?;
+        Ok(SavedDescriptor::Telnet {
+            fd: self.stream.as_raw_fd(),
+            hostname: self.hostname.clone(),
+            ip: Some(self.ip),
+            input: self.telnet.clone(),
+        })
+    }
 }
 
 impl Read for SocketDescriptor {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
+        let read = self.stream.read(buf)?;
+        match self.telnet.strip(&mut buf[..read]) {
+            // Nothing but negotiation, which isn't the end of the input
+            0 if read > 0 => Err(ErrorKind::WouldBlock.into()),
+            kept => Ok(kept),
+        }
     }
 }
 
@@ -117,3 +282,42 @@ impl Write for SocketDescriptor {
         self.stream.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inherited_listener_accepts_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let addr = listener.local_addr().unwrap();
+        let manager = SocketDescriptorManager::with_listener(listener, TrustedProxies::default())
+            .expect("manager");
+
+        let mut client = TcpStream::connect(addr).expect("connected");
+        manager
+            .block_until_descriptor()
+            .expect("a descriptor waiting");
+        let mut descriptor = manager.new_descriptor().expect("new descriptor");
+        descriptor.write_all(b"Hello").expect("written");
+
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).expect("read");
+        assert_eq!(b"Hello", &buf);
+    }
+
+    #[test]
+    fn test_shutdown_stops_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let addr = listener.local_addr().unwrap();
+        let mut manager =
+            SocketDescriptorManager::with_listener(listener, TrustedProxies::default())
+                .expect("manager");
+
+        let start = Instant::now();
+        manager.shutdown(start + std::time::Duration::from_secs(5));
+        assert!(manager.listener_thread.is_none());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Interpret As Command, which starts every telnet command (and, doubled, is a literal 255)
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+/// Subnegotiation begin and end
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+/// The option the game negotiates, to stop clients echoing passwords
+pub const ECHO: u8 = 1;
+
+/// The longest subnegotiation kept, beyond which the rest is dropped
+const MAX_SUBNEGOTIATION: usize = 1024;
+
+/// Text or a command from a telnet stream
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Data(Vec<u8>),
+    Will(u8),
+    Wont(u8),
+    Do(u8),
+    Dont(u8),
+    Subnegotiation(u8, Vec<u8>),
+    /// Any other command, eg GA (go ahead)
+    Command(u8),
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Option(u8),
+    SubnegotiationOption,
+    Subnegotiation(u8),
+    SubnegotiationIac(u8),
+}
+
+/// Just enough of telnet (RFC 854) to split what the game writes into its text and the
+/// negotiation mixed in, for transports whose clients aren't telnet clients. Commands cut off at
+/// the end of one chunk are finished from the next.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Parser {
+    state: State,
+    subnegotiation: Vec<u8>,
+}
+
+impl Parser {
+    pub fn parse(&mut self, input: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut data = Vec::new();
+        for &byte in input {
+            self.state = match std::mem::take(&mut self.state) {
+                State::Data if byte == IAC => State::Iac,
+                State::Data => {
+                    data.push(byte);
+                    State::Data
+                }
+                State::Iac => match byte {
+                    IAC => {
+                        data.push(IAC);
+                        State::Data
+                    }
+                    WILL | WONT | DO | DONT => State::Option(byte),
+                    SB => State::SubnegotiationOption,
+                    command => {
+                        flush(&mut data, &mut events);
+                        events.push(Event::Command(command));
+                        State::Data
+                    }
+                },
+                State::Option(verb) => {
+                    flush(&mut data, &mut events);
+                    events.push(match verb {
+                        WILL => Event::Will(byte),
+                        WONT => Event::Wont(byte),
+                        DO => Event::Do(byte),
+                        _ => Event::Dont(byte),
+                    });
+                    State::Data
+                }
+                State::SubnegotiationOption => {
+                    self.subnegotiation.clear();
+                    State::Subnegotiation(byte)
+                }
+                State::Subnegotiation(option) if byte == IAC => State::SubnegotiationIac(option),
+                State::Subnegotiation(option) => {
+                    self.push_subnegotiation(byte);
+                    State::Subnegotiation(option)
+                }
+                State::SubnegotiationIac(option) => match byte {
+                    SE => {
+                        flush(&mut data, &mut events);
+                        events.push(Event::Subnegotiation(
+                            option,
+                            std::mem::take(&mut self.subnegotiation),
+                        ));
+                        State::Data
+                    }
+                    // IAC IAC is a literal 255, and whatever else follows an IAC is kept as it is
+                    _ => {
+                        self.push_subnegotiation(byte);
+                        State::Subnegotiation(option)
+                    }
+                },
+            };
+        }
+        flush(&mut data, &mut events);
+        events
+    }
+
+    fn push_subnegotiation(&mut self, byte: u8) {
+        if self.subnegotiation.len() < MAX_SUBNEGOTIATION {
+            self.subnegotiation.push(byte);
+        }
+    }
+}
+
+/// Takes telnet's commands and any NULs out of what a client sends, so the game reads only what
+/// was typed. Clients send a NUL after a bare CR, and the game reads input as C strings, which
+/// it would cut short. Saved across a copyover, so a command cut off by it is still removed.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TelnetInput {
+    parser: Parser,
+}
+
+impl TelnetInput {
+    /// Strips `input` in place, returning how much of it is left
+    pub fn strip(&mut self, input: &mut [u8]) -> usize {
+        let mut kept = 0;
+        for event in self.parser.parse(input) {
+            if let Event::Data(data) = event {
+                for byte in data.into_iter().filter(|&byte| byte != 0) {
+                    input[kept] = byte;
+                    kept += 1;
+                }
+            }
+        }
+        kept
+    }
+}
+
+/// Whether `output` has the game saying it will echo (IAC WILL ECHO, around a password prompt)
+/// or won't any more, so the client stops or starts echoing what's typed
+pub fn echo_change(output: &[u8]) -> Option<bool> {
+    let contains = |sequence: &[u8]| {
+        output
+            .windows(sequence.len())
+            .any(|window| window == sequence)
+    };
+    if contains(&[IAC, WILL, ECHO]) {
+        Some(true)
+    } else if contains(&[IAC, WONT, ECHO]) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn flush(data: &mut Vec<u8>, events: &mut Vec<Event>) {
+    if !data.is_empty() {
+        events.push(Event::Data(std::mem::take(data)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_split_from_text_across_chunks() {
+        let mut parser = Parser::default();
+        assert_eq!(
+            vec![Event::Data(b"Password: ".to_vec())],
+            parser.parse(b"Password: \xFF")
+        );
+        assert_eq!(
+            vec![
+                Event::Will(ECHO),
+                Event::Data(b"\xFF\r\n".to_vec()),
+                Event::Command(249)
+            ],
+            parser.parse(b"\xFB\x01\xFF\xFF\r\n\xFF\xF9")
+        );
+        assert_eq!(
+            vec![
+                Event::Subnegotiation(24, b"\x00\xFFxterm".to_vec()),
+                Event::Data(b"> ".to_vec())
+            ],
+            parser.parse(b"\xFF\xFA\x18\x00\xFF\xFFxterm\xFF\xF0> ")
+        );
+    }
+}
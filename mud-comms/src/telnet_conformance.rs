@@ -0,0 +1,245 @@
+//! What the game reads from hostile or awkward telnet clients, through both socket transports:
+//! each chunk a client sends is read on its own, so commands cut across reads are covered too.
+
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::descriptor::Descriptor;
+use crate::descriptor::DescriptorManager;
+use crate::proxy::TrustedProxies;
+use crate::socket_libc;
+use crate::socket_std;
+
+/// Long enough for a chunk sent on loopback to have arrived before it's read
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// How long the game's side waits for what a client sent
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TRANSPORTS: [&str; 2] = ["socket-std", "socket-libc"];
+
+fn manager(
+    transport: &str,
+    listener: TcpListener,
+    proxies: TrustedProxies,
+) -> Box<dyn DescriptorManager> {
+    match transport {
+        "socket-std" => Box::new(
+            socket_std::SocketDescriptorManager::with_listener(listener, proxies).expect("manager"),
+        ),
+        _ => Box::new(
+            socket_libc::SocketDescriptorManager::with_listener(listener, proxies)
+                .expect("manager"),
+        ),
+    }
+}
+
+/// A connection through `transport`, as its manager and the client's and the game's ends
+fn connect(transport: &str) -> (Box<dyn DescriptorManager>, TcpStream, Box<dyn Descriptor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+    let addr = listener.local_addr().unwrap();
+    let manager = manager(transport, listener, TrustedProxies::default());
+    let client = TcpStream::connect(addr).expect("connected");
+    manager
+        .block_until_descriptor()
+        .expect("a descriptor waiting");
+    let descriptor = manager.new_descriptor().expect("new descriptor");
+    (manager, client, descriptor)
+}
+
+/// A connection through each socket transport, as the client's and the game's ends
+fn connections() -> Vec<(&'static str, TcpStream, Box<dyn Descriptor>)> {
+    TRANSPORTS
+        .into_iter()
+        .map(|transport| {
+            let (_manager, client, descriptor) = connect(transport);
+            (transport, client, descriptor)
+        })
+        .collect()
+}
+
+/// Sends each of `chunks` to be read separately, returning what the game reads of them all
+fn read_chunks(
+    client: &mut TcpStream,
+    descriptor: &mut Box<dyn Descriptor>,
+    chunks: &[&[u8]],
+    expected_len: usize,
+) -> Vec<u8> {
+    let mut read = Vec::new();
+    for chunk in chunks {
+        client.write_all(chunk).expect("sent");
+        thread::sleep(SETTLE);
+        read_available(descriptor, &mut read);
+    }
+    let deadline = Instant::now() + READ_TIMEOUT;
+    while read.len() < expected_len && Instant::now() < deadline {
+        thread::sleep(SETTLE);
+        read_available(descriptor, &mut read);
+    }
+    read
+}
+
+/// Reads once, if there's anything to read
+fn read_available(descriptor: &mut Box<dyn Descriptor>, read: &mut Vec<u8>) {
+    let mut buf = [0; 4096];
+    match descriptor.read(&mut buf) {
+        Ok(len) => read.extend_from_slice(&buf[..len]),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+        Err(e) => panic!("read failed: {}", e),
+    }
+}
+
+fn assert_reads(chunks: &[&[u8]], expected: &[u8]) {
+    for (transport, mut client, mut descriptor) in connections() {
+        let read = read_chunks(&mut client, &mut descriptor, chunks, expected.len());
+        assert_eq!(
+            String::from_utf8_lossy(expected),
+            String::from_utf8_lossy(&read),
+            "through {}",
+            transport
+        );
+    }
+}
+
+#[test]
+fn test_plain_text_is_read_as_sent() {
+    assert_reads(&[b"look\r\n", b"north\r\n"], b"look\r\nnorth\r\n");
+}
+
+#[test]
+fn test_negotiation_is_removed() {
+    assert_reads(&[b"\xFF\xFD\x01\xFF\xFB\x18look\xFF\xF9\r\n"], b"look\r\n");
+}
+
+#[test]
+fn test_negotiation_split_across_reads_is_removed() {
+    assert_reads(
+        &[b"look\xFF", b"\xFD", b"\x18north\r\n", b"\xFF", b"\xFC\x01"],
+        b"looknorth\r\n",
+    );
+}
+
+#[test]
+fn test_doubled_iac_is_a_literal_255() {
+    assert_reads(&[b"say \xFF\xFF\r\n"], b"say \xFF\r\n");
+    assert_reads(&[b"say \xFF", b"\xFF\r\n"], b"say \xFF\r\n");
+}
+
+#[test]
+fn test_subnegotiation_is_removed() {
+    assert_reads(&[b"\xFF\xFA\x18\x00xterm\xFF\xF0look\r\n"], b"look\r\n");
+    assert_reads(
+        &[b"lo\xFF\xFA\x1F\x00", b"\x50\x00\x18\xFF", b"\xF0ok\r\n"],
+        b"look\r\n",
+    );
+}
+
+#[test]
+fn test_unterminated_subnegotiation_swallows_input_until_ended() {
+    let flood = [b'a'; 4000];
+    assert_reads(
+        &[
+            b"\xFF\xFA\x18",
+            &flood,
+            b"look\r\n",
+            b"\xFF\xF0",
+            b"north\r\n",
+        ],
+        b"north\r\n",
+    );
+}
+
+#[test]
+fn test_cr_nul_is_read_as_cr() {
+    assert_reads(&[b"look\r\0north\r\0"], b"look\rnorth\r");
+    assert_reads(&[b"look\r", b"\0north\r\0"], b"look\rnorth\r");
+}
+
+#[test]
+fn test_cr_lf_is_read_as_sent() {
+    assert_reads(&[b"look\r", b"\nnorth\r\n"], b"look\r\nnorth\r\n");
+}
+
+#[test]
+fn test_negotiation_alone_is_not_the_end_of_input() {
+    for (transport, mut client, mut descriptor) in connections() {
+        client
+            .write_all(b"\xFF\xFB\x1F\xFF\xFA\x18\x00xterm\xFF\xF0")
+            .expect("sent");
+        thread::sleep(SETTLE);
+        let mut buf = [0; 64];
+        let error = descriptor
+            .read(&mut buf)
+            .expect_err(&format!("nothing to read through {}", transport));
+        assert_eq!(ErrorKind::WouldBlock, error.kind(), "through {}", transport);
+
+        let read = read_chunks(&mut client, &mut descriptor, &[b"look\n"], 5);
+        assert_eq!(b"look\n", &read[..], "through {}", transport);
+    }
+}
+
+#[test]
+fn test_telnet_state_survives_copyover() {
+    for transport in TRANSPORTS {
+        let (manager, mut client, mut descriptor) = connect(transport);
+        client.write_all(b"\xFF\xFD").expect("sent");
+        thread::sleep(SETTLE);
+        let mut buf = [0; 64];
+        assert!(descriptor.read(&mut buf).is_err(), "through {}", transport);
+
+        let saved = descriptor.save().expect("saved");
+        // The exec leaves the socket open for the next process rather than closing it
+        std::mem::forget(descriptor);
+
+        let mut restored = manager.restore_descriptor(saved).expect("restored");
+        let read = read_chunks(&mut client, &mut restored, &[b"\x01secret\r\n"], 8);
+        assert_eq!(b"secret\r\n", &read[..], "through {}", transport);
+    }
+}
+
+#[test]
+fn test_silent_proxy_holds_up_no_other_connection() {
+    for transport in TRANSPORTS {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free local port");
+        let addr = listener.local_addr().unwrap();
+        let proxies = TrustedProxies::parse("127.0.0.1").expect("proxies");
+        let manager = manager(transport, listener, proxies);
+        let _silent = TcpStream::connect(addr).expect("connected");
+        let mut client = TcpStream::connect(addr).expect("connected");
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 51234 4000\r\n")
+            .expect("header sent");
+
+        let started = Instant::now();
+        let mut descriptor = loop {
+            manager
+                .block_until_descriptor()
+                .expect("a descriptor waiting");
+            match manager.new_descriptor() {
+                Ok(descriptor) => break descriptor,
+                Err(_) if started.elapsed() < READ_TIMEOUT => continue,
+                Err(e) => panic!("no descriptor through {}: {}", transport, e),
+            }
+        };
+        // Well within the time the silent proxy has to send its header
+        assert!(
+            started.elapsed() < Duration::from_secs(2),
+            "through {}",
+            transport
+        );
+        assert_eq!(
+            Some("203.0.113.9".parse().unwrap()),
+            descriptor.get_ip(),
+            "through {}",
+            transport
+        );
+        let read = read_chunks(&mut client, &mut descriptor, &[b"look\r\n"], 6);
+        assert_eq!(b"look\r\n", &read[..], "through {}", transport);
+    }
+}
@@ -0,0 +1,250 @@
+//! Runs the real game for scenario tests: each `Server` gets its own copy of a test lib and its
+//! own port, so tests can run in parallel, and a `Client` waits for what it expects (up to a
+//! timeout) rather than assuming output arrives in one read. Not every test uses all of it.
+#![allow(dead_code)]
+
+use std::{
+    fs::{copy, read_dir},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bstr::{BStr, BString, ByteSlice};
+use tempdir::TempDir;
+
+/// How long the game gets to start
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a client waits for what it expects
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Server {
+    child: Child,
+    port: u16,
+    log: Arc<Mutex<Vec<String>>>,
+    _files: TempDir,
+}
+
+impl Server {
+    /// Starts the game on a copy of `tests/<lib>` (eg `clean_lib`) with the default transport
+    pub fn start(lib: &str) -> Self {
+        Server::start_with(lib, "socket-libc", &[])
+    }
+
+    /// Starts the game on a copy of `tests/<lib>` with `transport` and any other `env`,
+    /// returning once it's ready for connections
+    pub fn start_with(lib: &str, transport: &str, env: &[(&str, &str)]) -> Self {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join(lib);
+        let files = setup_mud_files(&source).expect("lib copied");
+
+        // The game inherits a listener already bound to a free port, so there's no picking a
+        // port and hoping nothing else takes it first
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener bound");
+        let port = listener.local_addr().expect("bound address").port();
+        let listener_fd = listener.as_raw_fd();
+        let mut command = Command::new(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("..")
+                .join("bin")
+                .join("circle"),
+        );
+        command
+            .args([
+                "-d",
+                files.path().to_str().expect("lib dir not present"),
+                port.to_string().as_str(),
+            ])
+            .env("MUD_COMMS_TRANSPORT", transport)
+            .env("MUD_COMMS_LISTEN_FD", listener_fd.to_string())
+            // Everything goes to the game's log on stderr, which is read until the game exits
+            .env("MUD_COMMS_LOG_OUTPUT", "circle")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        // SAFETY: only clears close-on-exec on a descriptor the child is meant to keep
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(listener_fd, libc::F_SETFD, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().expect("Server didn't start");
+        drop(listener);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (started, wait_for_start) = mpsc::channel();
+        let stderr = child.stderr.take().expect("server's stderr");
+        let lines = Arc::clone(&log);
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if line.contains("Entering game loop.") {
+                    let _ = started.send(());
+                }
+                lines.lock().unwrap().push(line);
+            }
+        });
+        let server = Server {
+            child,
+            port,
+            log,
+            _files: files,
+        };
+        if wait_for_start.recv_timeout(START_TIMEOUT).is_err() {
+            panic!("server didn't finish starting:\n{}", server.log());
+        }
+        server
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn connect(&self) -> Client {
+        Client::connect(self.port)
+    }
+
+    /// Everything the game has logged so far
+    pub fn log(&self) -> String {
+        self.log.lock().unwrap().join("\n")
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.child.kill().expect("Child process wasn't killed");
+        let _ = self.child.wait();
+    }
+}
+
+pub struct Client {
+    stream: TcpStream,
+    /// Output read but not yet expected
+    received: BString,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).expect("connected");
+        Client {
+            stream,
+            received: BString::new(Vec::new()),
+        }
+    }
+
+    pub fn stream(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    pub fn send(&mut self, input: impl AsRef<[u8]>) {
+        self.stream
+            .write_all(input.as_ref())
+            .expect("writing failed");
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        self.send(format!("{}\n", line));
+    }
+
+    /// Asserts the game sends exactly `expected` next, however many reads it arrives in
+    pub fn assert_response(&mut self, expected: impl AsRef<[u8]>) {
+        let expected = BStr::new(expected.as_ref());
+        self.fill(|received| received.len() >= expected.len(), expected);
+        let rest = self.received.split_off(expected.len());
+        let response = std::mem::replace(&mut self.received, rest.into());
+        assert_eq!(expected, response);
+    }
+
+    /// Waits for the game to send `expected` (eg a prompt), returning everything sent up to and
+    /// including it
+    pub fn read_until(&mut self, expected: impl AsRef<[u8]>) -> BString {
+        let expected = BStr::new(expected.as_ref());
+        self.fill(|received| received.find(expected).is_some(), expected);
+        let end = self.received.find(expected).expect("found") + expected.len();
+        let rest = self.received.split_off(end);
+        std::mem::replace(&mut self.received, rest.into())
+    }
+
+    /// Asserts the game closes the connection without sending anything more
+    pub fn assert_closed(&mut self) {
+        let deadline = Instant::now() + EXPECT_TIMEOUT;
+        let mut buf = [0; 4096];
+        loop {
+            self.set_timeout(deadline);
+            match self.stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => self.received.extend_from_slice(&buf[..read]),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => break,
+                Err(e) if is_timeout(&e) => panic!("connection wasn't closed"),
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+        assert_eq!(BStr::new(""), self.received.as_bstr());
+    }
+
+    fn fill(&mut self, done: impl Fn(&BString) -> bool, expected: &BStr) {
+        let deadline = Instant::now() + EXPECT_TIMEOUT;
+        let mut buf = [0; 4096];
+        while !done(&self.received) {
+            self.set_timeout(deadline);
+            match self.stream.read(&mut buf) {
+                Ok(0) => panic!(
+                    "connection closed waiting for {:?}, got {:?}",
+                    expected, self.received
+                ),
+                Ok(read) => self.received.extend_from_slice(&buf[..read]),
+                Err(e) if is_timeout(&e) => panic!(
+                    "timed out waiting for {:?}, got {:?}",
+                    expected, self.received
+                ),
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+    }
+
+    fn set_timeout(&self, deadline: Instant) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        self.stream
+            .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+            .expect("timeout set");
+    }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn setup_mud_files(source: &Path) -> std::io::Result<TempDir> {
+    let dir = TempDir::new("test_mud_files")?;
+
+    copy_recursively(source, dir.as_ref())?;
+
+    Ok(dir)
+}
+
+/// Copy files from source to destination recursively.
+pub fn copy_recursively(
+    source: impl AsRef<Path>,
+    destination: impl AsRef<Path>,
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(&destination)?;
+    for entry in read_dir(source)? {
+        let entry = entry?;
+        let filetype = entry.file_type()?;
+        if filetype.is_dir() {
+            copy_recursively(entry.path(), destination.as_ref().join(entry.file_name()))?;
+        } else {
+            copy(entry.path(), destination.as_ref().join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
@@ -24,6 +24,23 @@
 extern int level_can_shout;
 extern int holler_move_cost;
 
+/*
+ * do_gen_comm channels, by subcmd, as named in transport channel bindings.
+ * Speech arriving from a bound channel is run as this command.
+ */
+const char *mirrored_channels[] = {
+  "holler",
+  "shout",
+  "gossip",
+  "auction",
+  "grats",
+  "\n"
+};
+
+/* How ch is named in mirrored speech, since nobody watching can see invisible */
+#define MIRROR_NAME(ch)	((AFF_FLAGGED((ch), AFF_INVISIBLE) || GET_INVIS_LEV(ch)) ? \
+			 "Someone" : GET_NAME(ch))
+
 /* local functions */
 void perform_tell(struct char_data *ch, struct char_data *vict, char *arg);
 int is_tell_ok(struct char_data *ch, struct char_data *vict);
@@ -47,15 +64,19 @@ ACMD(do_say)
   else {
     char buf[MAX_INPUT_LENGTH + 12];
 
+    char target[32];
+
     snprintf(buf, sizeof(buf), "$n says, '%s'", argument);
     act(buf, FALSE, ch, 0, 0, TO_ROOM);
 
+    delete_doubledollar(argument);
     if (!IS_NPC(ch) && PRF_FLAGGED(ch, PRF_NOREPEAT))
       send_to_char(ch, "%s", OK);
-    else {
-      delete_doubledollar(argument);
+    else
       send_to_char(ch, "You say, '%s'\r\n", argument);
-    }
+
+    snprintf(target, sizeof(target), "room:%d", GET_ROOM_VNUM(IN_ROOM(ch)));
+    send_to_mirrors(target, "%s says, '%s'", MIRROR_NAME(ch), argument);
   }
 }
 
@@ -480,6 +501,9 @@ ACMD(do_gen_comm)
 	send_to_char(i->character, "%s", KNRM);
     }
   }
+
+  delete_doubledollar(argument);
+  send_to_mirrors(mirrored_channels[subcmd], "%s %ss, '%s'", MIRROR_NAME(ch), com_msgs[subcmd][1], argument);
 }
 
 
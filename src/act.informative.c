@@ -37,6 +37,7 @@ extern char *immlist;
 extern char *policies;
 extern char *handbook;
 extern char *class_abbrevs[];
+extern struct DescriptorManager *mother_desc;
 
 /* extern functions */
 ACMD(do_action);
@@ -1100,7 +1101,7 @@ ACMD(do_who)
 
 
 #define USERS_FORMAT \
-"format: users [-l minlevel[-maxlevel]] [-n name] [-h host] [-c classlist] [-o] [-p]\r\n"
+"format: users [-l minlevel[-maxlevel]] [-n name] [-h host] [-c classlist] [-o] [-p] [-i]\r\n"
 
 /* BIG OL' FIXME: Rewrite it all. Similar to do_who(). */
 ACMD(do_users)
@@ -1111,7 +1112,8 @@ ACMD(do_users)
   struct char_data *tch;
   struct descriptor_data *d;
   int low = 0, high = LVL_IMPL, num_can_see = 0;
-  int showclass = 0, outlaws = 0, playing = 0, deadweight = 0;
+  int showclass = 0, outlaws = 0, playing = 0, deadweight = 0, showinput = 0;
+  struct input_stats stats;
   char buf[MAX_INPUT_LENGTH], arg[MAX_INPUT_LENGTH];
 
   host_search[0] = name_search[0] = '\0';
@@ -1138,6 +1140,10 @@ ACMD(do_users)
 	deadweight = 1;
 	strcpy(buf, buf1);	/* strcpy: OK (sizeof: buf1 == buf) */
 	break;
+      case 'i':
+	showinput = 1;
+	strcpy(buf, buf1);	/* strcpy: OK (sizeof: buf1 == buf) */
+	break;
       case 'l':
 	playing = 1;
 	half_chop(buf1, arg, buf);
@@ -1166,7 +1172,12 @@ ACMD(do_users)
       return;
     }
   }				/* end while (parser) */
-  send_to_char(ch,
+  if (showinput)
+    send_to_char(ch,
+	 "Num Class   Name         State          Idl    Bytes   Lines Thrt Trnc Drop\r\n"
+	 "--- ------- ------------ -------------- --- -------- ------- ---- ---- ----\r\n");
+  else
+    send_to_char(ch,
 	 "Num Class   Name         State          Idl Login@   Site\r\n"
 	 "--- ------- ------------ -------------- --- -------- ------------------------\r\n");
 
@@ -1221,16 +1232,27 @@ ACMD(do_users)
     else
       strcpy(idletime, "");
 
-    sprintf(line, "%3d %-7s %-12s %-14s %-3s %-8s ", d->desc_num, classname,
+    sprintf(line, "%3d %-7s %-12s %-14s %-3s ", d->desc_num, classname,
 	d->original && d->original->player.name ? d->original->player.name :
 	d->character && d->character->player.name ? d->character->player.name :
 	"UNDEFINED",
-	state, idletime, timeptr);
+	state, idletime);
 
-    if (d->host && *d->host)
-      sprintf(line + strlen(line), "[%s]\r\n", d->host);
-    else
-      strcat(line, "[Hostname unknown]\r\n");
+    if (showinput) {
+      if (get_descriptor_input_stats(mother_desc, d->descriptor, &stats) < 0)
+	strcat(line, "[Input stats unknown]\r\n");
+      else
+	sprintf(line + strlen(line), "%8llu %7llu %4llu %4llu %4llu\r\n",
+		stats.bytes, stats.lines, stats.throttled, stats.truncated,
+		stats.dropped);
+    } else {
+      sprintf(line + strlen(line), "%-8s ", timeptr);
+
+      if (d->host && *d->host)
+	sprintf(line + strlen(line), "[%s]\r\n", d->host);
+      else
+	strcat(line, "[Hostname unknown]\r\n");
+    }
 
     if (STATE(d) != CON_PLAYING) {
       sprintf(line2, "%s%s%s", CCGRN(ch, C_SPR), line, CCNRM(ch, C_SPR));
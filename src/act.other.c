@@ -33,6 +33,7 @@ extern int max_filesize;
 extern int nameserver_is_slow;
 extern int auto_save;
 extern int track_through_doors;
+extern struct DescriptorManager *mother_desc;
 
 /* extern procedures */
 void list_skills(struct char_data *ch);
@@ -65,6 +66,7 @@ ACMD(do_wimpy);
 ACMD(do_display);
 ACMD(do_gen_write);
 ACMD(do_gen_tog);
+ACMD(do_link);
 
 
 ACMD(do_quit)
@@ -953,3 +955,33 @@ ACMD(do_gen_tog)
 
   return;
 }
+
+
+ACMD(do_link)
+{
+  char arg[MAX_INPUT_LENGTH], identity[MAX_INPUT_LENGTH];
+
+  if (IS_NPC(ch) || !ch->desc)
+    return;
+
+  if (get_descriptor_identity(mother_desc, ch->desc->descriptor, identity, sizeof(identity)) < 0 || !*identity) {
+    send_to_char(ch, "Your connection doesn't have an account that can be linked.\r\n");
+    return;
+  }
+
+  one_argument(argument, arg);
+
+  if (!str_cmp(arg, "off")) {
+    if (link_descriptor_character(mother_desc, ch->desc->descriptor, NULL) < 0)
+      send_to_char(ch, "Your account couldn't be unlinked, please try again later.\r\n");
+    else
+      send_to_char(ch, "Your account is no longer linked, you'll need your password to log in.\r\n");
+  } else if (*arg)
+    send_to_char(ch, "Usage: link [off]\r\n");
+  else if (link_descriptor_character(mother_desc, ch->desc->descriptor, GET_NAME(ch)) < 0)
+    send_to_char(ch, "Your account couldn't be linked, please try again later.\r\n");
+  else {
+    send_to_char(ch, "Your account is now linked to %s, you won't be asked for a password from it.\r\n", GET_NAME(ch));
+    mudlog(NRM, MAX(LVL_IMMORT, GET_INVIS_LEV(ch)), TRUE, "%s linked account %s.", GET_NAME(ch), identity);
+  }
+}
@@ -27,7 +27,7 @@ extern FILE *player_fl;
 extern struct attack_hit_type attack_hit_text[];
 extern char *class_abbrevs[];
 extern time_t boot_time;
-extern int circle_shutdown, circle_reboot;
+extern int circle_shutdown, circle_reboot, circle_copyover;
 extern int circle_restrict;
 extern int load_into_inventory;
 extern int buf_switches, buf_largecount, buf_overflows;
@@ -842,6 +842,10 @@ ACMD(do_shutdown)
     send_to_all("Rebooting.. come back in a minute or two.\r\n");
     touch(FASTBOOT_FILE);
     circle_shutdown = circle_reboot = 1;
+  } else if (!str_cmp(arg, "copyover")) {
+    log("(GC) Copyover by %s.", GET_NAME(ch));
+    send_to_all("Rebooting.. hold on to your hat.\r\n");
+    circle_shutdown = circle_copyover = 1;
   } else if (!str_cmp(arg, "die")) {
     log("(GC) Shutdown by %s.", GET_NAME(ch));
     send_to_all("Shutting down for maintenance.\r\n");
@@ -2014,6 +2018,8 @@ ACMD(do_show)
 	buf_largecount,
 	buf_switches, buf_overflows
 	);
+    if (get_transport_stats(buf, sizeof(buf)) == 0 && *buf)
+      send_to_char(ch, "Connections:\r\n%s", buf);
     break;
 
   /* show errors */
@@ -19,14 +19,12 @@
 #include "handler.h"
 #include "db.h"
 
-/* local globals */
-struct ban_list_element *ban_list = NULL;
+/* extern variables */
+extern struct DescriptorManager *mother_desc;
 
 /* local functions */
 void load_banned(void);
-int isbanned(char *hostname);
-void _write_one_node(FILE *fp, struct ban_list_element *node);
-void write_ban_list(void);
+int isbanned(struct descriptor_data *d);
 ACMD(do_ban);
 ACMD(do_unban);
 int Valid_Name(char *newname);
@@ -43,99 +41,51 @@ const char *ban_types[] = {
 };
 
 
+/*
+ * The ban list itself is kept by mud-comms, which refuses connections from
+ * BAN_ALL sites before they ever reach new_descriptor_data().
+ */
 void load_banned(void)
 {
-  FILE *fl;
-  int i, date;
-  char site_name[BANNED_SITE_LENGTH + 1], ban_type[100];
-  char name[MAX_NAME_LENGTH + 1];
-  struct ban_list_element *next_node;
-
-  ban_list = 0;
-
-  if (!(fl = fopen(BAN_FILE, "r"))) {
-    if (errno != ENOENT) {
-      log("SYSERR: Unable to open banfile '%s': %s", BAN_FILE, strerror(errno));
-    } else
-      log("   Ban file '%s' doesn't exist.", BAN_FILE);
-    return;
-  }
-  while (fscanf(fl, " %s %s %d %s ", ban_type, site_name, &date, name) == 4) {
-    CREATE(next_node, struct ban_list_element, 1);
-    strncpy(next_node->site, site_name, BANNED_SITE_LENGTH);	/* strncpy: OK (n_n->site:BANNED_SITE_LENGTH+1) */
-    next_node->site[BANNED_SITE_LENGTH] = '\0';
-    strncpy(next_node->name, name, MAX_NAME_LENGTH);	/* strncpy: OK (n_n->name:MAX_NAME_LENGTH+1) */
-    next_node->name[MAX_NAME_LENGTH] = '\0';
-    next_node->date = date;
-
-    for (i = BAN_NOT; i <= BAN_ALL; i++)
-      if (!strcmp(ban_type, ban_types[i]))
-	next_node->type = i;
-
-    next_node->next = ban_list;
-    ban_list = next_node;
-  }
-
-  fclose(fl);
+  if (load_bans(mother_desc, BAN_FILE) < 0)
+    log("SYSERR: Unable to load banfile '%s'.", BAN_FILE);
 }
 
 
-int isbanned(char *hostname)
+/* the strictest ban (BAN_xxx) on the site d is connected from */
+int isbanned(struct descriptor_data *d)
 {
-  int i;
-  struct ban_list_element *banned_node;
-  char *nextchar;
-
-  if (!hostname || !*hostname)
-    return (0);
-
-  i = 0;
-  for (nextchar = hostname; *nextchar; nextchar++)
-    *nextchar = LOWER(*nextchar);
+  int type;
 
-  for (banned_node = ban_list; banned_node; banned_node = banned_node->next)
-    if (strstr(hostname, banned_node->site))	/* if hostname is a substring */
-      i = MAX(i, banned_node->type);
+  if ((type = get_descriptor_ban(mother_desc, d->descriptor)) < 0)
+    return (BAN_NOT);
 
-  return (i);
+  return (type);
 }
 
 
-void _write_one_node(FILE *fp, struct ban_list_element *node)
+/* a ban's date (in the format of the ban list) into buf */
+static void ban_date(char *buf, size_t len, long long date)
 {
-  if (node) {
-    _write_one_node(fp, node->next);
-    fprintf(fp, "%s %s %ld %s\n", ban_types[node->type],
-	    node->site, (long) node->date, node->name);
-  }
-}
-
-
+  time_t when = (time_t) date;
 
-void write_ban_list(void)
-{
-  FILE *fl;
-
-  if (!(fl = fopen(BAN_FILE, "w"))) {
-    perror("SYSERR: Unable to open '" BAN_FILE "' for writing");
-    return;
-  }
-  _write_one_node(fl, ban_list);/* recursively write from end to start */
-  fclose(fl);
-  return;
+  strlcpy(buf, asctime(localtime(&when)), MIN(len, 10));
 }
 
 
-#define BAN_LIST_FORMAT "%-25.25s  %-8.8s  %-10.10s  %-16.16s\r\n"
+#define BAN_LIST_FORMAT "%-25.25s  %-8.8s  %-10.10s  %-16.16s  %-10.10s\r\n"
 ACMD(do_ban)
 {
-  char flag[MAX_INPUT_LENGTH], site[MAX_INPUT_LENGTH], *nextchar;
-  char timestr[16];
-  int i;
-  struct ban_list_element *ban_node;
+  char flag[MAX_INPUT_LENGTH], site[MAX_INPUT_LENGTH], duration[MAX_INPUT_LENGTH];
+  char timestr[16], expirestr[16], error[MAX_STRING_LENGTH];
+  char ban_site_name[BANNED_SITE_LENGTH + 1], ban_name[MAX_NAME_LENGTH + 1];
+  long long date, expires;
+  int i, type;
 
   if (!*argument) {
-    if (!ban_list) {
+    type = get_ban(mother_desc, 0, ban_site_name, sizeof(ban_site_name),
+		ban_name, sizeof(ban_name), &date, &expires);
+    if (type <= 0) {
       send_to_char(ch, "No sites are banned.\r\n");
       return;
     }
@@ -143,61 +93,60 @@ ACMD(do_ban)
 	    "Banned Site Name",
 	    "Ban Type",
 	    "Banned On",
-	    "Banned By");
+	    "Banned By",
+	    "Expires");
     send_to_char(ch, BAN_LIST_FORMAT,
 	    "---------------------------------",
 	    "---------------------------------",
 	    "---------------------------------",
+	    "---------------------------------",
 	    "---------------------------------");
 
-    for (ban_node = ban_list; ban_node; ban_node = ban_node->next) {
-      if (ban_node->date) {
-	strlcpy(timestr, asctime(localtime(&(ban_node->date))), 10);
-	timestr[10] = '\0';
-      } else
+    for (i = 1; type > 0; i++) {
+      if (date)
+	ban_date(timestr, sizeof(timestr), date);
+      else
 	strcpy(timestr, "Unknown");	/* strcpy: OK (strlen("Unknown") < 16) */
+      if (expires)
+	ban_date(expirestr, sizeof(expirestr), expires);
+      else
+	strcpy(expirestr, "Never");	/* strcpy: OK (strlen("Never") < 16) */
+
+      send_to_char(ch, BAN_LIST_FORMAT, ban_site_name, ban_types[type], timestr, ban_name, expirestr);
 
-      send_to_char(ch, BAN_LIST_FORMAT, ban_node->site, ban_types[ban_node->type], timestr, ban_node->name);
+      type = get_ban(mother_desc, i, ban_site_name, sizeof(ban_site_name),
+		ban_name, sizeof(ban_name), &date, &expires);
     }
     return;
   }
 
-  two_arguments(argument, flag, site);
+  one_argument(two_arguments(argument, flag, site), duration);
   if (!*site || !*flag) {
-    send_to_char(ch, "Usage: ban {all | select | new} site_name\r\n");
+    send_to_char(ch, "Usage: ban {all | select | new} site_name [duration]\r\n");
     return;
   }
   if (!(!str_cmp(flag, "select") || !str_cmp(flag, "all") || !str_cmp(flag, "new"))) {
     send_to_char(ch, "Flag must be ALL, SELECT, or NEW.\r\n");
     return;
   }
-  for (ban_node = ban_list; ban_node; ban_node = ban_node->next) {
-    if (!str_cmp(ban_node->site, site)) {
-      send_to_char(ch, "That site has already been banned -- unban it to change the ban type.\r\n");
-      return;
-    }
-  }
 
-  CREATE(ban_node, struct ban_list_element, 1);
-  strncpy(ban_node->site, site, BANNED_SITE_LENGTH);	/* strncpy: OK (b_n->site:BANNED_SITE_LENGTH+1) */
-  for (nextchar = ban_node->site; *nextchar; nextchar++)
-    *nextchar = LOWER(*nextchar);
-  ban_node->site[BANNED_SITE_LENGTH] = '\0';
-  strncpy(ban_node->name, GET_NAME(ch), MAX_NAME_LENGTH);	/* strncpy: OK (b_n->size:MAX_NAME_LENGTH+1) */
-  ban_node->name[MAX_NAME_LENGTH] = '\0';
-  ban_node->date = time(0);
-
-  for (i = BAN_NEW; i <= BAN_ALL; i++)
+  for (type = i = BAN_NEW; i <= BAN_ALL; i++)
     if (!str_cmp(flag, ban_types[i]))
-      ban_node->type = i;
+      type = i;
 
-  ban_node->next = ban_list;
-  ban_list = ban_node;
+  *error = '\0';
+  if (ban_site(mother_desc, type, site, *duration ? duration : NULL, GET_NAME(ch), error, sizeof(error)) < 0) {
+    send_to_char(ch, "%s\r\n", *error ? error : "The site could not be banned.");
+    return;
+  }
 
-  mudlog(NRM, MAX(LVL_GOD, GET_INVIS_LEV(ch)), TRUE, "%s has banned %s for %s players.",
-	GET_NAME(ch), site, ban_types[ban_node->type]);
+  if (*duration)
+    mudlog(NRM, MAX(LVL_GOD, GET_INVIS_LEV(ch)), TRUE, "%s has banned %s for %s players for %s.",
+	GET_NAME(ch), site, ban_types[type], duration);
+  else
+    mudlog(NRM, MAX(LVL_GOD, GET_INVIS_LEV(ch)), TRUE, "%s has banned %s for %s players.",
+	GET_NAME(ch), site, ban_types[type]);
   send_to_char(ch, "Site banned.\r\n");
-  write_ban_list();
 }
 #undef BAN_LIST_FORMAT
 
@@ -205,33 +154,24 @@ ACMD(do_ban)
 ACMD(do_unban)
 {
   char site[MAX_INPUT_LENGTH];
-  struct ban_list_element *ban_node, *temp;
-  int found = 0;
+  int type;
 
   one_argument(argument, site);
   if (!*site) {
     send_to_char(ch, "A site to unban might help.\r\n");
     return;
   }
-  ban_node = ban_list;
-  while (ban_node && !found) {
-    if (!str_cmp(ban_node->site, site))
-      found = 1;
-    else
-      ban_node = ban_node->next;
-  }
 
-  if (!found) {
+  if ((type = unban_site(mother_desc, site)) < 0) {
+    send_to_char(ch, "The ban could not be removed.\r\n");
+    return;
+  } else if (type == BAN_NOT) {
     send_to_char(ch, "That site is not currently banned.\r\n");
     return;
   }
-  REMOVE_FROM_LIST(ban_node, ban_list, next);
   send_to_char(ch, "Site unbanned.\r\n");
   mudlog(NRM, MAX(LVL_GOD, GET_INVIS_LEV(ch)), TRUE, "%s removed the %s-player ban on %s.",
-	GET_NAME(ch), ban_types[ban_node->type], ban_node->site);
-
-  free(ban_node);
-  write_ban_list();
+	GET_NAME(ch), ban_types[type], site);
 }
 
 
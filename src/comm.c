@@ -72,7 +72,6 @@
 #endif
 
 /* externs */
-extern struct ban_list_element *ban_list;
 extern int num_invalid;
 extern char *GREETINGS;
 extern const char *circlemud_version;
@@ -88,19 +87,26 @@ extern int max_playing;
 extern int nameserver_is_slow;	/* see config.c */
 extern int auto_save;		/* see config.c */
 extern int autosave_time;	/* see config.c */
+extern int shutdown_timeout;	/* see config.c */
 extern int *cmd_sort_info;
 
 extern struct time_info_data time_info;		/* In db.c */
 extern char *help;
+extern const char *mirrored_channels[];	/* In act.comm.c */
+extern const char *class_abbrevs[];	/* In class.c */
 
 /* local globals */
 struct descriptor_data *descriptor_list = NULL;		/* master desc list */
+struct DescriptorManager *mother_desc = NULL;	/* source of new descriptors */
 struct txt_block *bufpool = 0;	/* pool of large output buffers */
 int buf_largecount = 0;		/* # of large buffers which exist */
 int buf_overflows = 0;		/* # of overflows of output */
 int buf_switches = 0;		/* # of switches from small to large buf */
 int circle_shutdown = 0;	/* clean shutdown */
 int circle_reboot = 0;		/* reboot the game after a shutdown */
+int circle_copyover = 0;	/* restart the game keeping connections */
+char **copyover_argv = NULL;	/* how the game was started...	*/
+char copyover_dir[MAX_STRING_LENGTH];	/* ...and from where, to copyover */
 int no_specials = 0;		/* Suppress ass. of special routines */
 int max_players = 0;		/* max descriptors available */
 int tics = 0;			/* for extern checkpointing */
@@ -110,6 +116,7 @@ byte reread_wizlist;		/* signal: SIGUSR1 */
 byte emergency_unban;		/* signal: SIGUSR2 */
 FILE *logfile = NULL;		/* Where to send the log messages. */
 const char *text_overflow = "**OVERFLOW**\r\n";
+int speaking_from_channel = FALSE;	/* speech came from a mirrored channel */
 
 /* functions in this file */
 RETSIGTYPE reread_wizlists(int sig);
@@ -124,7 +131,12 @@ int get_from_q(struct txt_q *queue, char *dest, int *aliased);
 void init_game(ush_int port);
 void signal_setup(void);
 void game_loop(struct DescriptorManager *mother_desc);
-int new_descriptor_data(struct DescriptorManager *manager, struct Descriptor *desc);
+int new_descriptor_data(struct DescriptorManager *manager, descriptor_id desc);
+struct descriptor_data *init_descriptor_data(struct DescriptorManager *manager, descriptor_id desc);
+void copyover(void);
+void copyover_recover(void);
+void log_from_mud_comms(const char *message);
+void mudlog_from_mud_comms(int type, const char *message);
 int get_max_players(void);
 int process_output(struct DescriptorManager *manager, struct descriptor_data *t);
 int process_input(struct DescriptorManager *manager, struct descriptor_data *t);
@@ -135,6 +147,9 @@ int perform_subst(struct descriptor_data *t, char *orig, char *subst);
 void record_usage(void);
 char *make_prompt(struct descriptor_data *point);
 void check_idle_passwords(void);
+void process_channel_messages(void);
+void process_admin_commands(void);
+size_t describe_connections(char *buf, size_t len);
 void heartbeat(int pulse);
 struct in_addr *get_bind_addr(void);
 int parse_ip(const char *addr, struct in_addr *inaddr);
@@ -151,9 +166,9 @@ void affect_update(void);	/* In magic.c */
 void mobile_activity(void);
 void perform_violence(void);
 void show_string(struct descriptor_data *d, char *input);
-int isbanned(char *hostname);
 void weather_and_time(int mode);
 int perform_alias(struct descriptor_data *d, char *orig, size_t maxlen);
+void reattach_linked_character(struct descriptor_data *d);
 void clear_free_list(void);
 void free_messages(void);
 void Board_clear_all(void);
@@ -296,6 +311,11 @@ int main(int argc, char **argv)
     }
   }
 
+  /* Remember how we were started, for a copyover to start us again. */
+  copyover_argv = argv;
+  if (getcwd(copyover_dir, sizeof(copyover_dir)) == NULL)
+    *copyover_dir = '\0';
+
   /* All arguments have been parsed, try to open log file. */
   setup_log(LOGNAME, STDERR_FILENO);
 
@@ -343,7 +363,7 @@ int main(int argc, char **argv)
 /* Init sockets, run game, and cleanup sockets */
 void init_game(ush_int port)
 {
-  struct DescriptorManager *mother_desc;
+  struct descriptor_data *d, *next_d;
 
   /* We don't want to restart if we crash before we get up. */
   touch(KILLSCRIPT_FILE);
@@ -354,6 +374,8 @@ void init_game(ush_int port)
   max_players = get_max_players();
 
   log("Opening mother connection.");
+  set_log_callback(log_from_mud_comms);
+  set_mudlog_callback(mudlog_from_mud_comms);
   if((mother_desc = new_descriptor_manager(port)) == NULL) {
       log("Unable to open mother connection.");
       exit(1);
@@ -361,6 +383,8 @@ void init_game(ush_int port)
 
   boot_db();
 
+  copyover_recover();
+
 #if defined(CIRCLE_UNIX) || defined(CIRCLE_MACINTOSH)
   log("Signal trapping.");
   signal_setup();
@@ -375,11 +399,23 @@ void init_game(ush_int port)
 
   Crash_save_all();
 
+  if (circle_copyover)
+    copyover();
+
   log("Closing all sockets.");
+  for (d = descriptor_list; d; d = next_d) {
+    next_d = d->next;
+    if (*(d->output))
+      process_output(mother_desc, d);
+  }
+  shutdown_descriptor_manager(mother_desc, circle_reboot ?
+	"\r\nThe game is rebooting, please reconnect in a minute or two.\r\n" :
+	"\r\nThe game is shutting down, goodbye!\r\n", shutdown_timeout);
   while (descriptor_list)
     close_descriptor_data(mother_desc, descriptor_list);
 
   close_descriptor_manager(mother_desc);
+  mother_desc = NULL;
   fclose(player_fl);
 
   log("Saving current MUD time.");
@@ -392,6 +428,114 @@ void init_game(ush_int port)
   log("Normal termination of game.");
 }
 
+
+/* Where mud-comms logs to with MUD_COMMS_LOG_OUTPUT=circle. */
+void log_from_mud_comms(const char *message)
+{
+  log("%s", message);
+}
+
+
+/*
+ * Where mud-comms sends its warnings and errors (or whatever MUD_COMMS_MUDLOG
+ * asks for) for immortals on the syslog.  They're already in the log file.
+ */
+void mudlog_from_mud_comms(int type, const char *message)
+{
+  mudlog(type, type == BRF ? LVL_IMMORT : LVL_GOD, FALSE, "%s", message);
+}
+
+
+/*
+ * Hand every playing connection over to a new copy of the game.  Their
+ * characters are saved where they stand and each connection is tagged with
+ * "<name> <room vnum>" for copyover_recover() to bring them back with; all
+ * other connections are asked to come back later.  Only returns if the new
+ * copy couldn't be started, in which case we reboot instead.
+ */
+void copyover(void)
+{
+  struct descriptor_data *d, *next_d;
+  struct char_data *ch;
+  char tag[MAX_INPUT_LENGTH];
+
+  log("Copyover: saving connections.");
+  for (d = descriptor_list; d; d = next_d) {
+    next_d = d->next;
+    ch = d->original ? d->original : d->character;
+
+    if (STATE(d) != CON_PLAYING || !ch || IS_NPC(ch)) {
+      write_to_descriptor(mother_desc, d->descriptor, "\r\nRebooting, please reconnect in a moment.\r\n");
+      close_descriptor_data(mother_desc, d);
+      continue;
+    }
+
+    Crash_crashsave(ch);
+    save_char(ch);
+    snprintf(tag, sizeof(tag), "%s %d", GET_NAME(ch), (int) GET_ROOM_VNUM(IN_ROOM(ch)));
+    tag_descriptor(mother_desc, d->descriptor, tag);
+    write_to_descriptor(mother_desc, d->descriptor, "\r\nTime stands still for a moment...\r\n");
+  }
+
+  save_mud_time(&time_info);
+  /* Nothing buffered survives the exec. */
+  fflush(NULL);
+
+  exec_copyover(mother_desc, copyover_dir, copyover_argv);
+
+  log("SYSERR: Copyover failed, rebooting instead.");
+  touch(FASTBOOT_FILE);
+  circle_reboot = 1;
+}
+
+
+/*
+ * Reattach the connections handed over by a copyover to their characters,
+ * putting them back in the room they were saved in.  Connections that
+ * can't be reattached start over at the login screen.
+ */
+void copyover_recover(void)
+{
+  struct descriptor_data *d;
+  struct char_file_u tmp_store;
+  descriptor_id desc;
+  char tag[MAX_INPUT_LENGTH], name[MAX_INPUT_LENGTH];
+  int player_i, room;
+  room_rnum saved_room;
+
+  while (next_restored_descriptor(mother_desc, &desc, tag, sizeof(tag)) > 0) {
+    if ((d = init_descriptor_data(mother_desc, desc)) == NULL)
+      continue;
+
+    if (sscanf(tag, "%s %d", name, &room) != 2 ||
+	(player_i = load_char(name, &tmp_store)) < 0) {
+      write_to_output(d, "\r\nYour character could not be restored.\r\n%s", GREETINGS);
+      continue;
+    }
+
+    CREATE(d->character, struct char_data, 1);
+    clear_char(d->character);
+    CREATE(d->character->player_specials, struct player_special_data, 1);
+    d->character->desc = d;
+    store_to_char(&tmp_store, d->character);
+    GET_PFILEPOS(d->character) = player_i;
+    REMOVE_BIT(PLR_FLAGS(d->character), PLR_WRITING | PLR_MAILING | PLR_CRYO);
+    REMOVE_BIT(AFF_FLAGS(d->character), AFF_GROUP);
+
+    enter_player_game(d);
+    if ((saved_room = real_room((room_vnum) room)) != NOWHERE) {
+      char_from_room(d->character);
+      char_to_room(d->character, saved_room);
+    }
+    STATE(d) = CON_PLAYING;
+    d->has_prompt = 0;
+
+    write_to_output(d, "Time resumes.\r\n");
+    look_at_room(d->character, 0);
+    mudlog(NRM, MAX(LVL_IMMORT, GET_INVIS_LEV(d->character)), TRUE, "%s recovered from copyover.", GET_NAME(d->character));
+  }
+}
+
 int get_max_players(void)
 {
 #ifndef CIRCLE_UNIX
@@ -504,7 +648,7 @@ void game_loop(struct DescriptorManager* mother_desc)
   /* The Main Loop.  The Big Cheese.  The Top Dog.  The Head Honcho.  The.. */
   while (!circle_shutdown) {
 
-    struct Descriptor* new_d = NULL;
+    descriptor_id new_d = 0;
     /* Sleep if we don't have any connections */
     if (descriptor_list == NULL) {
       log("No connections.  Going to sleep.");
@@ -557,7 +701,7 @@ void game_loop(struct DescriptorManager* mother_desc)
       timediff(&timeout, &last_time, &now);
     } while (timeout.tv_usec || timeout.tv_sec);
 
-    if((new_d = new_descriptor(mother_desc)) != NULL) {
+    if((new_d = new_descriptor(mother_desc)) != 0) {
       new_descriptor_data(mother_desc, new_d);
     }
 
@@ -626,6 +770,15 @@ void game_loop(struct DescriptorManager* mother_desc)
       }
     }
 
+    /* Speech from transport channels bound to rooms and chat channels */
+    process_channel_messages();
+
+    /* Commands from mudctl */
+    process_admin_commands();
+
+    /* mud-comms' warnings and errors, for immortals on the syslog */
+    deliver_mudlogs();
+
     /* Send queued output out to the operating system (ultimately to user). */
     for (d = descriptor_list; d; d = next_d) {
       next_d = d->next;
@@ -684,7 +837,7 @@ void game_loop(struct DescriptorManager* mother_desc)
     if (emergency_unban) {
       emergency_unban = FALSE;
       mudlog(BRF, LVL_IMMORT, TRUE, "Received SIGUSR2 - completely unrestricting game (emergent)");
-      ban_list = NULL;
+      clear_bans(mother_desc);
       circle_restrict = 0;
       num_invalid = 0;
     }
@@ -1132,11 +1285,28 @@ int parse_ip(const char *addr, struct in_addr *inaddr)
 
 
 
-int new_descriptor_data(struct DescriptorManager *manager, struct Descriptor *desc)
+int new_descriptor_data(struct DescriptorManager *manager, descriptor_id desc)
+{
+  struct descriptor_data *newd;
+
+  if ((newd = init_descriptor_data(manager, desc)) == NULL)
+    return (0);
+
+  write_to_output(newd, "%s", GREETINGS);
+  reattach_linked_character(newd);
+
+  return (0);
+}
+
+
+/*
+ * Set up the descriptor_data for a connection, returning NULL (with the
+ * connection closed) if the game is full or the site is banned.
+ */
+struct descriptor_data *init_descriptor_data(struct DescriptorManager *manager, descriptor_id desc)
 {
   int sockets_connected = 0;
   socklen_t i;
-  static int last_desc = 0;	/* last descriptor number */
   struct descriptor_data *newd;
   struct sockaddr_in peer;
   struct hostent *from;
@@ -1148,7 +1318,7 @@ int new_descriptor_data(struct DescriptorManager *manager, struct Descriptor *de
   if (sockets_connected >= max_players) {
     write_to_descriptor(manager, desc, "Sorry, CircleMUD is full right now... please try again later!\r\n");
     close_descriptor(manager, desc);
-    return (0);
+    return (NULL);
   }
   /* create a new descriptor */
   CREATE(newd, struct descriptor_data, 1);
@@ -1159,13 +1329,7 @@ int new_descriptor_data(struct DescriptorManager *manager, struct Descriptor *de
           perror("SYSERR: get_descriptor_hostname");
       }
 
-  /* determine if the site is banned */
-  if (isbanned(newd->host) == BAN_ALL) {
-    close_descriptor(manager, desc);
-    mudlog(CMP, LVL_GOD, TRUE, "Connection attempt denied from [%s]", newd->host);
-    free(newd);
-    return (0);
-  }
+  /* sites banned outright were already refused by new_descriptor() */
 #if 0
   /*
    * Log new connections - probably unnecessary, but you may want it.
@@ -1193,17 +1357,14 @@ int new_descriptor_data(struct DescriptorManager *manager, struct Descriptor *de
    */
   CREATE(newd->history, char *, HISTORY_SIZE);
 
-  if (++last_desc == 1000)
-    last_desc = 1;
-  newd->desc_num = last_desc;
+  /* Number it the same as mud-comms does, for admin tools using either. */
+  newd->desc_num = (int) desc;
 
   /* prepend to list */
   newd->next = descriptor_list;
   descriptor_list = newd;
 
-  write_to_output(newd, "%s", GREETINGS);
-
-  return (0);
+  return (newd);
 }
 
 
@@ -1593,12 +1754,15 @@ void close_descriptor_data(struct DescriptorManager *manager, struct descriptor_
 void check_idle_passwords(void)
 {
   struct descriptor_data *d, *next_d;
+  long long idle;
 
   for (d = descriptor_list; d; d = next_d) {
     next_d = d->next;
     if (STATE(d) != CON_PASSWORD && STATE(d) != CON_GET_NAME)
       continue;
-    if (!d->idle_tics) {
+    /* Time the prompt by the clock where mud-comms can, by passes otherwise */
+    idle = get_descriptor_idle_seconds(mother_desc, d->descriptor);
+    if (idle >= 0 ? idle < PULSE_IDLEPWD / PASSES_PER_SEC : !d->idle_tics) {
       d->idle_tics++;
       continue;
     } else {
@@ -1792,6 +1956,199 @@ void send_to_outdoor(const char *messg, ...)
 }
 
 
+/*
+ * Copies speech to any transport channels (ie Slack) bound to target, which
+ * is "room:<vnum>" or one of the mirrored_channels.
+ */
+void send_to_mirrors(const char *target, const char *messg, ...)
+{
+  char buf[MAX_STRING_LENGTH];
+  va_list args;
+
+  /* The channel it came from already shows it. */
+  if (!mother_desc || speaking_from_channel || !messg || !*messg)
+    return;
+
+  va_start(args, messg);
+  vsnprintf(buf, sizeof(buf), messg, args);
+  va_end(args);
+
+  mirror_to_channels(mother_desc, target, buf);
+}
+
+
+/*
+ * Runs speech from bound transport channels as a 'say' or channel command by
+ * the speaker's linked character.  It's dropped unless the character is
+ * playing, and in the bound room for room channels.
+ */
+void process_channel_messages(void)
+{
+  char target[MAX_INPUT_LENGTH], name[MAX_INPUT_LENGTH], text[MAX_INPUT_LENGTH];
+  char line[MAX_INPUT_LENGTH], *write_point;
+  const char *ptr;
+  struct descriptor_data *d;
+  int channel;
+
+  while (next_channel_message(mother_desc, target, sizeof(target), name, sizeof(name), text, sizeof(text)) > 0) {
+    for (d = descriptor_list; d; d = d->next)
+      if (STATE(d) == CON_PLAYING && d->character && !str_cmp(GET_NAME(d->character), name))
+	break;
+
+    if (!d || d->str)
+      continue;
+
+    if (!strn_cmp(target, "room:", 5)) {
+      if (GET_ROOM_VNUM(IN_ROOM(d->character)) != atoi(target + 5))
+	continue;
+      strlcpy(line, "say ", sizeof(line));
+    } else if ((channel = search_block(target, mirrored_channels, TRUE)) >= 0)
+      snprintf(line, sizeof(line), "%s ", mirrored_channels[channel]);
+    else {
+      log("SYSERR: Unknown channel target '%s' for %s.", target, name);
+      continue;
+    }
+
+    /* Filter and double '$' the same as process_input does for typed lines. */
+    write_point = line + strlen(line);
+    for (ptr = text; *ptr && write_point < line + sizeof(line) - 2; ptr++)
+      if (isascii(*ptr) && isprint(*ptr))
+	if ((*(write_point++) = *ptr) == '$')
+	  *(write_point++) = '$';
+    *write_point = '\0';
+
+    speaking_from_channel = TRUE;
+    command_interpreter(d->character, line);
+    speaking_from_channel = FALSE;
+  }
+}
+
+
+
+/*
+ * Counts the connections mud-comms has open by transport, ie "3 connected
+ * (2 TELNET, 1 SLACK)."  Returns the length written, as snprintf does.
+ */
+#define MAX_TRANSPORTS	8
+size_t describe_connections(char *buf, size_t len)
+{
+  char types[MAX_TRANSPORTS][MAX_INPUT_LENGTH];
+  int counts[MAX_TRANSPORTS], num_types = 0, total, i, j;
+  descriptor_id *ids;
+  size_t written;
+
+  if ((total = list_descriptors(mother_desc, NULL, 0)) < 0)
+    return snprintf(buf, len, "Unable to list connections.\n");
+
+  CREATE(ids, descriptor_id, total + 1);
+  if (list_descriptors(mother_desc, ids, total) < 0)
+    total = 0;
+
+  for (i = 0; i < total; i++) {
+    if (get_descriptor_type(mother_desc, ids[i], types[num_types], sizeof(types[num_types])) < 0)
+      strlcpy(types[num_types], "unknown", sizeof(types[num_types]));
+    for (j = 0; j < num_types; j++)
+      if (!strcmp(types[j], types[num_types]))
+	break;
+    if (j < num_types)
+      counts[j]++;
+    else if (num_types < MAX_TRANSPORTS - 1)
+      counts[num_types++] = 1;
+  }
+  free(ids);
+
+  written = snprintf(buf, len, "%d connected", total);
+  for (j = 0; j < num_types && written < len; j++)
+    written += snprintf(buf + written, len - written, "%s%d %s", j ? ", " : " (", counts[j], types[j]);
+  if (written < len)
+    written += snprintf(buf + written, len - written, "%s.\n", num_types ? ")" : "");
+  return (written);
+}
+
+
+/*
+ * Runs commands from mud-comms' admin socket (ie mudctl), answering each
+ * with what it did.  shutdown and reboot work like their 'shutdown' command
+ * counterparts, and close like 'dc' but by descriptor ID.
+ */
+void process_admin_commands(void)
+{
+  char command[MAX_INPUT_LENGTH], arg[MAX_INPUT_LENGTH], reply[MAX_STRING_LENGTH];
+  char type[MAX_INPUT_LENGTH], *rest, *end;
+  struct descriptor_data *d;
+  struct char_data *ch;
+  unsigned long long id, target;
+  size_t len;
+  int players;
+
+  while (next_admin_command(mother_desc, &id, command, sizeof(command)) > 0) {
+    rest = one_argument(command, arg);
+    skip_spaces(&rest);
+
+    if (!str_cmp(arg, "who")) {
+      len = 0;
+      players = 0;
+      for (d = descriptor_list; d; d = d->next) {
+	if (STATE(d) != CON_PLAYING)
+	  continue;
+	ch = d->original ? d->original : d->character;
+	players++;
+	if (get_descriptor_type(mother_desc, d->descriptor, type, sizeof(type)) < 0)
+	  strlcpy(type, "unknown", sizeof(type));
+	if (len < sizeof(reply))
+	  len += snprintf(reply + len, sizeof(reply) - len, "%4llu [%2d %s] %s (%s)%s\n", d->descriptor,
+		GET_LEVEL(ch), CLASS_ABBR(ch), GET_NAME(ch), type, d->original ? " (switched)" : "");
+      }
+      if (len < sizeof(reply))
+	len += snprintf(reply + len, sizeof(reply) - len, "%d playing, ", players);
+      if (len < sizeof(reply))
+	describe_connections(reply + len, sizeof(reply) - len);
+    } else if (!str_cmp(arg, "shutdown")) {
+      log("(GC) Shutdown by mudctl.");
+      send_to_all("Shutting down.\r\n");
+      circle_shutdown = 1;
+      strlcpy(reply, "Shutting down.\n", sizeof(reply));
+    } else if (!str_cmp(arg, "reboot")) {
+      log("(GC) Reboot by mudctl.");
+      send_to_all("Rebooting.. come back in a minute or two.\r\n");
+      touch(FASTBOOT_FILE);
+      circle_shutdown = circle_reboot = 1;
+      strlcpy(reply, "Rebooting.\n", sizeof(reply));
+    } else if (!str_cmp(arg, "wall")) {
+      if (!*rest)
+	strlcpy(reply, "Wall what?\n", sizeof(reply));
+      else {
+	log("(GC) mudctl walled: %s", rest);
+	send_to_all("%s\r\n", rest);
+	strlcpy(reply, "Sent.\n", sizeof(reply));
+      }
+    } else if (!str_cmp(arg, "close")) {
+      target = strtoull(rest, &end, 10);
+      for (d = descriptor_list; d && d->descriptor != target; d = d->next);
+
+      if (!*rest || *end || !target)
+	strlcpy(reply, "Close which connection?  Give its ID, as who shows.\n", sizeof(reply));
+      else if (!d)
+	strlcpy(reply, "No such connection.\n", sizeof(reply));
+      else if (STATE(d) == CON_DISCONNECT || STATE(d) == CON_CLOSE)
+	strlcpy(reply, "It's already being closed.\n", sizeof(reply));
+      else {
+	/* As in do_dc, players leave the game before their connection goes */
+	if (STATE(d) == CON_PLAYING)
+	  STATE(d) = CON_DISCONNECT;
+	else
+	  STATE(d) = CON_CLOSE;
+	log("(GC) Connection %llu closed by mudctl.", target);
+	snprintf(reply, sizeof(reply), "Connection %llu closed.\n", target);
+      }
+    } else
+      snprintf(reply, sizeof(reply), "Unknown command '%s'.  Try who, shutdown, reboot, wall <message> or close <id>.\n", arg);
+
+    reply_admin_command(mother_desc, id, reply);
+  }
+}
+
+
 
 void send_to_room(room_rnum room, const char *messg, ...)
 {
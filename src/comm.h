@@ -16,6 +16,7 @@ size_t	send_to_char(struct char_data *ch, const char *messg, ...) __attribute__
 void	send_to_all(const char *messg, ...) __attribute__ ((format (printf, 1, 2)));
 void	send_to_room(room_rnum room, const char *messg, ...) __attribute__ ((format (printf, 2, 3)));
 void	send_to_outdoor(const char *messg, ...) __attribute__ ((format (printf, 1, 2)));
+void	send_to_mirrors(const char *target, const char *messg, ...) __attribute__ ((format (printf, 2, 3)));
 void	close_descriptor_data(struct DescriptorManager *manager, struct descriptor_data *d);
 
 void	perform_act(const char *orig, struct char_data *ch,
@@ -266,6 +266,12 @@ int siteok_everyone = TRUE;
 
 int nameserver_is_slow = NO;
 
+/*
+ * How many seconds a shutdown or reboot waits for its goodbye (and any
+ * other output still queued) to reach each connection before closing them.
+ */
+int shutdown_timeout = 10;
+
 
 const char *MENU =
 "\r\n"
@@ -1,16 +1,150 @@
+#ifndef _DESCRIPTOR_H_
+#define _DESCRIPTOR_H_
+
 #include <stddef.h>
 struct DescriptorManager; // eg for Telnet descriptors, monitors new telnet connections and returns them via new_descriptor
-struct Descriptor; // the handle for IO (ie a Telnet socket)
+typedef unsigned long long descriptor_id; // the handle for IO (ie a Telnet socket), never reused; 0 is no descriptor
 
 
+/*
+ * With MUD_COMMS_LOG_OUTPUT=circle, mud-comms hands each line it logs to
+ * this callback (which may be called from any of its threads) instead of
+ * writing to stdout.  Set it before new_descriptor_manager.  mud-comms'
+ * logging is otherwise configured by MUD_COMMS_LOG, MUD_COMMS_LOG_FORMAT,
+ * MUD_COMMS_LOG_MAX_SIZE and MUD_COMMS_LOG_KEEP (see README.md).
+ */
+void set_log_callback(void (*callback)(const char *message));
+/*
+ * Records at or above MUD_COMMS_MUDLOG (warn by default) are also handed to
+ * this callback with a mudlog() type: BRF for errors, NRM for warnings and
+ * CMP for the rest.  It's only called from deliver_mudlogs, which the game
+ * loop calls once a pass, rather than from mud-comms' own threads.
+ */
+void set_mudlog_callback(void (*callback)(int type, const char *message));
+void deliver_mudlogs(void);
 struct DescriptorManager* new_descriptor_manager(unsigned short int port);
+/*
+ * Stops accepting connections and ends the manager's background threads,
+ * then sends every open descriptor the message, waiting up to timeout_seconds
+ * for slow clients to take it and hang up.  The descriptors still need
+ * closing as usual, before close_descriptor_manager frees the manager.
+ */
+int shutdown_descriptor_manager(struct DescriptorManager *manager, const char *message, unsigned int timeout_seconds);
 int close_descriptor_manager(struct DescriptorManager *manager);
 
 int block_until_descriptor(struct DescriptorManager *manager);
-struct Descriptor* new_descriptor(struct DescriptorManager *manager);
-int close_descriptor(struct DescriptorManager *manager, struct Descriptor *descriptor);
+descriptor_id new_descriptor(struct DescriptorManager *manager);
+int close_descriptor(struct DescriptorManager *manager, descriptor_id descriptor);
+
+/*
+ * list_descriptors fills ids with up to len open descriptors, oldest first.
+ *
+ * Returns:
+ * >=0  The number of open descriptors, which may be more than len.
+ *  -1  If an error was encountered.
+ */
+int list_descriptors(struct DescriptorManager *manager, descriptor_id *ids, size_t len);
+int get_descriptor_type(struct DescriptorManager *manager, descriptor_id descriptor, char *type, size_t len);
+int get_descriptor_hostname(struct DescriptorManager *manager, descriptor_id descriptor, char *hostname, size_t len);
+
+/*
+ * Account linking for transports that verify who their users are (ie Slack).
+ * get_descriptor_identity and get_descriptor_linked_character fill the buffer
+ * with an empty string when the descriptor has no identity or link.  Passing
+ * a NULL character to link_descriptor_character removes the link.
+ * unlink_character removes every account's link to a character, for when it
+ * is deleted or its name is taken by a new character.
+ *
+ * Returns:
+ *   0  If all is well and good.
+ *  -1  If an error was encountered.
+ */
+int get_descriptor_identity(struct DescriptorManager *manager, descriptor_id descriptor, char *identity, size_t len);
+int get_descriptor_linked_character(struct DescriptorManager *manager, descriptor_id descriptor, char *character, size_t len);
+int link_descriptor_character(struct DescriptorManager *manager, descriptor_id descriptor, const char *character);
+int unlink_character(struct DescriptorManager *manager, const char *character);
+
+/*
+ * Channel mode, for transports whose channels can be bound to a room
+ * ("room:<vnum>") or chat channel ("gossip").  mirror_to_channels copies
+ * text to every channel bound to target.  next_channel_message fills the
+ * buffers with the target, the speaker's linked character and one line of
+ * what they said.
+ *
+ * Returns:
+ *   1  If next_channel_message filled in a message.
+ *   0  If all is well and good (and there was no message).
+ *  -1  If an error was encountered.
+ */
+int mirror_to_channels(struct DescriptorManager *manager, const char *target, const char *text);
+int next_channel_message(struct DescriptorManager *manager, char *target, size_t target_len, char *character, size_t character_len, char *text, size_t text_len);
 
-int get_descriptor_hostname(struct DescriptorManager *manager, struct Descriptor *descriptor, char *hostname, size_t len);
+/*
+ * Admin commands (eg "who" or "wall <message>") from mudctl, over the admin
+ * socket at MUD_COMMS_ADMIN_SOCKET.  next_admin_command fills in an ID and
+ * the command, which must be answered exactly once by passing the ID and
+ * what it did to reply_admin_command.
+ *
+ * Returns:
+ *   1  If next_admin_command filled in a command.
+ *   0  If all is well and good (and there was no command).
+ *  -1  If an error was encountered.
+ */
+int next_admin_command(struct DescriptorManager *manager, unsigned long long *id, char *command, size_t len);
+int reply_admin_command(struct DescriptorManager *manager, unsigned long long id, const char *reply);
+
+/*
+ * Copyover, which restarts the game without dropping its connections.
+ * tag_descriptor marks a descriptor with whatever is needed to find its
+ * place again (a NULL tag removes it).  exec_copyover saves every open
+ * descriptor and runs the NULL terminated argv from dir in place of this
+ * process; it only returns if that failed.  The new process's manager
+ * restores them, and next_restored_descriptor hands each one back with its
+ * tag (an empty string if it had none).
+ *
+ * Returns:
+ *   1  If next_restored_descriptor filled in a descriptor.
+ *   0  If all is well and good (and there are no more restored descriptors).
+ *  -1  If an error was encountered.
+ */
+int tag_descriptor(struct DescriptorManager *manager, descriptor_id descriptor, const char *tag);
+int exec_copyover(struct DescriptorManager *manager, const char *dir, char *const argv[]);
+int next_restored_descriptor(struct DescriptorManager *manager, descriptor_id *descriptor, char *tag, size_t len);
+/*
+ * mark_descriptor_authenticated tells the manager a descriptor has logged
+ * in, so it no longer counts against MUD_COMMS_MAX_UNAUTHENTICATED (the limit
+ * on connections still at the login prompts).  Descriptors restored by a
+ * copyover are already marked.
+ *
+ * Returns:
+ *   0  If all is well and good.
+ *  -1  If an error was encountered.
+ */
+int mark_descriptor_authenticated(struct DescriptorManager *manager, descriptor_id descriptor);
+
+/*
+ * The ban list (BAN_FILE), which the manager uses to refuse connections from
+ * BAN_ALL sites before new_descriptor() returns them.  Sites may be an
+ * address or CIDR block (IPv4 or IPv6), a hostname pattern with * and ?
+ * wildcards, or any part of a hostname.  load_bans replaces the list with the
+ * file's, which ban_site and unban_site then save to; clear_bans empties it
+ * without touching the file.  ban_site takes a BAN_xxx type and a duration
+ * such as "30m", "12h", "7d" or "2w" (NULL to never expire), filling error
+ * with why if the site can't be banned.
+ *
+ * Returns:
+ * >=0  The BAN_xxx type for get_descriptor_ban, unban_site (BAN_NOT if the
+ *      site wasn't banned) and get_ban (BAN_NOT past the last ban in force,
+ *      newest first; expires is 0 if the ban never does).
+ *   0  If all is well and good, for the others.
+ *  -1  If an error was encountered.
+ */
+int load_bans(struct DescriptorManager *manager, const char *path);
+int get_descriptor_ban(struct DescriptorManager *manager, descriptor_id descriptor);
+int ban_site(struct DescriptorManager *manager, int type, const char *site, const char *duration, const char *name, char *error, size_t error_len);
+int unban_site(struct DescriptorManager *manager, const char *site);
+int get_ban(struct DescriptorManager *manager, size_t index, char *site, size_t site_len, char *name, size_t name_len, long long *date, long long *expires);
+int clear_bans(struct DescriptorManager *manager);
 /*
  * write_to_descriptor takes a descriptor, and text to write to the
  * descriptor.  It keeps calling the system-level write() until all
@@ -21,7 +155,7 @@ int get_descriptor_hostname(struct DescriptorManager *manager, struct Descriptor
  * >=0  If all is well and good.
  *  -1  If an error was encountered, so that the player should be cut off.
  */
-int write_to_descriptor(struct DescriptorManager *manager, struct Descriptor *descriptor, const char *content);
+int write_to_descriptor(struct DescriptorManager *manager, descriptor_id descriptor, const char *content);
 /*
 int write_to_descriptor(socket_t desc, const char *txt)
 {
@@ -144,7 +278,60 @@ ssize_t perform_socket_write(socket_t desc, const char *txt, size_t length)
 #endif CIRCLE_WINDOWS
 */
 
-int read_from_descriptor(struct DescriptorManager *manager, struct Descriptor *descriptor, char *read_point, size_t space_left);
+/*
+ * Returns:
+ *  >0  The number of bytes read.
+ *   0  If there's nothing to read yet.
+ *  -1  If the descriptor should be closed, including when the client has
+ *      hung up.
+ */
+int read_from_descriptor(struct DescriptorManager *manager, descriptor_id descriptor, char *read_point, size_t space_left);
+
+/*
+ * read_from_descriptor only hands over input as fast as the manager's input
+ * limits allow, holding the rest back (MUD_COMMS_INPUT_LINE_RATE and
+ * MUD_COMMS_INPUT_BYTE_RATE), and never a line longer than
+ * MUD_COMMS_MAX_LINE_LENGTH.  get_descriptor_input_stats fills in how much a
+ * descriptor has sent and how often it ran into those limits.
+ *
+ * Returns:
+ *   0  If all is well and good.
+ *  -1  If an error was encountered.
+ */
+struct input_stats {
+  unsigned long long bytes;	/* read from the client			*/
+  unsigned long long lines;	/* handed over to the game		*/
+  unsigned long long throttled;	/* times input was held back		*/
+  unsigned long long truncated;	/* overlong lines cut short		*/
+  unsigned long long dropped;	/* overlong lines discarded		*/
+};
+int get_descriptor_input_stats(struct DescriptorManager *manager, descriptor_id descriptor, struct input_stats *stats);
+
+/*
+ * Fills buf with a few lines on every transport's connections, traffic and
+ * failures (and Slack API latency), as shown by "show stats".  The same
+ * figures are served to Prometheus from /metrics on MUD_COMMS_METRICS_ADDR
+ * (eg 127.0.0.1:9100) if it's set.
+ *
+ * Returns:
+ *   0 on success
+ *   -1 on error
+ */
+int get_transport_stats(char *buf, size_t len);
+
+/*
+ * How many seconds since a descriptor last sent anything (or connected, if
+ * it never has), so idling is timed by the clock rather than by counting
+ * game ticks.  The manager itself closes connections that haven't logged in
+ * within MUD_COMMS_LOGIN_TIMEOUT seconds (default 300, 0 for never) and
+ * probes quiet TCP connections with keepalives (MUD_COMMS_KEEPALIVE_IDLE,
+ * MUD_COMMS_KEEPALIVE_INTERVAL, MUD_COMMS_KEEPALIVE_PROBES).
+ *
+ * Returns:
+ *   The seconds idle
+ *   -1 if the descriptor can't be found
+ */
+long long get_descriptor_idle_seconds(struct DescriptorManager *manager, descriptor_id descriptor);
 /*
  * Same information about perform_socket_write applies here. I like
  * standards, there are so many of them. -gg 6/30/98
@@ -212,3 +399,5 @@ ssize_t read_from_descriptor(socket_t desc, char *read_point, size_t space_left)
   return (-1);
 }
 */
+
+#endif /* _DESCRIPTOR_H_ */
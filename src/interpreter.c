@@ -40,6 +40,7 @@ extern int top_of_p_table;
 extern int circle_restrict;
 extern int no_specials;
 extern int max_bad_pws;
+extern struct DescriptorManager *mother_desc;
 
 /* external functions */
 void echo_on(struct descriptor_data *d);
@@ -47,13 +48,16 @@ void echo_off(struct descriptor_data *d);
 void do_start(struct char_data *ch);
 int parse_class(char arg);
 int special(struct char_data *ch, int cmd, char *arg);
-int isbanned(char *hostname);
+int isbanned(struct descriptor_data *d);
 int Valid_Name(char *newname);
 void read_aliases(struct char_data *ch);
 void delete_aliases(const char *charname);
 
 /* local functions */
 int perform_dupe_check(struct descriptor_data *d);
+int is_linked_character(struct descriptor_data *d, const char *name);
+void perform_login(struct descriptor_data *d);
+void reattach_linked_character(struct descriptor_data *d);
 struct alias_data *find_alias(struct alias_data *alias_list, char *str);
 void free_alias(struct alias_data *a);
 void perform_complex_alias(struct txt_q *input_q, char *orig, struct alias_data *a);
@@ -117,6 +121,7 @@ ACMD(do_kill);
 ACMD(do_last);
 ACMD(do_leave);
 ACMD(do_levels);
+ACMD(do_link);
 ACMD(do_load);
 ACMD(do_look);
 /* ACMD(do_move); -- interpreter.h */
@@ -347,6 +352,7 @@ cpp_extern const struct command_info cmd_info[] = {
   { "leave"    , POS_STANDING, do_leave    , 0, 0 },
   { "levels"   , POS_DEAD    , do_levels   , 0, 0 },
   { "list"     , POS_STANDING, do_not_here , 0, 0 },
+  { "link"     , POS_DEAD    , do_link     , 1, 0 },
   { "lick"     , POS_RESTING , do_action   , 0, 0 },
   { "lock"     , POS_SITTING , do_gen_door , 0, SCMD_LOCK },
   { "load"     , POS_DEAD    , do_load     , LVL_GOD, 0 },
@@ -1276,6 +1282,137 @@ int perform_dupe_check(struct descriptor_data *d)
 }
 
 
+/*
+ * Finish logging in a known character once they've proven who they are,
+ * either with their password or through a linked account.
+ */
+void perform_login(struct descriptor_data *d)
+{
+  int load_result;
+
+  load_result = GET_BAD_PWS(d->character);
+  GET_BAD_PWS(d->character) = 0;
+  d->bad_pws = 0;
+
+  if (isbanned(d) == BAN_SELECT &&
+      !PLR_FLAGGED(d->character, PLR_SITEOK)) {
+    write_to_output(d, "Sorry, this char has not been cleared for login from your site!\r\n");
+    STATE(d) = CON_CLOSE;
+    mudlog(NRM, LVL_GOD, TRUE, "Connection attempt for %s denied from %s", GET_NAME(d->character), d->host);
+    return;
+  }
+  if (GET_LEVEL(d->character) < circle_restrict) {
+    write_to_output(d, "The game is temporarily restricted.. try again later.\r\n");
+    STATE(d) = CON_CLOSE;
+    mudlog(NRM, LVL_GOD, TRUE, "Request for login denied for %s [%s] (wizlock)", GET_NAME(d->character), d->host);
+    return;
+  }
+  /* logged in, so no longer counted against the limit on connections logging in */
+  mark_descriptor_authenticated(mother_desc, d->descriptor);
+
+  /* check and make sure no other copies of this player are logged in */
+  if (perform_dupe_check(d))
+    return;
+
+  if (GET_LEVEL(d->character) >= LVL_IMMORT)
+    write_to_output(d, "%s", imotd);
+  else
+    write_to_output(d, "%s", motd);
+
+  mudlog(BRF, MAX(LVL_IMMORT, GET_INVIS_LEV(d->character)), TRUE, "%s [%s] has connected.", GET_NAME(d->character), d->host);
+
+  if (load_result) {
+    write_to_output(d, "\r\n\r\n\007\007\007"
+	    "%s%d LOGIN FAILURE%s SINCE LAST SUCCESSFUL LOGIN.%s\r\n",
+	    CCRED(d->character, C_SPR), load_result,
+	    (load_result > 1) ? "S" : "", CCNRM(d->character, C_SPR));
+    GET_BAD_PWS(d->character) = 0;
+  }
+  write_to_output(d, "\r\n*** PRESS RETURN: ");
+  STATE(d) = CON_RMOTD;
+}
+
+
+/* Is this descriptor's verified account linked to the character 'name'? */
+int is_linked_character(struct descriptor_data *d, const char *name)
+{
+  char linked[MAX_INPUT_LENGTH];
+
+  if (get_descriptor_linked_character(mother_desc, d->descriptor, linked, sizeof(linked)) < 0)
+    return (FALSE);
+
+  return (*linked && !str_cmp(linked, name));
+}
+
+
+/*
+ * A returning user whose account is linked to a link-dead character goes
+ * straight back to them, as if they'd given the character's name.
+ */
+void reattach_linked_character(struct descriptor_data *d)
+{
+  char linked[MAX_INPUT_LENGTH];
+  struct char_data *ch;
+
+  if (get_descriptor_linked_character(mother_desc, d->descriptor, linked, sizeof(linked)) < 0 || !*linked)
+    return;
+
+  for (ch = character_list; ch; ch = ch->next)
+    if (!IS_NPC(ch) && !ch->desc && !str_cmp(GET_NAME(ch), linked))
+      break;
+
+  if (ch)
+    nanny(d, linked);
+}
+
+
+
+/*
+ * Put a loaded character into the world, at their load room and with their
+ * rented equipment.  Returns the result of Crash_load().
+ */
+int enter_player_game(struct descriptor_data *d)
+{
+  room_rnum load_room;
+  int load_result;
+
+  reset_char(d->character);
+  read_aliases(d->character);
+
+  if (PLR_FLAGGED(d->character, PLR_INVSTART))
+    GET_INVIS_LEV(d->character) = GET_LEVEL(d->character);
+
+  /*
+   * We have to place the character in a room before equipping them
+   * or equip_char() will gripe about the person in NOWHERE.
+   */
+  if ((load_room = GET_LOADROOM(d->character)) != NOWHERE)
+    load_room = real_room(load_room);
+
+  /* If char was saved with NOWHERE, or real_room above failed... */
+  if (load_room == NOWHERE) {
+    if (GET_LEVEL(d->character) >= LVL_IMMORT)
+      load_room = r_immort_start_room;
+    else
+      load_room = r_mortal_start_room;
+  }
+
+  if (PLR_FLAGGED(d->character, PLR_FROZEN))
+    load_room = r_frozen_start_room;
+
+  d->character->next = character_list;
+  character_list = d->character;
+  char_to_room(d->character, load_room);
+  load_result = Crash_load(d->character);
+
+  /* Clear their load room if it's not persistant. */
+  if (!PLR_FLAGGED(d->character, PLR_LOADROOM))
+    GET_LOADROOM(d->character) = NOWHERE;
+  save_char(d->character);
+
+  return (load_result);
+}
+
 
 /* deal with newcomers and other non-playing sockets */
 void nanny(struct descriptor_data *d, char *arg)
@@ -1331,10 +1468,16 @@ void nanny(struct descriptor_data *d, char *arg)
 	  REMOVE_BIT(PLR_FLAGS(d->character),
 		     PLR_WRITING | PLR_MAILING | PLR_CRYO);
 	  REMOVE_BIT(AFF_FLAGS(d->character), AFF_GROUP);
-	  write_to_output(d, "Password: ");
-	  echo_off(d);
 	  d->idle_tics = 0;
 	  STATE(d) = CON_PASSWORD;
+	  if (is_linked_character(d, tmp_name)) {
+	    /* The transport has already verified who this is. */
+	    write_to_output(d, "Welcome back, %s.\r\n", GET_NAME(d->character));
+	    perform_login(d);
+	  } else {
+	    write_to_output(d, "Password: ");
+	    echo_off(d);
+	  }
 	}
       } else {
 	/* player unknown -- make new character */
@@ -1355,7 +1498,7 @@ void nanny(struct descriptor_data *d, char *arg)
 
   case CON_NAME_CNFRM:		/* wait for conf. of new name    */
     if (UPPER(*arg) == 'Y') {
-      if (isbanned(d->host) >= BAN_NEW) {
+      if (isbanned(d) >= BAN_NEW) {
 	mudlog(NRM, LVL_GOD, TRUE, "Request for new char %s denied from [%s] (siteban)", GET_PC_NAME(d->character), d->host);
 	write_to_output(d, "Sorry, new characters are not allowed from your site!\r\n");
 	STATE(d) = CON_CLOSE;
@@ -1367,6 +1510,8 @@ void nanny(struct descriptor_data *d, char *arg)
 	STATE(d) = CON_CLOSE;
 	return;
       }
+      /* The name may have belonged to a deleted character; its links don't carry over. */
+      unlink_character(mother_desc, GET_PC_NAME(d->character));
       write_to_output(d, "New character.\r\nGive me a password for %s: ", GET_PC_NAME(d->character));
       echo_off(d);
       STATE(d) = CON_NEWPASSWD;
@@ -1412,43 +1557,7 @@ void nanny(struct descriptor_data *d, char *arg)
       }
 
       /* Password was correct. */
-      load_result = GET_BAD_PWS(d->character);
-      GET_BAD_PWS(d->character) = 0;
-      d->bad_pws = 0;
-
-      if (isbanned(d->host) == BAN_SELECT &&
-	  !PLR_FLAGGED(d->character, PLR_SITEOK)) {
-	write_to_output(d, "Sorry, this char has not been cleared for login from your site!\r\n");
-	STATE(d) = CON_CLOSE;
-	mudlog(NRM, LVL_GOD, TRUE, "Connection attempt for %s denied from %s", GET_NAME(d->character), d->host);
-	return;
-      }
-      if (GET_LEVEL(d->character) < circle_restrict) {
-	write_to_output(d, "The game is temporarily restricted.. try again later.\r\n");
-	STATE(d) = CON_CLOSE;
-	mudlog(NRM, LVL_GOD, TRUE, "Request for login denied for %s [%s] (wizlock)", GET_NAME(d->character), d->host);
-	return;
-      }
-      /* check and make sure no other copies of this player are logged in */
-      if (perform_dupe_check(d))
-	return;
-
-      if (GET_LEVEL(d->character) >= LVL_IMMORT)
-	write_to_output(d, "%s", imotd);
-      else
-	write_to_output(d, "%s", motd);
-
-      mudlog(BRF, MAX(LVL_IMMORT, GET_INVIS_LEV(d->character)), TRUE, "%s [%s] has connected.", GET_NAME(d->character), d->host);
-
-      if (load_result) {
-        write_to_output(d, "\r\n\r\n\007\007\007"
-		"%s%d LOGIN FAILURE%s SINCE LAST SUCCESSFUL LOGIN.%s\r\n",
-		CCRED(d->character, C_SPR), load_result,
-		(load_result > 1) ? "S" : "", CCNRM(d->character, C_SPR));
-	GET_BAD_PWS(d->character) = 0;
-      }
-      write_to_output(d, "\r\n*** PRESS RETURN: ");
-      STATE(d) = CON_RMOTD;
+      perform_login(d);
     }
     break;
 
@@ -1525,6 +1634,7 @@ void nanny(struct descriptor_data *d, char *arg)
     /* Now GET_NAME() will work properly. */
     init_char(d->character);
     save_char(d->character);
+    mark_descriptor_authenticated(mother_desc, d->descriptor);
     write_to_output(d, "%s\r\n*** PRESS RETURN: ", motd);
     STATE(d) = CON_RMOTD;
 
@@ -1536,9 +1646,7 @@ void nanny(struct descriptor_data *d, char *arg)
     STATE(d) = CON_MENU;
     break;
 
-  case CON_MENU: {		/* get selection from main menu  */
-    room_vnum load_room;
-
+  case CON_MENU:		/* get selection from main menu  */
     switch (*arg) {
     case '0':
       write_to_output(d, "Goodbye.\r\n");
@@ -1546,40 +1654,8 @@ void nanny(struct descriptor_data *d, char *arg)
       break;
 
     case '1':
-      reset_char(d->character);
-      read_aliases(d->character);
-
-      if (PLR_FLAGGED(d->character, PLR_INVSTART))
-	GET_INVIS_LEV(d->character) = GET_LEVEL(d->character);
-
-      /*
-       * We have to place the character in a room before equipping them
-       * or equip_char() will gripe about the person in NOWHERE.
-       */
-      if ((load_room = GET_LOADROOM(d->character)) != NOWHERE)
-	load_room = real_room(load_room);
-
-      /* If char was saved with NOWHERE, or real_room above failed... */
-      if (load_room == NOWHERE) {
-	if (GET_LEVEL(d->character) >= LVL_IMMORT)
-	  load_room = r_immort_start_room;
-	else
-	  load_room = r_mortal_start_room;
-      }
-
-      if (PLR_FLAGGED(d->character, PLR_FROZEN))
-	load_room = r_frozen_start_room;
-
       send_to_char(d->character, "%s", WELC_MESSG);
-      d->character->next = character_list;
-      character_list = d->character;
-      char_to_room(d->character, load_room);
-      load_result = Crash_load(d->character);
-
-      /* Clear their load room if it's not persistant. */
-      if (!PLR_FLAGGED(d->character, PLR_LOADROOM))
-        GET_LOADROOM(d->character) = NOWHERE;
-      save_char(d->character);
+      load_result = enter_player_game(d);
 
       act("$n has entered the game.", TRUE, d->character, 0, 0, TO_ROOM);
 
@@ -1633,7 +1709,6 @@ void nanny(struct descriptor_data *d, char *arg)
       break;
     }
     break;
-  }
 
   case CON_CHPWD_GETOLD:
     if (strncmp(CRYPT(arg, GET_PASSWD(d->character)), GET_PASSWD(d->character), MAX_PWD_LENGTH)) {
@@ -1672,6 +1747,7 @@ void nanny(struct descriptor_data *d, char *arg)
       save_char(d->character);
       Crash_delete_file(GET_NAME(d->character));
       delete_aliases(GET_NAME(d->character));
+      unlink_character(mother_desc, GET_NAME(d->character));
       write_to_output(d, "Character '%s' deleted!\r\n"
 	      "Goodbye.\r\n", GET_NAME(d->character));
       mudlog(NRM, LVL_GOD, TRUE, "%s (lev %d) has self-deleted.", GET_NAME(d->character), GET_LEVEL(d->character));
@@ -30,6 +30,7 @@ extern int immort_level_ok;
 extern int use_autowiz;
 extern int min_wizlist_lev;
 extern int free_rent;
+extern struct DescriptorManager *mother_desc;
 
 /* local functions */
 int graf(int grafage, int p0, int p1, int p2, int p3, int p4, int p5, int p6);
@@ -363,7 +364,15 @@ void gain_condition(struct char_data *ch, int condition, int value)
 
 void check_idling(struct char_data *ch)
 {
-  if (++(ch->char_specials.timer) > idle_void) {
+  long long idle;
+
+  /* Go by when the connection last sent anything, where mud-comms knows */
+  if (ch->desc && (idle = get_descriptor_idle_seconds(mother_desc, ch->desc->descriptor)) >= 0)
+    ch->char_specials.timer = idle / SECS_PER_MUD_HOUR;
+  else
+    ch->char_specials.timer++;
+
+  if (ch->char_specials.timer > idle_void) {
     if (GET_WAS_IN(ch) == NOWHERE && IN_ROOM(ch) != NOWHERE) {
       GET_WAS_IN(ch) = IN_ROOM(ch);
       if (FIGHTING(ch)) {
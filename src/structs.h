@@ -57,6 +57,9 @@
 #define SPECIAL(name) \
    int (name)(struct char_data *ch, void *me, int cmd, char *argument)
 
+/* Connections are owned by mud-comms and referred to by descriptor_id */
+#include "descriptor.h"
+
 
 /* room-related defines *************************************************/
 
@@ -978,7 +981,7 @@ struct txt_q {
 
 
 struct descriptor_data {
-   struct Descriptor*	descriptor;	/* file descriptor for socket		*/
+   descriptor_id descriptor;	/* mud-comms ID for the connection	*/
    char	host[HOST_LENGTH+1];	/* hostname				*/
    byte	bad_pws;		/* number of bad pw attemps this login	*/
    byte idle_tics;		/* tics idle at password prompt		*/
@@ -201,7 +201,8 @@ void log_death_trap(struct char_data *ch)
 void basic_mud_vlog(const char *format, va_list args)
 {
   time_t ct = time(0);
-  char *time_s = asctime(localtime(&ct));
+  struct tm tm;
+  char time_s[26];
 
   if (logfile == NULL) {
     puts("SYSERR: Using log() before stream was initialized!");
@@ -211,12 +212,19 @@ void basic_mud_vlog(const char *format, va_list args)
   if (format == NULL)
     format = "SYSERR: log() received a NULL format.";
 
+  /*
+   * mud-comms may log from its own threads, so nothing here can be shared
+   * and each line has to be written out in one go.
+   */
+  asctime_r(localtime_r(&ct, &tm), time_s);
   time_s[strlen(time_s) - 1] = '\0';
 
+  flockfile(logfile);
   fprintf(logfile, "%-15.15s :: ", time_s + 4);
   vfprintf(logfile, format, args);
   fputc('\n', logfile);
   fflush(logfile);
+  funlockfile(logfile);
 }
 
 